@@ -15,10 +15,12 @@
       with pkgs;
       {
         devShell = mkShell rec {
+          nativeBuildInputs = [ pkg-config ];
           buildInputs = [
             libxkbcommon
             libGL
             wayland
+            udev
           ];
           LD_LIBRARY_PATH = "${lib.makeLibraryPath buildInputs}";
         };
@@ -0,0 +1,307 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+use crate::patch::MAX_ROM;
+
+/// Extensions the file dialog offers.
+pub(crate) const EXTENSIONS: [&str; 6] = ["gb", "gbc", "sgb", "zip", "gz", "7z"];
+
+const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+/// A ROM on disk, which may be one of several inside an archive.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RomFile {
+    pub(crate) path: PathBuf,
+    /// The archive entry to load. `None` takes the first ROM in it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) entry: Option<String>,
+}
+
+impl RomFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path, entry: None }
+    }
+
+    /// Where a file belonging to this ROM goes, like `game.sym` for
+    /// `game.gb`. ROMs in an archive are named after their entry.
+    pub(crate) fn sibling(&self, ext: &str) -> PathBuf {
+        let name = self.entry.as_deref().and_then(|e| Path::new(e).file_name());
+
+        match name {
+            Some(name) => self.path.with_file_name(name).with_extension(ext),
+            None => self.path.with_extension(ext),
+        }
+    }
+
+    /// The file name, or the entry's for a ROM in an archive.
+    pub(crate) fn name(&self) -> String {
+        let path = self.entry.as_deref().map_or(self.path.as_path(), Path::new);
+
+        path.file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub(crate) fn read(&self) -> io::Result<Vec<u8>> {
+        match extension(&self.path).as_str() {
+            "zip" => read_zip(&self.path, self.entry.as_deref()),
+            "7z" => read_7z(&self.path, self.entry.as_deref()),
+            "gz" => read_rom(GzDecoder::new(File::open(&self.path)?)),
+            _ => read_rom(File::open(&self.path)?),
+        }
+    }
+}
+
+/// The ROMs inside a zip or 7z archive, so the user can pick one. Anything
+/// else holds a single ROM and gives an empty list.
+pub(crate) fn entries(path: &Path) -> io::Result<Vec<String>> {
+    let names = match extension(path).as_str() {
+        "zip" => ZipArchive::new(File::open(path)?)?
+            .file_names()
+            .map(|name| name.map(|n| n.into_owned()))
+            .collect::<Result<Vec<_>, _>>()?,
+        "7z" => sevenz_rust::Archive::open(path)
+            .map_err(io::Error::other)?
+            .files
+            .iter()
+            .filter(|f| !f.is_directory())
+            .map(|f| f.name().to_string())
+            .collect(),
+        _ => return Ok(Vec::new()),
+    };
+
+    let roms: Vec<String> = names.into_iter().filter(|n| is_rom(n)).collect();
+
+    if roms.is_empty() {
+        return Err(not_found(path));
+    }
+
+    Ok(roms)
+}
+
+fn read_zip(path: &Path, entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+
+    let name = match entry {
+        Some(entry) => entry.to_string(),
+        None => entries(path)?.swap_remove(0),
+    };
+
+    let file = zip.by_name(&name)?;
+    read_rom(file)
+}
+
+fn read_7z(path: &Path, entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut archive = SevenZReader::open(path, Password::empty()).map_err(io::Error::other)?;
+    let mut data = None;
+
+    archive
+        .for_each_entries(|file, reader| {
+            let wanted = match entry {
+                Some(entry) => file.name() == entry,
+                None => !file.is_directory() && is_rom(file.name()),
+            };
+
+            // Entries in the same solid block still come through after
+            // stopping, and the first ROM is the one wanted.
+            if !wanted || data.is_some() {
+                // Solid archives have to be read through in order anyway.
+                io::copy(reader, &mut io::sink())?;
+                return Ok(true);
+            }
+
+            data = Some(read_rom(reader)?);
+            Ok(false)
+        })
+        .map_err(io::Error::other)?;
+
+    data.ok_or_else(|| not_found(path))
+}
+
+/// Reads the whole of `reader`, unless it's bigger than any ROM. A small
+/// archive can unpack to gigabytes otherwise.
+fn read_rom(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(MAX_ROM as u64 + 1).read_to_end(&mut data)?;
+
+    if data.len() > MAX_ROM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "more than {} MiB, which is too big for a ROM",
+                MAX_ROM >> 20
+            ),
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Whether `path` is a ROM or an archive that might have one in.
+pub(crate) fn is_loadable(path: &Path) -> bool {
+    EXTENSIONS.contains(&extension(path).as_str())
+}
+
+fn is_rom(name: &str) -> bool {
+    ROM_EXTENSIONS.contains(&extension(Path::new(name)).as_str())
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no .gb, .gbc or .sgb file in {}", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{write::GzEncoder, Compression};
+    use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("oxyboy-{}-{name}", std::process::id()))
+    }
+
+    fn zip(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let path = temp(name);
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (name, data) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    fn gz(name: &str, data: &[u8]) -> PathBuf {
+        let path = temp(name);
+        let mut gz = GzEncoder::new(File::create(&path).unwrap(), Compression::fast());
+        gz.write_all(data).unwrap();
+        gz.finish().unwrap();
+        path
+    }
+
+    fn sevenz(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let path = temp(name);
+        let mut archive = SevenZWriter::create(&path).unwrap();
+        for (name, data) in files {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            entry.has_stream = true;
+            archive.push_archive_entry(entry, Some(*data)).unwrap();
+        }
+        archive.finish().unwrap();
+        path
+    }
+
+    fn read(path: &Path, entry: Option<&str>) -> io::Result<Vec<u8>> {
+        RomFile {
+            path: path.to_path_buf(),
+            entry: entry.map(String::from),
+        }
+        .read()
+    }
+
+    #[test]
+    fn zips() {
+        let path = zip(
+            "roms.zip",
+            &[
+                ("readme.txt", b"hello"),
+                ("game.gb", b"first"),
+                ("dir/other.GBC", b"second"),
+            ],
+        );
+
+        assert_eq!(entries(&path).unwrap(), ["game.gb", "dir/other.GBC"]);
+        assert_eq!(read(&path, None).unwrap(), b"first");
+        assert_eq!(read(&path, Some("dir/other.GBC")).unwrap(), b"second");
+        assert!(read(&path, Some("missing.gb")).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn gzips() {
+        let path = gz("game.gb.gz", b"rom");
+
+        assert!(entries(&path).unwrap().is_empty());
+        assert_eq!(read(&path, None).unwrap(), b"rom");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sevenzips() {
+        let path = sevenz(
+            "roms.7z",
+            &[
+                ("readme.txt", b"hello"),
+                ("game.gb", b"first"),
+                ("other.sgb", b"second"),
+            ],
+        );
+
+        assert_eq!(entries(&path).unwrap(), ["game.gb", "other.sgb"]);
+        assert_eq!(read(&path, None).unwrap(), b"first");
+        assert_eq!(read(&path, Some("other.sgb")).unwrap(), b"second");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn archives_without_roms() {
+        let files: &[(&str, &[u8])] = &[("readme.txt", b"hello")];
+
+        for path in [zip("empty.zip", files), sevenz("empty.7z", files)] {
+            let kind = |e: io::Error| e.kind();
+            assert_eq!(entries(&path).map_err(kind), Err(io::ErrorKind::NotFound));
+            assert_eq!(
+                read(&path, None).map_err(kind),
+                Err(io::ErrorKind::NotFound)
+            );
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn oversize_roms() {
+        let biggest = vec![0; MAX_ROM];
+        let too_big = vec![0; MAX_ROM + 1];
+
+        let path = gz("biggest.gb.gz", &biggest);
+        assert_eq!(read(&path, None).unwrap().len(), MAX_ROM);
+        fs::remove_file(path).unwrap();
+
+        let paths = [
+            gz("bomb.gb.gz", &too_big),
+            zip("bomb.zip", &[("bomb.gb", &too_big)]),
+        ];
+        for path in paths {
+            let e = read(&path, None).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(e.to_string(), "more than 8 MiB, which is too big for a ROM");
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
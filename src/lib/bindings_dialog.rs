@@ -0,0 +1,252 @@
+use eframe::egui::{self, Key};
+
+use crate::{
+    gamepad::PadButton,
+    input::{Action, Bindings, Button, Hotkey, Keyboard},
+};
+
+/// Edits the key bindings, either for every ROM or just the loaded one.
+/// Changes are saved straight away.
+pub(crate) struct BindingsDialog {
+    pub(crate) open: bool,
+    // Whether edits go to the loaded ROM's profile.
+    for_rom: bool,
+    // Waiting for a key to add to this.
+    capturing: Option<Action>,
+}
+
+impl BindingsDialog {
+    pub(crate) fn new() -> Self {
+        Self {
+            open: false,
+            for_rom: false,
+            capturing: None,
+        }
+    }
+
+    /// Whether the next key press is for the dialog rather than the game.
+    pub(crate) fn capturing(&self) -> bool {
+        self.open && self.capturing.is_some()
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context, keyboard: &mut Keyboard, pads: &[String]) {
+        let mut open = self.open;
+        let mut changed = false;
+
+        if let Some(action) = self.capturing {
+            changed |= self.capture(ctx, keyboard, action);
+        }
+
+        egui::Window::new("Key bindings")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let profile = keyboard.profile.clone();
+
+                match &profile {
+                    Some(title) => {
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut self.for_rom, false, "All ROMs");
+                            ui.radio_value(&mut self.for_rom, true, format!("{title} only"));
+                        });
+                    }
+                    None => self.for_rom = false,
+                }
+
+                let profile = profile.filter(|_| self.for_rom);
+
+                egui::Grid::new("bindings").striped(true).show(ui, |ui| {
+                    for button in Button::ALL {
+                        let action = Action::Button(button);
+                        ui.label(action.name());
+                        changed |= self.row(ui, keyboard, profile.as_deref(), action);
+                        ui.end_row();
+                    }
+                });
+
+                if ui.button("Reset to defaults").clicked() {
+                    let bindings = &mut keyboard.bindings;
+
+                    match &profile {
+                        Some(title) => _ = bindings.profiles.remove(title),
+                        None => bindings.keys = Bindings::default().keys,
+                    }
+                    changed = true;
+                }
+
+                ui.separator();
+
+                ui.collapsing("Hotkeys", |ui| {
+                    egui::Grid::new("hotkeys").striped(true).show(ui, |ui| {
+                        for hotkey in Hotkey::ALL {
+                            let action = Action::Hotkey(hotkey);
+                            ui.label(action.name());
+                            changed |= self.row(ui, keyboard, None, action);
+                            ui.end_row();
+                        }
+                    });
+                });
+
+                ui.separator();
+                changed |= gamepad(ui, keyboard, pads);
+            });
+
+        self.open = open;
+
+        if !self.open {
+            self.capturing = None;
+        }
+
+        if changed {
+            keyboard.bindings.save();
+        }
+    }
+
+    fn row(
+        &mut self,
+        ui: &mut egui::Ui,
+        keyboard: &mut Keyboard,
+        profile: Option<&str>,
+        action: Action,
+    ) -> bool {
+        let mut changed = false;
+        let keys = keyboard.bindings.action_keys(action, profile).to_vec();
+
+        ui.horizontal(|ui| {
+            for (i, key) in keys.iter().enumerate() {
+                ui.monospace(key);
+
+                if ui.small_button("x").clicked() {
+                    let mut keys = keys.clone();
+                    keys.remove(i);
+                    keyboard.bindings.set_keys(action, profile, keys);
+                    changed = true;
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if self.capturing == Some(action) {
+                ui.label("Press a key (Esc cancels)");
+            } else if ui.button("Add").clicked() {
+                self.capturing = Some(action);
+            }
+
+            let Action::Button(button) = action else {
+                return;
+            };
+
+            let overridden = profile
+                .and_then(|p| keyboard.bindings.profiles.get(p))
+                .is_some_and(|keys| keys.contains_key(&button));
+
+            if overridden && ui.button("Use default").clicked() {
+                let profiles = &mut keyboard.bindings.profiles;
+                let title = profile.unwrap();
+
+                if let Some(keys) = profiles.get_mut(title) {
+                    keys.remove(&button);
+
+                    if keys.is_empty() {
+                        profiles.remove(title);
+                    }
+                }
+                changed = true;
+            }
+        });
+
+        changed
+    }
+
+    /// Adds the first key pressed this frame to `action`.
+    fn capture(&mut self, ctx: &egui::Context, keyboard: &mut Keyboard, action: Action) -> bool {
+        let Some(key) = pressed_key(ctx) else {
+            return false;
+        };
+
+        self.capturing = None;
+
+        if key == Key::Escape {
+            return false;
+        }
+
+        let profile = keyboard.profile.clone().filter(|_| self.for_rom);
+        let mut keys = keyboard
+            .bindings
+            .action_keys(action, profile.as_deref())
+            .to_vec();
+
+        if keys.iter().any(|k| k == key.name()) {
+            return false;
+        }
+
+        keys.push(key.name().to_string());
+        keyboard.bindings.set_keys(action, profile.as_deref(), keys);
+        true
+    }
+}
+
+/// The first key pressed this frame, for binding it to something.
+pub(crate) fn pressed_key(ctx: &egui::Context) -> Option<Key> {
+    ctx.input(|i| {
+        i.events.iter().find_map(|e| match e {
+            egui::Event::Key {
+                key, pressed: true, ..
+            } => Some(*key),
+            _ => None,
+        })
+    })
+}
+
+/// The gamepad mapping, which is the same for every ROM.
+fn gamepad(ui: &mut egui::Ui, keyboard: &mut Keyboard, pads: &[String]) -> bool {
+    let mapping = &mut keyboard.bindings.gamepad;
+    let mut changed = false;
+
+    ui.collapsing("Gamepad", |ui| {
+        if pads.is_empty() {
+            ui.label("No gamepads connected");
+        }
+        for pad in pads {
+            ui.label(pad);
+        }
+
+        changed |= ui
+            .add(egui::Slider::new(&mut mapping.deadzone, 0.05..=0.95).text("Stick deadzone"))
+            .drag_stopped();
+
+        let actions = Button::ALL
+            .map(Action::Button)
+            .into_iter()
+            .chain(Hotkey::ALL.map(Action::Hotkey));
+
+        egui::Grid::new("gamepad").striped(true).show(ui, |ui| {
+            for pad in PadButton::ALL {
+                ui.label(pad.name());
+
+                let mut action = mapping.map.get(&pad).copied();
+
+                egui::ComboBox::from_id_source(pad)
+                    .selected_text(action.map_or("-", Action::name))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut action, None, "-");
+
+                        for a in actions.clone() {
+                            ui.selectable_value(&mut action, Some(a), a.name());
+                        }
+                    });
+
+                if action != mapping.map.get(&pad).copied() {
+                    match action {
+                        Some(action) => mapping.map.insert(pad, action),
+                        None => mapping.map.remove(&pad),
+                    };
+                    changed = true;
+                }
+                ui.end_row();
+            }
+        });
+    });
+
+    changed
+}
@@ -1,14 +1,23 @@
 use std::path::PathBuf;
 
+use crate::{
+    archive::RomFile,
+    cheats::GenieCode,
+    error::{Error, Result},
+    patch,
+    state::{Reader, Writer},
+};
+
 #[derive(Default)]
 pub(crate) struct Mbc {
     pub(crate) rom: Vec<u8>,
-    rom_bank: u16,
-    ram: Vec<u8>,
-    ram_bank: u8,
+    pub(crate) rom_bank: u16,
+    pub(crate) ram: Vec<u8>,
+    pub(crate) ram_bank: u8,
     ram_enabled: bool,
     banking_mode: bool,
     secondary_banking: bool,
+    pub(crate) genie: Vec<GenieCode>,
 }
 
 impl Mbc {
@@ -23,17 +32,15 @@ impl Mbc {
             ram_enabled: false,
             banking_mode: false,
             secondary_banking,
+            genie: Vec::new(),
         }
     }
 
     #[inline(always)]
     pub(crate) fn read(&self, a: u16) -> u8 {
         match a {
-            0..0x4000 => self.rom[a as usize],
-            0x4000..0x8000 => {
-                let rom_addr = (a as usize % 0x4000) + (self.rom_bank as usize * 0x4000);
-                self.rom[rom_addr]
-            }
+            0..0x8000 if !self.genie.is_empty() => self.genie_read(a),
+            0..0x8000 => self.rom_byte(a),
             0xA000..0xC000 => {
                 if !self.ram_enabled {
                     return 0xFF;
@@ -47,6 +54,74 @@ impl Mbc {
         }
     }
 
+    /// A ROM read with Game Genie codes applied. A code only kicks in when
+    /// the ROM has its compare value there, which keeps it to one bank.
+    fn genie_read(&self, a: u16) -> u8 {
+        let v = self.rom_byte(a);
+
+        self.genie
+            .iter()
+            .find(|g| g.addr == a && g.compare.is_none_or(|c| c == v))
+            .map_or(v, |g| g.value)
+    }
+
+    #[inline(always)]
+    fn rom_byte(&self, a: u16) -> u8 {
+        self.rom.get(self.rom_offset(a)).copied().unwrap_or(0xFF)
+    }
+
+    /// Where ROM address `a` is in the image. Banks past the end wrap
+    /// around, since the cartridge doesn't wire up the extra bank bits.
+    #[inline(always)]
+    fn rom_offset(&self, a: u16) -> usize {
+        let addr = match a {
+            0..0x4000 => a as usize,
+            _ => (a as usize % 0x4000) + (self.rom_bank as usize * 0x4000),
+        };
+
+        addr % self.rom.len().max(1)
+    }
+
+    /// Changes the ROM or RAM byte `a` maps to, without touching the mapper
+    /// registers a write there would normally hit.
+    pub(crate) fn poke(&mut self, a: u16, v: u8) {
+        let addr = match a {
+            0..0x8000 => self.rom_offset(a),
+            0xA000..0xC000 => {
+                let addr = (a as usize - 0xA000) + (self.ram_bank as usize * 0x2000);
+
+                if let Some(b) = self.ram.get_mut(addr) {
+                    *b = v;
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        if let Some(b) = self.rom.get_mut(addr) {
+            *b = v;
+        }
+    }
+
+    /// The mapper registers and RAM. The ROM is left out; save states are
+    /// tied to it by CRC32 instead.
+    fn save_state(&self, w: &mut Writer) {
+        w.u16(self.rom_bank);
+        w.bytes(&self.ram);
+        w.u8(self.ram_bank);
+        w.bool(self.ram_enabled);
+        w.bool(self.banking_mode);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<()> {
+        self.rom_bank = r.u16()?;
+        r.fill(&mut self.ram)?;
+        self.ram_bank = r.u8()? & 0b11;
+        self.ram_enabled = r.bool()?;
+        self.banking_mode = r.bool()?;
+        Ok(())
+    }
+
     #[inline(always)]
     pub(crate) fn write(&mut self, a: u16, v: u8) {
         match a {
@@ -85,6 +160,8 @@ pub(crate) struct Cartridge {
     pub(crate) hram: [u8; 0x80],
     pub(crate) wram: [u8; 0x8000],
     pub(crate) checksum: u8,
+    /// The CRC32 of the ROM as loaded, patches and all.
+    pub(crate) crc: u32,
     pub(crate) mbc: Mbc,
 }
 
@@ -94,6 +171,7 @@ impl Default for Cartridge {
             wram: [0x0; 0x8000],
             hram: [0xFF; 0x80],
             checksum: 0,
+            crc: 0,
             mbc: Mbc::default(),
         }
     }
@@ -104,12 +182,43 @@ impl Cartridge {
         Self::default()
     }
 
-    pub(crate) fn from(filename: PathBuf) -> Self {
-        let cart_data = std::fs::read(&filename).unwrap();
+    /// Loads a ROM, soft-patched with `patch` or else a same-named patch
+    /// file if there is one.
+    pub(crate) fn from(rom: &RomFile, patch: Option<PathBuf>) -> Result<Self> {
+        let mut cart_data = rom.read()?;
+
+        if let Some(patch) = patch.or_else(|| patch::find(rom)) {
+            let data = std::fs::read(&patch)
+                .map_err(|e| Error::Patch(format!("couldn't read {}: {e}", patch.display())))?;
+            cart_data = patch::apply(&cart_data, &data)?;
+        }
+
         let mut cart = Self::default();
-        cart.mbc = Mbc::new(cart_data);
+        cart.mbc = Mbc::new(check_header(cart_data)?);
         cart.checksum = cart.calculate_checksum();
-        cart
+        cart.crc = patch::crc32(&cart.mbc.rom);
+        Ok(cart)
+    }
+
+    pub(crate) fn title(&self) -> String {
+        title(&self.mbc.rom)
+    }
+
+    /// Whether the header's CGB flag is set.
+    pub(crate) fn cgb(&self) -> bool {
+        cgb_flag(&self.mbc.rom) != 0
+    }
+
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.hram);
+        w.bytes(&self.wram);
+        self.mbc.save_state(w);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut Reader) -> Result<()> {
+        r.fill(&mut self.hram)?;
+        r.fill(&mut self.wram)?;
+        self.mbc.load_state(r)
     }
 
     fn calculate_checksum(&self) -> u8 {
@@ -161,3 +270,168 @@ impl Cartridge {
         self.hram[address as usize] = value;
     }
 }
+
+/// The header's title, which CGB cartridges cut short for their flags.
+pub(crate) fn title(rom: &[u8]) -> String {
+    let end = if cgb_flag(rom) != 0 { 0x143 } else { 0x144 };
+    let title = rom.get(0x134..end).unwrap_or_default();
+
+    title
+        .iter()
+        .take_while(|&&b| b != 0)
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|&b| b as char)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// The header's CGB flag: $80 if the game also runs on a DMG, $C0 if it's
+/// CGB only and 0 if it's a DMG game.
+pub(crate) fn cgb_flag(rom: &[u8]) -> u8 {
+    match rom.get(0x143) {
+        Some(&flag) if flag & 0x80 != 0 => flag & 0xC0,
+        _ => 0,
+    }
+}
+
+/// The mapper a cartridge type byte ($0147) stands for.
+pub(crate) fn mapper_name(kind: u8) -> &'static str {
+    match kind {
+        0x00 => "ROM only",
+        0x01..=0x03 => "MBC1",
+        0x05 | 0x06 => "MBC2",
+        0x08 | 0x09 => "ROM+RAM",
+        0x0B..=0x0D => "MMM01",
+        0x0F..=0x13 => "MBC3",
+        0x19..=0x1E => "MBC5",
+        0x20 => "MBC6",
+        0x22 => "MBC7",
+        0xFC => "Pocket Camera",
+        0xFD => "TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1",
+        _ => "unknown",
+    }
+}
+
+/// ROM only and MBC1, with or without RAM and a battery.
+pub(crate) fn supported(kind: u8) -> bool {
+    matches!(kind, 0x00..=0x03 | 0x08 | 0x09)
+}
+
+/// Makes sure the header describes a cartridge we can run, and mirrors an
+/// image smaller than its header says up to full size, like the address
+/// lines on a smaller ROM chip would.
+fn check_header(rom: Vec<u8>) -> Result<Vec<u8>> {
+    if rom.len() < 0x150 {
+        return Err(Error::Truncated(rom.len()));
+    }
+
+    if !supported(rom[0x147]) {
+        return Err(Error::UnsupportedMapper(rom[0x147]));
+    }
+
+    if rom[0x149] > 5 {
+        return Err(Error::BadRamSize(rom[0x149]));
+    }
+
+    let size = match rom[0x148] {
+        code @ 0..=8 => 0x8000 << code,
+        code => return Err(Error::BadRomSize(code)),
+    };
+
+    if rom.len() >= size {
+        return Ok(rom);
+    }
+
+    eprintln!(
+        "The ROM is {} bytes but its header says {size}, mirroring it.",
+        rom.len()
+    );
+
+    Ok(rom.iter().copied().cycle().take(size).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 32 KiB ROM-only image with `title` in its header.
+    fn rom(title: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom
+    }
+
+    #[test]
+    fn good_headers() {
+        let rom = rom(b"TETRIS");
+        assert_eq!(check_header(rom.clone()).unwrap(), rom);
+
+        let mut mbc1 = rom.clone();
+        mbc1[0x147] = 0x03;
+        mbc1[0x148] = 0x01;
+        mbc1[0x149] = 0x03;
+        mbc1.resize(0x10000, 0xAA);
+        assert_eq!(check_header(mbc1.clone()).unwrap(), mbc1);
+    }
+
+    #[test]
+    fn short_roms_are_mirrored() {
+        let mut rom = rom(b"SHORT");
+        rom[0x148] = 0x01;
+
+        let mirrored = check_header(rom.clone()).unwrap();
+        assert_eq!(mirrored.len(), 0x10000);
+        assert_eq!(mirrored[..0x8000], rom[..]);
+        assert_eq!(mirrored[0x8000..], rom[..]);
+    }
+
+    #[test]
+    fn bad_headers() {
+        assert!(matches!(
+            check_header(vec![0; 0x14F]),
+            Err(Error::Truncated(0x14F))
+        ));
+
+        let mut mbc5 = rom(b"");
+        mbc5[0x147] = 0x19;
+        assert!(matches!(
+            check_header(mbc5),
+            Err(Error::UnsupportedMapper(0x19))
+        ));
+
+        let mut rom_size = rom(b"");
+        rom_size[0x148] = 0x09;
+        assert!(matches!(
+            check_header(rom_size),
+            Err(Error::BadRomSize(0x09))
+        ));
+
+        let mut ram_size = rom(b"");
+        ram_size[0x149] = 0x06;
+        assert!(matches!(
+            check_header(ram_size),
+            Err(Error::BadRamSize(0x06))
+        ));
+    }
+
+    #[test]
+    fn header_fields() {
+        let mut rom = rom(b"POKEMON RED\0\0\0\0\x80");
+        assert_eq!(title(&rom), "POKEMON RED");
+        assert_eq!(cgb_flag(&rom), 0x80);
+
+        rom[0x143] = 0xC0;
+        assert_eq!(cgb_flag(&rom), 0xC0);
+        // Old titles run into where the flag is.
+        rom[0x143] = b'X';
+        assert_eq!(cgb_flag(&rom), 0);
+        assert_eq!(cgb_flag(&[]), 0);
+
+        assert_eq!(mapper_name(0x13), "MBC3");
+        assert!(supported(0x09));
+        assert!(!supported(0x0F));
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::mpsc::Sender;
+
+use eframe::egui::{self, Color32};
+
+use crate::{
+    cheats::{Cheat, Code},
+    EmuCommand,
+};
+
+/// Lists the ROM's cheats. Every change goes to the emulator, which saves it.
+pub(crate) struct CheatDialog {
+    pub(crate) open: bool,
+    tx: Sender<EmuCommand>,
+    list: Vec<Cheat>,
+    code: String,
+    name: String,
+    error: Option<String>,
+}
+
+impl CheatDialog {
+    pub(crate) fn new(tx: Sender<EmuCommand>) -> Self {
+        Self {
+            open: false,
+            tx,
+            list: Vec::new(),
+            code: String::new(),
+            name: String::new(),
+            error: None,
+        }
+    }
+
+    /// Takes the cheats saved for a newly loaded ROM.
+    pub(crate) fn set_list(&mut self, list: Vec<Cheat>) {
+        self.list = list;
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context) {
+        let mut open = self.open;
+        let mut changed = false;
+
+        egui::Window::new("Cheats").open(&mut open).show(ctx, |ui| {
+            let mut remove = None;
+
+            egui::Grid::new("cheats").striped(true).show(ui, |ui| {
+                for (i, cheat) in self.list.iter_mut().enumerate() {
+                    changed |= ui.checkbox(&mut cheat.enabled, "").changed();
+                    ui.monospace(&cheat.code);
+                    ui.label(&cheat.name);
+
+                    if ui.small_button("x").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+
+            if let Some(i) = remove {
+                self.list.remove(i);
+                changed = true;
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.code)
+                        .hint_text("01FF1AC1 or 00A-17B-C49")
+                        .desired_width(120.0),
+                );
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.name)
+                        .hint_text("Name")
+                        .desired_width(120.0),
+                );
+
+                if ui.button("Add").clicked() {
+                    changed |= self.add();
+                }
+            });
+
+            if let Some(e) = &self.error {
+                ui.colored_label(Color32::RED, e);
+            }
+        });
+
+        self.open = open;
+
+        if changed {
+            _ = self.tx.send(EmuCommand::Cheats(self.list.clone()));
+        }
+    }
+
+    fn add(&mut self) -> bool {
+        let code = self.code.trim().to_uppercase();
+
+        if let Err(e) = Code::parse(&code) {
+            self.error = Some(e);
+            return false;
+        }
+
+        self.list.push(Cheat {
+            name: self.name.trim().to_string(),
+            code,
+            enabled: true,
+        });
+
+        self.code.clear();
+        self.name.clear();
+        self.error = None;
+        true
+    }
+}
@@ -0,0 +1,265 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::{archive::RomFile, mmu::Mmu};
+
+/// A cheat as the user entered it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Cheat {
+    pub(crate) name: String,
+    pub(crate) code: String,
+    pub(crate) enabled: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Code {
+    /// Writes `value` to `addr` once a frame.
+    GameShark {
+        addr: u16,
+        value: u8,
+    },
+    GameGenie(GenieCode),
+}
+
+/// Replaces ROM reads of `addr`, if the ROM has `compare` there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct GenieCode {
+    pub(crate) addr: u16,
+    pub(crate) value: u8,
+    pub(crate) compare: Option<u8>,
+}
+
+impl Code {
+    /// Takes `01vvaaaa` GameShark codes and `ABC-DEF(-GHI)` Game Genie codes.
+    pub(crate) fn parse(code: &str) -> Result<Code, String> {
+        let digits: String = code
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .collect();
+
+        let nibbles: Vec<u8> = digits
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("{code:?} isn't hex"))?;
+
+        let byte = |i: usize| (nibbles[i] << 4) | nibbles[i + 1];
+
+        match nibbles.len() {
+            // The first byte picks an external RAM bank, which we leave to
+            // whatever's mapped in.
+            8 if !code.contains('-') => {
+                let addr = u16::from_le_bytes([byte(4), byte(6)]);
+
+                if addr < 0x8000 {
+                    return Err(format!("{code:?} writes ${addr:04X}, which isn't RAM"));
+                }
+
+                Ok(Code::GameShark {
+                    value: byte(2),
+                    addr,
+                })
+            }
+            6 | 9 => {
+                let addr = (((nibbles[5] ^ 0xF) as u16) << 12)
+                    | (nibbles[2] as u16) << 8
+                    | (nibbles[3] as u16) << 4
+                    | nibbles[4] as u16;
+
+                // H is a check digit the real device ignores too.
+                let compare = (nibbles.len() == 9)
+                    .then(|| ((nibbles[6] << 4) | nibbles[8]).rotate_right(2) ^ 0xBA);
+
+                if addr >= 0x8000 {
+                    return Err(format!("{code:?} patches ${addr:04X}, which isn't ROM"));
+                }
+
+                Ok(Code::GameGenie(GenieCode {
+                    addr,
+                    value: byte(0),
+                    compare,
+                }))
+            }
+            _ => Err(format!(
+                "{code:?} is neither a GameShark (01vvaaaa) nor a Game Genie (ABC-DEF-GHI) code"
+            )),
+        }
+    }
+}
+
+/// The cheats for the loaded ROM, kept in `game.cht` next to `game.gb`.
+#[derive(Default)]
+pub(crate) struct Cheats {
+    pub(crate) list: Vec<Cheat>,
+    path: Option<PathBuf>,
+}
+
+impl Cheats {
+    pub(crate) fn load(rom: &RomFile) -> Cheats {
+        let path = rom.sibling("cht");
+
+        let list = match fs::read_to_string(&path) {
+            Ok(text) => parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                eprintln!("Couldn't read cheats from {}: {e}", path.display());
+                Vec::new()
+            }
+        };
+
+        Cheats {
+            list,
+            path: Some(path),
+        }
+    }
+
+    pub(crate) fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        if self.list.is_empty() && !path.exists() {
+            return;
+        }
+
+        let text: String = self
+            .list
+            .iter()
+            .map(|c| format!("{} {} {}\n", c.enabled as u8, c.code, c.name))
+            .collect();
+
+        if let Err(e) = fs::write(path, text) {
+            eprintln!("Couldn't save cheats to {}: {e}", path.display());
+        }
+    }
+
+    /// Hands the enabled Game Genie codes to the mapper.
+    pub(crate) fn install(&self, mmu: &mut Mmu) {
+        mmu.cart.mbc.genie = self
+            .codes()
+            .filter_map(|code| match code {
+                Code::GameGenie(genie) => Some(genie),
+                Code::GameShark { .. } => None,
+            })
+            .collect();
+    }
+
+    /// Does the GameShark writes. Called once a frame.
+    pub(crate) fn write_ram(&self, mmu: &mut Mmu) {
+        for code in self.codes() {
+            if let Code::GameShark { addr, value } = code {
+                mmu.poke(addr, value);
+            }
+        }
+    }
+
+    fn codes(&self) -> impl Iterator<Item = Code> + '_ {
+        self.list
+            .iter()
+            .filter(|c| c.enabled)
+            .filter_map(|c| Code::parse(&c.code).ok())
+    }
+}
+
+/// One cheat a line: `1 01FF1AC1 Infinite lives`, with 0 for disabled.
+fn parse(text: &str) -> Vec<Cheat> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let mut parts = line.trim().splitn(3, ' ');
+            let enabled = parts.next()? == "1";
+            let code = parts.next()?.to_string();
+            let name = parts.next().unwrap_or_default().to_string();
+
+            Some(Cheat {
+                name,
+                code,
+                enabled,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gameshark_codes() {
+        assert_eq!(
+            Code::parse("010FA1C1"),
+            Ok(Code::GameShark {
+                addr: 0xC1A1,
+                value: 0x0F
+            })
+        );
+        assert_eq!(
+            Code::parse(" 01ff 80da "),
+            Ok(Code::GameShark {
+                addr: 0xDA80,
+                value: 0xFF
+            })
+        );
+    }
+
+    #[test]
+    fn game_genie_codes() {
+        assert_eq!(
+            Code::parse("00A-17B-C49"),
+            Ok(Code::GameGenie(GenieCode {
+                addr: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            }))
+        );
+        assert_eq!(
+            Code::parse("3e0-14b"),
+            Ok(Code::GameGenie(GenieCode {
+                addr: 0x4014,
+                value: 0x3E,
+                compare: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn malformed_codes() {
+        for code in [
+            "",
+            "01FF",
+            "01FFC0G0",
+            "010F-A1C1",
+            "01FF0010",
+            "00A-170",
+            "00A-17B-C4",
+            "00A-17B-C49-0",
+        ] {
+            assert!(Code::parse(code).is_err(), "{code:?}");
+        }
+    }
+
+    #[test]
+    fn cheat_files() {
+        let list = parse("1 01FF1AC1 Infinite lives\n\n0 00A-17B-C49\n  1 3E0-14B Jump high \n");
+
+        assert_eq!(
+            list,
+            [
+                Cheat {
+                    name: "Infinite lives".into(),
+                    code: "01FF1AC1".into(),
+                    enabled: true,
+                },
+                Cheat {
+                    name: String::new(),
+                    code: "00A-17B-C49".into(),
+                    enabled: false,
+                },
+                Cheat {
+                    name: "Jump high".into(),
+                    code: "3E0-14B".into(),
+                    enabled: true,
+                },
+            ]
+        );
+    }
+}
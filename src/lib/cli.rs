@@ -0,0 +1,345 @@
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+pub(crate) const HELP: &str = "\
+Usage: oxyboy [OPTIONS] [ROM] [PATCH]
+
+Runs a Game Boy ROM (.gb, .gbc, .sgb, or one inside a .zip, .gz or .7z),
+optionally soft-patched with an IPS, UPS or BPS file. Options left out
+come from config.toml in the config directory, then the defaults below.
+
+Options:
+      --model <dmg>          Which Game Boy to run as. Only the DMG is
+                             emulated so far [default: dmg]
+      --boot-rom <FILE>      Run a 256-byte DMG boot ROM first
+      --no-boot-rom          Skip the boot ROM set in the config
+      --scale <N>            Window size as a multiple of 160x144 [default: 4]
+      --fullscreen           Start fullscreen
+      --save-dir <DIR>       Where save states go [default: next to the ROM]
+      --load-state <FILE>    Load a save state once the ROM is running
+      --speed <PERCENT>      Emulation speed, 25 or more, or 'unlimited' [default: 100]
+      --mute                 Start muted. There's no sound yet, so this does
+                             nothing for now
+      --headless             Run without a window. Needs --frames
+      --frames <N>           With --headless, how many frames to run
+      --screenshot <FILE>    With --headless, save the last frame as a PNG
+      --trace <FILE>         Log every instruction, gzipped if FILE ends in .gz
+      --link <HOST:PORT>     Link cable over TCP. Connects, or waits for the
+                             other side to connect if nobody's listening
+  -h, --help                 Print this help
+  -V, --version              Print the version
+
+Exits with 1 if something goes wrong and 2 if the options are wrong.
+";
+
+/// What the command line asked for.
+pub(crate) enum Parsed {
+    Run(Box<Options>),
+    Help,
+    Version,
+}
+
+pub(crate) struct Options {
+    pub(crate) rom: Option<PathBuf>,
+    pub(crate) patch: Option<PathBuf>,
+    pub(crate) boot_rom: Option<PathBuf>,
+    pub(crate) scale: f32,
+    pub(crate) fullscreen: bool,
+    pub(crate) save_dir: Option<PathBuf>,
+    pub(crate) load_state: Option<PathBuf>,
+    /// A multiple of full speed, or `None` for flat out.
+    pub(crate) speed: Option<f32>,
+    pub(crate) mute: bool,
+    pub(crate) headless: bool,
+    pub(crate) frames: Option<u64>,
+    pub(crate) screenshot: Option<PathBuf>,
+    pub(crate) trace: Option<PathBuf>,
+    pub(crate) link: Option<String>,
+}
+
+impl Options {
+    fn new(config: &Config) -> Self {
+        Self {
+            rom: None,
+            patch: None,
+            boot_rom: None,
+            scale: config.scale,
+            fullscreen: false,
+            save_dir: config.save_dir.clone(),
+            load_state: None,
+            speed: Some(1.0),
+            mute: config.mute,
+            headless: false,
+            frames: None,
+            screenshot: None,
+            trace: None,
+            link: None,
+        }
+    }
+}
+
+/// Parses the arguments after the program name, on top of `config`.
+/// Options can be given as `--name value` or `--name=value`.
+pub(crate) fn parse(
+    args: impl IntoIterator<Item = String>,
+    config: &Config,
+) -> Result<Parsed, String> {
+    let mut options = Options::new(config);
+    let mut boot_rom = true;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix("--").filter(|_| arg != "--") else {
+            match arg.as_str() {
+                "-h" => return Ok(Parsed::Help),
+                "-V" => return Ok(Parsed::Version),
+                "--" => positional.extend(args.by_ref().map(PathBuf::from)),
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("unknown option '{arg}'"))
+                }
+                _ => positional.push(PathBuf::from(&arg)),
+            }
+            continue;
+        };
+
+        let (name, inline) = match name.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (name, None),
+        };
+
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("--{name} needs a value"))
+        };
+
+        match name {
+            "help" => return Ok(Parsed::Help),
+            "version" => return Ok(Parsed::Version),
+            "model" => match value()?.to_ascii_lowercase().as_str() {
+                "dmg" => {}
+                "cgb" => return Err("CGB hardware isn't emulated yet, only dmg is".into()),
+                other => return Err(format!("unknown model '{other}', expected dmg")),
+            },
+            "boot-rom" => options.boot_rom = Some(value()?.into()),
+            "no-boot-rom" => boot_rom = false,
+            "scale" => {
+                options.scale = number(name, &value()?)?;
+                if !(1.0..=16.0).contains(&options.scale) {
+                    return Err("--scale must be from 1 to 16".into());
+                }
+            }
+            "fullscreen" => options.fullscreen = true,
+            "save-dir" => options.save_dir = Some(value()?.into()),
+            "load-state" => options.load_state = Some(value()?.into()),
+            "speed" => {
+                let speed = value()?;
+                options.speed = match speed.trim_end_matches('%') {
+                    "unlimited" => None,
+                    percent => {
+                        let percent: f32 = number(name, percent)?;
+                        if !percent.is_finite() {
+                            return Err(format!("--speed expects a number, not '{speed}'"));
+                        }
+                        if percent < 25.0 {
+                            return Err("--speed must be at least 25".into());
+                        }
+                        Some(percent / 100.0)
+                    }
+                };
+            }
+            "mute" => options.mute = true,
+            "headless" => options.headless = true,
+            "frames" => options.frames = Some(number(name, &value()?)?),
+            "screenshot" => options.screenshot = Some(value()?.into()),
+            "trace" => options.trace = Some(value()?.into()),
+            "link" => options.link = Some(value()?),
+            _ => return Err(format!("unknown option '--{name}'")),
+        }
+
+        let flag = matches!(name, "no-boot-rom" | "fullscreen" | "mute" | "headless");
+        if flag && inline.is_some() {
+            return Err(format!("--{name} doesn't take a value"));
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    options.rom = positional.next();
+    options.patch = positional.next();
+
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument '{}'", extra.display()));
+    }
+
+    if options.headless {
+        if options.rom.is_none() {
+            return Err("--headless needs a ROM".into());
+        }
+        if options.frames.is_none() {
+            return Err("--headless needs --frames".into());
+        }
+    } else if options.frames.is_some() || options.screenshot.is_some() {
+        return Err("--frames and --screenshot only work with --headless".into());
+    }
+
+    if options.load_state.is_some() && options.rom.is_none() {
+        return Err("--load-state needs a ROM".into());
+    }
+
+    if !boot_rom {
+        options.boot_rom = None;
+    } else if options.boot_rom.is_none() {
+        options.boot_rom = config.boot_rom.clone();
+    }
+
+    Ok(Parsed::Run(Box::new(options)))
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("--{name} expects a number, not '{value}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Parsed, String> {
+        parse(args.iter().map(|arg| arg.to_string()), &Config::default())
+    }
+
+    fn options(args: &[&str]) -> Options {
+        match parse_args(args) {
+            Ok(Parsed::Run(options)) => *options,
+            Ok(_) => panic!("{args:?} didn't ask for a run"),
+            Err(e) => panic!("{args:?}: {e}"),
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse_args(args) {
+            Err(e) => e,
+            Ok(_) => panic!("{args:?} parsed"),
+        }
+    }
+
+    #[test]
+    fn options_and_values() {
+        let o = options(&[
+            "--scale=2",
+            "--boot-rom",
+            "dmg.bin",
+            "--speed",
+            "150%",
+            "--fullscreen",
+            "--mute",
+            "--model",
+            "DMG",
+            "game.gb",
+            "game.ips",
+        ]);
+        assert_eq!(o.scale, 2.0);
+        assert_eq!(o.boot_rom, Some("dmg.bin".into()));
+        assert_eq!(o.speed, Some(1.5));
+        assert!(o.fullscreen);
+        assert!(o.mute);
+        assert_eq!(o.rom, Some("game.gb".into()));
+        assert_eq!(o.patch, Some("game.ips".into()));
+
+        let o = options(&[
+            "--headless",
+            "--frames",
+            "60",
+            "--screenshot",
+            "out.png",
+            "game.gb",
+        ]);
+        assert!(o.headless);
+        assert_eq!(o.frames, Some(60));
+        assert_eq!(o.screenshot, Some("out.png".into()));
+
+        assert_eq!(options(&["--speed", "unlimited"]).speed, None);
+        assert_eq!(options(&["--", "--odd.gb"]).rom, Some("--odd.gb".into()));
+        assert!(matches!(parse_args(&["-h"]), Ok(Parsed::Help)));
+        assert!(matches!(parse_args(&["--version"]), Ok(Parsed::Version)));
+    }
+
+    #[test]
+    fn the_config_fills_in() {
+        let config = Config {
+            scale: 3.0,
+            boot_rom: Some("dmg.bin".into()),
+            mute: true,
+            ..Config::default()
+        };
+        let args = |args: &[&str]| match parse(args.iter().map(|a| a.to_string()), &config) {
+            Ok(Parsed::Run(options)) => options,
+            _ => panic!("{args:?} didn't parse"),
+        };
+
+        let o = args(&[]);
+        assert_eq!(o.scale, 3.0);
+        assert!(o.mute);
+        assert_eq!(o.boot_rom, Some("dmg.bin".into()));
+
+        assert_eq!(args(&["--no-boot-rom"]).boot_rom, None);
+        assert_eq!(args(&["--scale", "5"]).scale, 5.0);
+    }
+
+    #[test]
+    fn malformed_options() {
+        assert_eq!(error(&["--nope"]), "unknown option '--nope'");
+        assert_eq!(error(&["-x"]), "unknown option '-x'");
+        assert_eq!(error(&["--scale"]), "--scale needs a value");
+        assert_eq!(
+            error(&["--scale", "big"]),
+            "--scale expects a number, not 'big'"
+        );
+        assert_eq!(error(&["--scale", "20"]), "--scale must be from 1 to 16");
+        assert_eq!(error(&["--speed", "10"]), "--speed must be at least 25");
+        assert_eq!(
+            error(&["--speed", "NaN"]),
+            "--speed expects a number, not 'NaN'"
+        );
+        assert_eq!(
+            error(&["--speed", "inf%"]),
+            "--speed expects a number, not 'inf%'"
+        );
+        assert_eq!(error(&["--scale", "NaN"]), "--scale must be from 1 to 16");
+        assert_eq!(
+            error(&["--fullscreen=yes"]),
+            "--fullscreen doesn't take a value"
+        );
+        assert_eq!(error(&["--mute=no"]), "--mute doesn't take a value");
+        assert_eq!(error(&["a.gb", "b.ips", "c"]), "unexpected argument 'c'");
+        assert_eq!(
+            error(&["--headless", "--frames", "1"]),
+            "--headless needs a ROM"
+        );
+        assert_eq!(
+            error(&["--headless", "game.gb"]),
+            "--headless needs --frames"
+        );
+        assert_eq!(
+            error(&["--frames", "1", "game.gb"]),
+            "--frames and --screenshot only work with --headless"
+        );
+        assert_eq!(error(&["--load-state", "s"]), "--load-state needs a ROM");
+        assert_eq!(
+            error(&["--model", "sgb"]),
+            "unknown model 'sgb', expected dmg"
+        );
+    }
+
+    #[test]
+    fn unemulated_hardware_is_refused() {
+        assert_eq!(
+            error(&["--model", "cgb"]),
+            "CGB hardware isn't emulated yet, only dmg is"
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// What's in `config.toml`. Key bindings have `input.toml` to themselves.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    /// Window size as a multiple of 160x144.
+    pub(crate) scale: f32,
+    /// What the four shades of grey are drawn as, lightest first.
+    pub(crate) palette: [Colour; 4],
+    /// 0 to 1. There's no sound yet, so it's only remembered.
+    pub(crate) volume: f32,
+    pub(crate) mute: bool,
+    /// Where save states go, if not next to the ROM.
+    pub(crate) save_dir: Option<PathBuf>,
+    /// A 256-byte DMG boot ROM, run before every ROM when set.
+    pub(crate) boot_rom: Option<PathBuf>,
+}
+
+/// An `#rrggbb` colour.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Colour(pub(crate) [u8; 3]);
+
+impl TryFrom<String> for Colour {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        let hex = s.strip_prefix('#').unwrap_or(&s);
+        let rgb = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("expected a colour like #e0f8d0, got `{s}`"))?;
+
+        let [_, r, g, b] = rgb.to_be_bytes();
+        Ok(Colour([r, g, b]))
+    }
+}
+
+impl From<Colour> for String {
+    fn from(Colour([r, g, b]): Colour) -> String {
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+const fn rgb(rgb: u32) -> Colour {
+    let [_, r, g, b] = rgb.to_be_bytes();
+    Colour([r, g, b])
+}
+
+/// Palettes the settings window offers, as well as picking each colour.
+pub(crate) const PALETTES: [(&str, [Colour; 4]); 3] = [
+    (
+        "Grey",
+        [rgb(0xFFFFFF), rgb(0xC0C0C0), rgb(0x606060), rgb(0x000000)],
+    ),
+    (
+        "Original green",
+        [rgb(0x9BBC0F), rgb(0x8BAC0F), rgb(0x306230), rgb(0x0F380F)],
+    ),
+    (
+        "Pocket",
+        [rgb(0xC4CFA1), rgb(0x8B956D), rgb(0x4D533C), rgb(0x1F1F1F)],
+    ),
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scale: 4.0,
+            palette: PALETTES[0].1,
+            volume: 1.0,
+            mute: false,
+            save_dir: None,
+            boot_rom: None,
+        }
+    }
+}
+
+impl Config {
+    /// Falls back to the defaults, saying why unless there's simply no file.
+    pub(crate) fn load() -> Config {
+        let Some(path) = path("config.toml") else {
+            return Config::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text).unwrap_or_else(|e| {
+                eprintln!("Couldn't parse {}: {e}", path.display());
+                Config::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                eprintln!("Couldn't read {}: {e}", path.display());
+                Config::default()
+            }
+        }
+    }
+
+    fn parse(text: &str) -> Result<Config, toml::de::Error> {
+        let config: Config = toml::from_str(text)?;
+
+        Ok(Config {
+            scale: config.scale.clamp(1.0, 16.0),
+            volume: config.volume.clamp(0.0, 1.0),
+            ..config
+        })
+    }
+
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let path = path("config.toml").ok_or(io::ErrorKind::NotFound)?;
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        write_atomic(&path, &text)
+    }
+
+    /// Redraws a frame from the PPU, in shades of grey, in the palette.
+    pub(crate) fn colourise(&self, frame: &[u8]) -> Vec<u8> {
+        frame
+            .chunks(3)
+            .flat_map(|pixel| {
+                let shade = match pixel[0] {
+                    0xC1.. => 0,
+                    0x61..=0xC0 => 1,
+                    0x01..=0x60 => 2,
+                    0 => 3,
+                };
+                self.palette[shade].0
+            })
+            .collect()
+    }
+}
+
+/// Where `name` goes in the platform config directory
+/// (`~/.config/oxyboy` on Linux).
+pub(crate) fn path(name: &str) -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("oxyboy").join(name))
+}
+
+/// Writes to a temporary file and renames it over `path`, so a crash
+/// halfway through can't leave half a file.
+pub(crate) fn write_atomic(path: &Path, text: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    fs::write(&temp, text)?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colour(s: &str) -> Result<[u8; 3], String> {
+        Colour::try_from(s.to_string()).map(|c| c.0)
+    }
+
+    #[test]
+    fn colours() {
+        assert_eq!(colour("#e0f8d0"), Ok([0xE0, 0xF8, 0xD0]));
+        assert_eq!(colour("#0F380F"), Ok([0x0F, 0x38, 0x0F]));
+        // The # can be left off.
+        assert_eq!(colour("9bbc0f"), Ok([0x9B, 0xBC, 0x0F]));
+        assert_eq!(String::from(Colour([0x9B, 0xBC, 0x0F])), "#9bbc0f");
+    }
+
+    #[test]
+    fn malformed_colours() {
+        for bad in [
+            "",
+            "#",
+            "#e0f8d",
+            "#e0f8d0ff",
+            "#e0f8dg",
+            "#+0f8d0",
+            "e0 f8 d0",
+        ] {
+            assert_eq!(
+                colour(bad),
+                Err(format!("expected a colour like #e0f8d0, got `{bad}`")),
+                "{bad:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn config() {
+        let config = Config::parse(
+            r##"
+            scale = 3.0
+            palette = ["#9bbc0f", "#8bac0f", "#306230", "#0f380f"]
+            boot_rom = "dmg.bin"
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(config.scale, 3.0);
+        assert!(config.palette == PALETTES[1].1);
+        assert_eq!(config.boot_rom, Some("dmg.bin".into()));
+        // Anything left out is the default.
+        assert_eq!(config.volume, 1.0);
+        assert_eq!(config.save_dir, None);
+
+        let saved = toml::to_string(&config).unwrap();
+        assert!(Config::parse(&saved).unwrap() == config);
+        assert!(Config::parse("").unwrap() == Config::default());
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let config = Config::parse("scale = 0.5\nvolume = -1.0").unwrap();
+        assert_eq!((config.scale, config.volume), (1.0, 0.0));
+
+        let config = Config::parse("scale = 40.0\nvolume = 2.5").unwrap();
+        assert_eq!((config.scale, config.volume), (16.0, 1.0));
+    }
+
+    #[test]
+    fn malformed_config() {
+        let Err(e) = Config::parse(r##"palette = ["#fff", "#000", "#000", "#000"]"##) else {
+            panic!("a short colour parsed");
+        };
+        assert!(e
+            .to_string()
+            .contains("expected a colour like #e0f8d0, got `#fff`"));
+
+        assert!(Config::parse(r##"palette = ["#ffffff"]"##).is_err());
+        assert!(Config::parse("scale = \"big\"").is_err());
+        assert!(Config::parse("scale = ").is_err());
+    }
+
+    #[test]
+    fn atomic_writes() {
+        let dir = std::env::temp_dir().join(format!("oxyboy-{}-config", std::process::id()));
+        let path = dir.join("nested").join("config.toml");
+
+        write_atomic(&path, "scale = 2.0").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "scale = 2.0");
+
+        write_atomic(&path, "scale = 3.0").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "scale = 3.0");
+        assert!(!path.with_extension("toml.tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{
+    error::Result,
     mmu::Mmu,
     registers::{Register, Registers},
+    state::{Reader, Writer},
+    trace::Tracer,
 };
 
 use bitmatch::bitmatch;
@@ -29,6 +32,7 @@ impl Condition {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 #[repr(u8)]
 pub(crate) enum Flag {
     Z = 0b1000_0000,
@@ -44,6 +48,17 @@ pub(crate) struct Cpu {
     setdi: u8,
     pub(crate) reg: Registers,
     pub(crate) halted: bool,
+    halt_bug: bool,
+    pub(crate) lockup: Option<Lockup>,
+    pub(crate) cycles: u64,
+    pub(crate) tracer: Option<Tracer>,
+}
+
+/// Where the CPU hung after fetching an unused opcode.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Lockup {
+    pub(crate) pc: u16,
+    pub(crate) opcode: u8,
 }
 
 fn decode_condition(cond: u8) -> Condition {
@@ -80,6 +95,10 @@ impl Cpu {
             setei: 0,
             setdi: 0,
             halted: false,
+            halt_bug: false,
+            lockup: None,
+            cycles: 0,
+            tracer: None,
         }
     }
 
@@ -101,61 +120,120 @@ impl Cpu {
         self.setei = 0;
         self.setdi = 0;
         self.halted = false;
+        self.halt_bug = false;
+        self.lockup = None;
+    }
+
+    /// Starts from the top of a boot ROM instead of where it leaves off.
+    pub(crate) fn boot(&mut self, rom: Vec<u8>) {
+        self.reg = Registers::default();
+        self.mmu.boot_rom = rom;
+        self.mmu.booting = true;
+    }
+
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        let r = &self.reg;
+        for v in [r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l] {
+            w.u8(v);
+        }
+        w.u16(r.pc);
+        w.u16(r.sp);
+
+        w.bool(self.ime);
+        w.u8(self.setei);
+        w.u8(self.setdi);
+        w.bool(self.halted);
+        w.bool(self.halt_bug);
+        w.bool(self.lockup.is_some());
+        if let Some(lockup) = self.lockup {
+            w.u16(lockup.pc);
+            w.u8(lockup.opcode);
+        }
+        w.u64(self.cycles);
+
+        self.mmu.save_state(w);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut Reader) -> Result<()> {
+        let reg = &mut self.reg;
+        for v in [
+            &mut reg.a, &mut reg.f, &mut reg.b, &mut reg.c, &mut reg.d, &mut reg.e, &mut reg.h,
+            &mut reg.l,
+        ] {
+            *v = r.u8()?;
+        }
+        reg.pc = r.u16()?;
+        reg.sp = r.u16()?;
+
+        self.ime = r.bool()?;
+        self.setei = r.u8()?;
+        self.setdi = r.u8()?;
+        self.halted = r.bool()?;
+        self.halt_bug = r.bool()?;
+        self.lockup = match r.bool()? {
+            true => Some(Lockup {
+                pc: r.u16()?,
+                opcode: r.u8()?,
+            }),
+            false => None,
+        };
+        self.cycles = r.u64()?;
+
+        self.mmu.load_state(r)
     }
 
     #[rustfmt::skip]
     #[bitmatch]
-    pub fn fde(&mut self) -> u32 {
+    pub fn fde(&mut self) {
         let opcode = self.fetchb();
 
         #[bitmatch]
         match opcode {
             // NOP
-            "00000000" => 1,
+            "00000000" => {}
 
             // LD (u16), SP
-            "00001000" => { let v = self.fetchw(); self.writew(v, self.reg.sp);  5 }
+            "00001000" => { let a = self.fetchw(); self.writew(a, self.reg.sp); }
             
             // STOP
-            "00010000" => 1,
+            "00010000" => {}
 
             // JR (unconditional)
-            "00011000" => { self.jr(); 3 }
+            "00011000" => self.jr(),
 
             // JR (conditional)
             "001cc000" => {
-                if decode_condition(c).check(self) { self.jr(); 3 }
-                else { self.reg.pc = self.reg.pc.wrapping_add(1); 2 }
+                if decode_condition(c).check(self) { self.jr(); }
+                else { self.fetchb(); }
             }
 
             // LD r16, u16
-            "00rr0001" => { let r = self.reg.decode_r16_g1(r); let v = self.fetchw(); self.setreg(&r, v); 3 }
+            "00rr0001" => { let r = self.reg.decode_r16_g1(r); let v = self.fetchw(); self.setreg(&r, v); }
 
             // ADD HL, r16
             "00rr1001" => {
                 let r = self.reg.decode_r16_g1(r);
                 let v = self.readreg(&r);
                 self.alu_add16(v);
-                2
+                self.internal();
             }
 
             // LD (r16), A
-            "00rr0010" => { let r = self.reg.decode_r16_g2(r); let a = self.readreg(&r); let v = self.reg.a; self.writeb(a, v); 2 }
+            "00rr0010" => { let r = self.reg.decode_r16_g2(r); let a = self.readreg(&r); let v = self.reg.a; self.writeb(a, v); }
 
             // LD A,(r16)
             "00rr1010" => {
                 let r = self.reg.decode_r16_g2(r);
                 let a = self.readreg(&r);
-                let v = self.mmu.rb(a);
+                let v = self.readb(a);
                 self.reg.a = v;
-                2
             }
 
             // INC r16
-            "00rr0011" => { let r = self.reg.decode_r16_g1(r); let v = self.readreg(&r); self.setreg(&r, v.wrapping_add(1)); 2 }
+            "00rr0011" => { let r = self.reg.decode_r16_g1(r); let v = self.readreg(&r); self.setreg(&r, v.wrapping_add(1)); self.internal(); }
 
             // DEC r16
-            "00rr1011" => { let r = self.reg.decode_r16_g1(r); let v = self.readreg(&r); self.setreg(&r, v.wrapping_sub(1)); 2 }
+            "00rr1011" => { let r = self.reg.decode_r16_g1(r); let v = self.readreg(&r); self.setreg(&r, v.wrapping_sub(1)); self.internal(); }
 
             // INC r8
             "00rrr100" => {
@@ -163,16 +241,13 @@ impl Cpu {
                 let a = self.readreg(&r);
                 match r {
                     Register::Hlm => {
-                        let a = self.reg.hl();
-                        let v = self.mmu.rb(a);
+                        let v = self.readb(a);
                         let v2 = self.alu_inc(v);
-                        self.mmu.wb(a, v2);
-                        3
+                        self.writeb(a, v2);
                     }
                     _ => {
                         let v = self.alu_inc(a as u8);
                         self.setreg(&r, v as u16);
-                        1
                     }
                 }
             }
@@ -183,16 +258,13 @@ impl Cpu {
                 let a = self.readreg(&r);
                 match r {
                     Register::Hlm => {
-                        let a = self.reg.hl();
-                        let v = self.mmu.rb(a);
+                        let v = self.readb(a);
                         let v2 = self.alu_dec(v);
-                        self.mmu.wb(a, v2);
-                        3
+                        self.writeb(a, v2);
                     }
                     _ => {
                         let v = self.alu_dec(a as u8);
                         self.setreg(&r, v as u16);
-                        1
                     }
                 }
             }
@@ -200,24 +272,32 @@ impl Cpu {
             "00rrr110" => {
                 let r = self.reg.decode_r8(r);
                 match r {
-                    Register::Hlm => { let v = self.fetchb(); let a = self.readreg(&r); self.writeb(a, v); 3 }
-                    _ => { let v = self.fetchb(); self.setreg(&r, v as u16); 2 }
+                    Register::Hlm => { let v = self.fetchb(); let a = self.readreg(&r); self.writeb(a, v); }
+                    _ => { let v = self.fetchb(); self.setreg(&r, v as u16); }
                 }
             }
             // 
             "00iii111" => match i {
-                0 => { self.reg.a = self.alu_rlc(self.reg.a); self.reg.flag(Z, false); 1 },
-                1 => { self.reg.a = self.alu_rrc(self.reg.a); self.reg.flag(Z, false); 1 }
-                2 => { self.reg.a = self.alu_rl(self.reg.a); self.reg.flag(Z, false); 1 }
-                3 => { self.reg.a = self.alu_rr(self.reg.a); self.reg.flag(Z, false); 1 }
-                4 => { self.alu_daa(); 1},
-                5 => { self.reg.a = !self.reg.a; self.reg.flag(H, true); self.reg.flag(N, true); 1 }
-                6 => { self.reg.flag(C, true); self.reg.flag(H, false); self.reg.flag(N, false); 1 }
-                7 => { let c = !self.reg.getflag(C); self.reg.flag(C, c); self.reg.flag(H, false); self.reg.flag(N, false); 1 }
+                0 => { self.reg.a = self.alu_rlc(self.reg.a); self.reg.flag(Z, false); },
+                1 => { self.reg.a = self.alu_rrc(self.reg.a); self.reg.flag(Z, false); }
+                2 => { self.reg.a = self.alu_rl(self.reg.a); self.reg.flag(Z, false); }
+                3 => { self.reg.a = self.alu_rr(self.reg.a); self.reg.flag(Z, false); }
+                4 => self.alu_daa(),
+                5 => { self.reg.a = !self.reg.a; self.reg.flag(H, true); self.reg.flag(N, true); }
+                6 => { self.reg.flag(C, true); self.reg.flag(H, false); self.reg.flag(N, false); }
+                7 => { let c = !self.reg.getflag(C); self.reg.flag(C, c); self.reg.flag(H, false); self.reg.flag(N, false); }
                 _ => unreachable!(),
             },
             // HALT
-            "01110110" => { self.halted = true; 1 }
+            "01110110" => {
+                // With IME=0 and an interrupt already pending, HALT exits immediately
+                // and the next opcode byte is read twice.
+                if !self.ime && self.mmu.inte & self.mmu.intf & 0x1F != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
             // LD r8, r8
             "01dddsss" => {
                 let s = &self.reg.decode_r8(s);
@@ -227,16 +307,13 @@ impl Cpu {
                     let a = self.reg.hl();
                     let v = self.readreg(s) as u8;
                     self.writeb(a, v);
-                    2
                 } else if s == &Register::Hlm {
                     let a = self.reg.hl();
-                    let v = self.mmu.rb(a);
+                    let v = self.readb(a);
                     self.setreg(d, v as u16);
-                    2
                 } else {
                     let v = self.readreg(s);
                     self.setreg(d, v);
-                    1
                 }
             }
 
@@ -245,20 +322,19 @@ impl Cpu {
                 let r = self.reg.decode_r8(r);
                 let v = self.readr8reg(&r);
                 self.alu_a(i, v);
-                if r == Register::Hlm { 2 } else { 1 }
             }
 
             // RET condition
             "110cc000" => {
                 let c = decode_condition(c).check(self);
-                if c { self.reg.pc = self.popstack(); 5 } else { 2 }
+                self.internal();
+                if c { self.reg.pc = self.popstack(); self.internal(); }
             }
             
             // LD (FF00 + u8), A
             "11100000" => {
                 let a = 0xFF00 | self.fetchb() as u16;
                 self.writeb(a, self.reg.a);
-                3
             }
 
             // ADD SP, i8
@@ -271,12 +347,13 @@ impl Cpu {
                 self.reg.flag(H, (a & 0x000F) + (b & 0x000F) > 0x000F);
                 self.reg.flag(C, (a & 0x00FF) + (b & 0x00FF) > 0x00FF);
 
+                self.internal();
+                self.internal();
                 self.reg.sp = a.wrapping_add(b);
-                4
             }
 
             // LD A, (FF00+u8)
-            "11110000" => { let a = self.fetchb() as u16 | 0xFF00; let v = self.mmu.rb(a); self.reg.a = v; 3 }
+            "11110000" => { let a = self.fetchb() as u16 | 0xFF00; let v = self.readb(a); self.reg.a = v; }
 
             // LD HL, SP+i8
             "11111000" => {
@@ -288,8 +365,8 @@ impl Cpu {
                 self.reg.flag(H, (sp & 0x000F) + (v & 0x000F) > 0x000F);
                 self.reg.flag(C, (sp & 0x00FF) + (v & 0x00FF) > 0x00FF);
 
+                self.internal();
                 self.reg.sethl(sp.wrapping_add(v));
-                3
             }
 
             // POP r16
@@ -298,39 +375,38 @@ impl Cpu {
                 let mut v = self.popstack();
                 if r == Register::A { v &= 0xFFF0 }
                 self.setreg(&r, v);
-                3
             }
 
             // RET, RETI, JP HL, LD SP, HL
             "11ii1001" => match i {
-                0 => { self.reg.pc = self.popstack(); 4 },
-                1 => { self.setei = 1; self.reg.pc = self.popstack(); 4 }
-                2 => { self.reg.pc = self.reg.hl(); 1 },
-                3 => { self.reg.sp = self.reg.hl(); 2 },
+                0 => { self.reg.pc = self.popstack(); self.internal(); },
+                1 => { self.setei = 1; self.reg.pc = self.popstack(); self.internal(); }
+                2 => self.reg.pc = self.reg.hl(),
+                3 => { self.reg.sp = self.reg.hl(); self.internal(); },
                 _ => unreachable!(),
             },
 
             // JP (conditional)
-            "110cc010" => match decode_condition(c).check(self) {
-                true => { self.reg.pc = self.fetchw(); 4 }
-                false => { self.reg.pc = self.reg.pc.wrapping_add(2); 3 }
-            },
+            "110cc010" => {
+                let a = self.fetchw();
+                if decode_condition(c).check(self) { self.reg.pc = a; self.internal(); }
+            }
 
             // LD (FF00+C), A
-            "11100010" => { let a = self.reg.c as u16 | 0xFF00; let v = self.reg.a; self.writeb(a, v); 2 }
+            "11100010" => { let a = self.reg.c as u16 | 0xFF00; let v = self.reg.a; self.writeb(a, v); }
             // LD (u16), A
-            "11101010" => { let a = self.fetchw(); self.writeb(a, self.reg.a); 4 }
+            "11101010" => { let a = self.fetchw(); self.writeb(a, self.reg.a); }
             //LD A, (0xFF00+C)
-            "11110010" => { let a = self.reg.c as u16 | 0xFF00; let v = self.mmu.rb(a); self.reg.a = v; 2 }
+            "11110010" => { let a = self.reg.c as u16 | 0xFF00; let v = self.readb(a); self.reg.a = v; }
             //LD A, (u16)
-            "11111010" => { let a = self.fetchw(); let v = self.mmu.rb(a); self.reg.a = v; 4 }
+            "11111010" => { let a = self.fetchw(); let v = self.readb(a); self.reg.a = v; }
 
             // 0: JP u16, 1:
             // (CB prefix), 6:
             // DI, 7: EI. ALL
             "11iii011" => {
                 match i {
-                    0 => { let x = self.fetchw(); self.reg.pc = x; 4 }
+                    0 => { let x = self.fetchw(); self.reg.pc = x; self.internal(); }
                     1 => {
                         let opcode = self.fetchb();
 
@@ -339,18 +415,15 @@ impl Cpu {
                             "00iiirrr" => {
                                 let r = self.reg.decode_r8(r);
                                 self.alu_shift(i, &r);
-                                if r == Register::Hlm { 4 } else { 2 }
                             }
                             "01bbbrrr" => {
                                 let r = self.reg.decode_r8(r);
                                 let v = self.readr8reg(&r);
                                 self.alu_bit(v, b);
-                                if r == Register::Hlm { 3 } else { 2 }
                             }
                             "10bbbrrr" => {
                                 let r = self.reg.decode_r8(r);
                                 self.alu_res(&r, b);
-                                if r == Register::Hlm { 4 } else { 2 }
                             }
                             "11bbbrrr" => {
                                 let r = self.reg.decode_r8(r);
@@ -358,40 +431,46 @@ impl Cpu {
 
                                 if r == Register::Hlm {
                                     let a = v;
-                                    let v = self.mmu.rb(a);
+                                    let v = self.readb(a);
                                     self.writeb(a, v | 1 << b);
-                                    4
                                 } else {
                                     self.setreg(&r, v | 1 << b);
-                                    2
                                 }
                             }
                             _ => unreachable!()
                         }
                     }
-                    6 => { self.setdi = 1; 1 },
-                    7 => { self.setei = 2; 1 },
-                    _ => unreachable!(),
+                    6 => self.setdi = 1,
+                    7 => self.setei = 2,
+                    _ => self.lock(opcode),
                 }
             },
             // CALL condition
             "110cc100" => { let c = decode_condition(c); self.conditional_call(c) }
             // PUSH r16
-            "11rr0101" => { let r = self.reg.decode_r16_g3(r); let v = self.readreg(&r); self.pushstack(v); 4 }
+            "11rr0101" => { let r = self.reg.decode_r16_g3(r); let v = self.readreg(&r); self.internal(); self.pushstack(v); }
             // CALL
-            "11001101" => { self.pushstack(self.reg.pc + 2); self.reg.pc = self.fetchw(); 6 }
+            "11001101" => { let a = self.fetchw(); self.internal(); self.pushstack(self.reg.pc); self.reg.pc = a; }
             // ALU A, u8
-            "11iii110" => { let v = self.fetchb(); self.alu_a(i, v); 2 }
+            "11iii110" => { let v = self.fetchb(); self.alu_a(i, v); }
             // RST
             "11eee111" => {
+                self.internal();
                 self.pushstack(self.reg.pc);
                 self.reg.pc = (e << 3) as u16;
-                4
             }
-            _ => unreachable!(),
+            // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB-0xED, 0xF4, 0xFC, 0xFD
+            _ => self.lock(opcode),
         }
     }
 
+    fn lock(&mut self, opcode: u8) {
+        self.lockup = Some(Lockup {
+            pc: self.reg.pc.wrapping_sub(1),
+            opcode,
+        });
+    }
+
     fn updateime(&mut self) {
         self.setdi = match self.setdi {
             2 => 1,
@@ -419,7 +498,7 @@ impl Cpu {
             0 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_rlc(val);
                     return self.writeb(a, res);
                 }
@@ -430,7 +509,7 @@ impl Cpu {
             1 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_rrc(val);
                     return self.writeb(a, res);
                 }
@@ -441,7 +520,7 @@ impl Cpu {
             2 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_rl(val);
                     return self.writeb(a, res);
                 }
@@ -452,7 +531,7 @@ impl Cpu {
             3 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_rr(val);
                     return self.writeb(a, res);
                 }
@@ -463,7 +542,7 @@ impl Cpu {
             4 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_sla(val);
                     return self.writeb(a, res);
                 }
@@ -474,7 +553,7 @@ impl Cpu {
             5 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_sra(val);
                     return self.writeb(a, res);
                 }
@@ -485,7 +564,7 @@ impl Cpu {
             6 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_swap(val);
                     return self.writeb(a, res);
                 }
@@ -496,7 +575,7 @@ impl Cpu {
             7 => {
                 if r == &Register::Hlm {
                     let a = v;
-                    let val = self.mmu.rb(a);
+                    let val = self.readb(a);
                     let res = self.alu_srl(val);
                     return self.writeb(a, res);
                 }
@@ -508,48 +587,13 @@ impl Cpu {
         }
     }
 
-    fn conditional_call(&mut self, cond: Condition) -> u32 {
-        match cond {
-            Condition::Z => {
-                if self.reg.getflag(Z) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchw();
-                    6
-                } else {
-                    self.reg.pc = self.reg.pc.wrapping_add(2);
-                    3
-                }
-            }
-            Condition::NZ => {
-                if !self.reg.getflag(Z) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchw();
-                    6
-                } else {
-                    self.reg.pc = self.reg.pc.wrapping_add(2);
-                    3
-                }
-            }
-            Condition::NC => {
-                if !self.reg.getflag(C) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchw();
-                    6
-                } else {
-                    self.reg.pc = self.reg.pc.wrapping_add(2);
-                    3
-                }
-            }
-            Condition::C => {
-                if self.reg.getflag(C) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchw();
-                    6
-                } else {
-                    self.reg.pc = self.reg.pc.wrapping_add(2);
-                    3
-                }
-            }
+    fn conditional_call(&mut self, cond: Condition) {
+        let a = self.fetchw();
+
+        if cond.check(self) {
+            self.internal();
+            self.pushstack(self.reg.pc);
+            self.reg.pc = a;
         }
     }
 
@@ -589,7 +633,7 @@ impl Cpu {
         match r {
             A | B | C | D | E | H | L => self.setreg(r, v & !(1 << b)),
             Hlm => {
-                let res = self.mmu.rb(v);
+                let res = self.readb(v);
                 self.writeb(v, res & !(1 << b))
             }
             _ => unreachable!(),
@@ -604,38 +648,67 @@ impl Cpu {
     }
 
     fn popstack(&mut self) -> u16 {
-        let res = self.mmu.rw(self.reg.sp);
-        self.reg.sp = self.reg.sp.wrapping_add(2);
-        res
+        let lo = self.readb(self.reg.sp) as u16;
+        self.reg.sp = self.reg.sp.wrapping_add(1);
+        let hi = self.readb(self.reg.sp) as u16;
+        self.reg.sp = self.reg.sp.wrapping_add(1);
+        lo | hi << 8
     }
 
     fn pushstack(&mut self, value: u16) {
-        self.reg.sp = self.reg.sp.wrapping_sub(2);
-        self.writew(self.reg.sp, value);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.writeb(self.reg.sp, (value >> 8) as u8);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.writeb(self.reg.sp, (value & 0xFF) as u8);
     }
 
     fn jr(&mut self) {
         let n = self.fetchb() as i8;
+        self.internal();
         self.reg.pc = ((self.reg.pc as u32 as i32) + (n as i32)) as u16;
     }
 
-    fn writew(&mut self, a: u16, v: u16) {
-        self.mmu.ww(a, v)
+    /// Advances the rest of the system by one M-cycle (4 T-cycles).
+    fn tick(&mut self) {
+        self.mmu.tick(4);
+        self.cycles += 4;
+    }
+
+    /// An M-cycle in which the CPU doesn't touch the bus.
+    fn internal(&mut self) {
+        self.tick();
+    }
+
+    fn readb(&mut self, a: u16) -> u8 {
+        self.tick();
+        self.mmu.rb(a)
     }
 
     fn writeb(&mut self, a: u16, v: u8) {
+        self.tick();
         self.mmu.wb(a, v)
     }
 
+    fn writew(&mut self, a: u16, v: u16) {
+        self.writeb(a, (v & 0xFF) as u8);
+        self.writeb(a.wrapping_add(1), (v >> 8) as u8);
+    }
+
     fn fetchw(&mut self) -> u16 {
-        let w = self.mmu.rw(self.reg.pc);
-        self.reg.pc = self.reg.pc.wrapping_add(2);
-        w
+        let lo = self.fetchb() as u16;
+        let hi = self.fetchb() as u16;
+        lo | hi << 8
     }
 
     fn fetchb(&mut self) -> u8 {
-        let b = self.mmu.rb(self.reg.pc);
-        self.reg.pc = self.reg.pc.wrapping_add(1);
+        let b = self.readb(self.reg.pc);
+
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.reg.pc = self.reg.pc.wrapping_add(1);
+        }
+
         b
     }
 
@@ -648,56 +721,96 @@ impl Cpu {
         }
     }
 
+    /// Runs one instruction (or interrupt dispatch, or halted M-cycle) and
+    /// returns the number of T-cycles it took. The bus is ticked as each
+    /// M-cycle happens, so there is nothing left to catch up on afterwards.
     pub(crate) fn step(&mut self) -> u32 {
+        let start = self.cycles;
+
         self.updateime();
 
-        match self.handle_interrupt() {
-            0 => {}
-            n => return n,
+        if self.lockup.is_some() {
+            // A locked up CPU never fetches again, not even for interrupts.
+            self.internal();
+        } else if !self.handle_interrupt() {
+            if self.halted {
+                self.internal();
+            } else {
+                self.trace();
+                self.fde();
+            }
+        }
+
+        (self.cycles - start) as u32
+    }
+
+    fn trace(&mut self) {
+        let Some(tracer) = &mut self.tracer else {
+            return;
         };
 
-        if self.halted {
-            1
-        } else {
-            self.fde()
+        if let Err(e) = tracer.log(&self.reg, &self.mmu) {
+            eprintln!("Stopped tracing: {e}");
+            self.tracer = None;
+        } else if tracer.finished {
+            self.stop_trace();
         }
     }
 
-    pub(crate) fn docycle(&mut self) {
-        let cycle = self.step() * 4;
-        self.mmu.tick(cycle);
+    /// Finishes off the trace file, if there is one.
+    pub(crate) fn stop_trace(&mut self) {
+        if let Some(tracer) = self.tracer.take() {
+            if let Err(e) = tracer.finish() {
+                eprintln!("Couldn't finish the trace: {e}");
+            }
+        }
     }
 
-    fn handle_interrupt(&mut self) -> u32 {
+    fn handle_interrupt(&mut self) -> bool {
         if !self.ime && !self.halted {
-            return 0;
+            return false;
         }
 
-        let triggered = self.mmu.inte & self.mmu.intf;
+        let triggered = self.mmu.inte & self.mmu.intf & 0x1F;
 
         if triggered == 0 {
-            return 0;
+            return false;
         }
 
         self.halted = false;
 
         if !self.ime {
-            return 0;
+            return false;
         }
 
         self.ime = false;
 
-        let n = triggered.trailing_zeros();
-
-        self.mmu.intf &= !(1 << n);
+        self.internal();
+        self.internal();
 
         let pc = self.reg.pc;
 
-        self.pushstack(pc);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.writeb(self.reg.sp, (pc >> 8) as u8);
+
+        // The high byte push can land on IE (SP=0x0000) and change which
+        // interrupt gets serviced. If none is left, PC ends up at 0x0000.
+        let triggered = self.mmu.inte & self.mmu.intf & 0x1F;
 
-        self.reg.pc = 0x0040 | ((n as u16) << 3);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.writeb(self.reg.sp, (pc & 0xFF) as u8);
 
-        4
+        self.reg.pc = if triggered == 0 {
+            0x0000
+        } else {
+            let n = triggered.trailing_zeros();
+            self.mmu.intf &= !(1 << n);
+            0x0040 | ((n as u16) << 3)
+        };
+
+        self.internal();
+
+        true
     }
 
     fn alu_inc(&mut self, a: u8) -> u8 {
@@ -754,7 +867,7 @@ impl Cpu {
 
         match r {
             A | B | C | D | E | H | L => self.readreg(r) as u8,
-            Hlm => self.mmu.rb(self.reg.hl()),
+            Hlm => self.readb(self.reg.hl()),
             _ => unreachable!(),
         }
     }
@@ -910,3 +1023,269 @@ impl Cpu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// M-cycles for each opcode, with conditional branches not taken.
+    /// Zero for the ones that can't be timed alone: STOP, HALT, CB and the
+    /// illegal opcodes.
+    #[rustfmt::skip]
+    const TIMES: [u8; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 0, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    /// A CPU about to run `code` from WRAM, with every pointer register
+    /// pointing at WRAM too.
+    fn cpu(code: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new();
+        for (i, &b) in code.iter().enumerate() {
+            cpu.mmu.wb(0xC000 + i as u16, b);
+        }
+
+        cpu.reg.pc = 0xC000;
+        cpu.reg.sp = 0xDFF0;
+        cpu.reg.setbc(0xC800);
+        cpu.reg.setde(0xC800);
+        cpu.reg.sethl(0xC800);
+        cpu
+    }
+
+    /// How many more M-cycles `op` takes if its condition holds.
+    fn taken(op: u8, f: u8) -> u8 {
+        let extra = match op {
+            0x20 | 0x28 | 0x30 | 0x38 | 0xC2 | 0xCA | 0xD2 | 0xDA => 1,
+            0xC0 | 0xC8 | 0xD0 | 0xD8 | 0xC4 | 0xCC | 0xD4 | 0xDC => 3,
+            _ => return 0,
+        };
+
+        let holds = match (op >> 3) & 3 {
+            0 => f & 0x80 == 0,
+            1 => f & 0x80 != 0,
+            2 => f & 0x10 == 0,
+            _ => f & 0x10 != 0,
+        };
+        if holds {
+            extra
+        } else {
+            0
+        }
+    }
+
+    #[test]
+    fn instruction_timing() {
+        for f in [0x00, 0xF0] {
+            for op in 0..=255u8 {
+                if TIMES[op as usize] == 0 {
+                    continue;
+                }
+
+                // Operands point at WRAM, or $FF00 for the LDH ones.
+                let mut cpu = cpu(&[op, 0x00, 0xC8]);
+                cpu.reg.f = f;
+
+                let expected = TIMES[op as usize] + taken(op, f);
+                assert_eq!(
+                    cpu.step() / 4,
+                    expected as u32,
+                    "opcode ${op:02X}, F=${f:02X}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cb_timing() {
+        for op in 0..=255u8 {
+            let expected = match (op & 7, op) {
+                (6, 0x40..=0x7F) => 3,
+                (6, _) => 4,
+                _ => 2,
+            };
+
+            let mut cpu = cpu(&[0xCB, op]);
+            assert_eq!(cpu.step() / 4, expected, "opcode $CB ${op:02X}");
+        }
+    }
+
+    /// Runs `code` after `delay` NOPs, with HL at TIMA and the timer
+    /// counting every 4 M-cycles from the first NOP.
+    fn with_timer(code: &[u8], delay: usize, a: u8) -> Cpu {
+        let mut program = vec![0x00; delay];
+        program.extend_from_slice(code);
+
+        let mut cpu = cpu(&program);
+        cpu.reg.a = a;
+        cpu.reg.sethl(0xFF05);
+        cpu.mmu.wb(0xFF07, 0x05);
+
+        for _ in 0..=delay {
+            cpu.step();
+        }
+        cpu
+    }
+
+    /// TIMA on M-cycle `cycle` of an instruction run after `delay` NOPs.
+    fn tima(delay: usize, cycle: usize) -> u8 {
+        ((delay + cycle) / 4) as u8
+    }
+
+    #[test]
+    fn read_timing() {
+        // The M-cycle the read happens on, counting the opcode fetch as 1.
+        let reads: [(&[u8], usize); 4] = [
+            (&[0x7E], 2),             // LD A,(HL)
+            (&[0x2A], 2),             // LD A,(HL+)
+            (&[0xF0, 0x05], 3),       // LDH A,($05)
+            (&[0xFA, 0x05, 0xFF], 4), // LD A,($FF05)
+        ];
+
+        for (code, cycle) in reads {
+            for delay in 0..4 {
+                let cpu = with_timer(code, delay, 0xFF);
+                assert_eq!(
+                    cpu.reg.a,
+                    tima(delay, cycle),
+                    "{code:02X?} after {delay} NOPs"
+                );
+            }
+        }
+
+        // BIT 0,(HL) reads on M3, which shows in Z.
+        for delay in 0..4 {
+            let cpu = with_timer(&[0xCB, 0x46], delay, 0);
+            let zero = cpu.reg.f & 0x80 != 0;
+            assert_eq!(
+                zero,
+                tima(delay, 3) & 1 == 0,
+                "BIT 0,(HL) after {delay} NOPs"
+            );
+        }
+    }
+
+    #[test]
+    fn write_timing() {
+        // The M-cycle the write happens on, and how long the instruction is.
+        let writes: [(&[u8], usize, usize); 4] = [
+            (&[0x77], 2, 2),             // LD (HL),A
+            (&[0x36, 0x80], 3, 3),       // LD (HL),$80
+            (&[0xE0, 0x05], 3, 3),       // LDH ($05),A
+            (&[0xEA, 0x05, 0xFF], 4, 4), // LD ($FF05),A
+        ];
+
+        for (code, cycle, len) in writes {
+            for delay in 0..4 {
+                let cpu = with_timer(code, delay, 0x80);
+                // Whatever counting happens after the write lands on top.
+                let after = tima(delay, len) - tima(delay, cycle);
+                assert_eq!(
+                    cpu.mmu.peek(0xFF05),
+                    0x80 + after,
+                    "{code:02X?} after {delay} NOPs"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn read_modify_write_timing() {
+        // INC (HL) reads on M2 and writes back on M3, its last.
+        for delay in 0..4 {
+            let cpu = with_timer(&[0x34], delay, 0);
+            assert_eq!(
+                cpu.mmu.peek(0xFF05),
+                tima(delay, 2) + 1,
+                "INC (HL) after {delay} NOPs"
+            );
+        }
+    }
+
+    #[test]
+    fn halt_bug() {
+        // With nothing pending, HALT halts.
+        let mut halted = cpu(&[0x76, 0x3C]);
+        halted.step();
+        assert!(halted.halted);
+
+        // HALT; INC A
+        let mut cpu = cpu(&[0x76, 0x3C, 0x00]);
+        cpu.reg.a = 0;
+        cpu.mmu.inte = 0x01;
+        cpu.mmu.intf = 0x01;
+
+        cpu.step();
+        assert!(!cpu.halted, "HALT with an interrupt pending doesn't halt");
+
+        // The byte after HALT is read twice, so INC A runs twice.
+        cpu.step();
+        assert_eq!(cpu.reg.pc, 0xC001);
+        cpu.step();
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (2, 0xC002));
+    }
+
+    #[test]
+    fn interrupt_pushed_onto_ie() {
+        let dispatch = |pc| {
+            let mut cpu = cpu(&[]);
+            cpu.reg.pc = pc;
+            cpu.reg.sp = 0x0000;
+            cpu.ime = true;
+            cpu.mmu.inte = 0x01;
+            cpu.mmu.intf = 0x01;
+
+            assert_eq!(cpu.step() / 4, 5);
+            assert!(!cpu.ime);
+            cpu
+        };
+
+        // $C0 lands on IE and leaves nothing enabled, so the dispatch is
+        // cancelled and IF isn't acknowledged.
+        let cpu = dispatch(0xC000);
+        assert_eq!(cpu.mmu.inte, 0xC0);
+        assert_eq!(cpu.mmu.intf & 0x1F, 0x01);
+        assert_eq!((cpu.reg.pc, cpu.reg.sp), (0x0000, 0xFFFE));
+
+        // $C1 keeps VBlank enabled, so it's serviced as normal.
+        let cpu = dispatch(0xC100);
+        assert_eq!(cpu.mmu.inte, 0xC1);
+        assert_eq!(cpu.mmu.intf & 0x1F, 0x00);
+        assert_eq!(cpu.reg.pc, 0x0040);
+    }
+
+    #[test]
+    fn illegal_opcodes_lock_up() {
+        // $D3; INC A
+        let mut cpu = cpu(&[0xD3, 0x3C]);
+        cpu.reg.a = 0;
+        cpu.step();
+
+        let lockup = cpu.lockup.expect("$D3 locks up");
+        assert_eq!((lockup.pc, lockup.opcode), (0xC000, 0xD3));
+
+        // Not even an interrupt gets it going again.
+        cpu.ime = true;
+        cpu.mmu.inte = 0x01;
+        cpu.mmu.intf = 0x01;
+        for _ in 0..10 {
+            assert_eq!(cpu.step(), 4);
+        }
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0, 0xC001));
+        assert_eq!(cpu.mmu.intf & 0x1F, 0x01);
+    }
+}
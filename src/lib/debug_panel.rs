@@ -0,0 +1,498 @@
+use std::sync::mpsc::Sender;
+
+use eframe::egui::{self, DragValue, Key, RichText};
+
+use crate::{
+    cpu::Flag,
+    debugger::{
+        parse_number, Access, BreakReason, Breakpoint, Condition, DebugCommand, DebugState, Reg,
+        Watchpoint,
+    },
+    trace::{TraceConfig, Trigger},
+    EmuCommand,
+};
+
+/// Side panel driving the `Debugger` on the emulator thread.
+pub(crate) struct DebugPanel {
+    pub(crate) open: bool,
+    tx: Sender<EmuCommand>,
+    state: Option<DebugState>,
+    bp_addr: String,
+    bp_cond: String,
+    wp_addr: String,
+    wp_access: Access,
+    trace_start: String,
+    trace_stop: String,
+    trace_compress: bool,
+    gdb_port: u16,
+    error: Option<String>,
+}
+
+impl DebugPanel {
+    pub(crate) fn new(tx: Sender<EmuCommand>) -> Self {
+        Self {
+            open: false,
+            tx,
+            state: None,
+            bp_addr: String::new(),
+            bp_cond: String::new(),
+            wp_addr: String::new(),
+            wp_access: Access::Write,
+            trace_start: String::new(),
+            trace_stop: String::new(),
+            trace_compress: true,
+            gdb_port: 2345,
+            error: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, state: DebugState) {
+        self.state = Some(state);
+    }
+
+    pub(crate) fn state(&self) -> Option<&DebugState> {
+        self.state.as_ref()
+    }
+
+    fn send(&self, cmd: DebugCommand) {
+        _ = self.tx.send(EmuCommand::Debug(cmd));
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context) {
+        if !self.open {
+            return;
+        }
+
+        self.hotkeys(ctx);
+
+        egui::SidePanel::right("debugger")
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.controls(ui);
+                    ui.separator();
+                    self.registers(ui);
+                    ui.separator();
+                    self.disassembly(ui);
+                    ui.separator();
+                    self.breakpoints(ui);
+                    ui.separator();
+                    self.watchpoints(ui);
+                    ui.separator();
+                    self.trace(ui);
+                    ui.separator();
+                    self.gdb(ui);
+                });
+            });
+    }
+
+    fn hotkeys(&self, ctx: &egui::Context) {
+        let paused = self.state.as_ref().is_some_and(|s| s.paused);
+
+        ctx.input(|i| {
+            if i.key_pressed(Key::F5) {
+                self.send(if paused {
+                    DebugCommand::Continue
+                } else {
+                    DebugCommand::Pause
+                });
+            }
+            if i.key_pressed(Key::F10) {
+                self.send(DebugCommand::StepOver);
+            }
+            if i.key_pressed(Key::F11) {
+                self.send(if i.modifiers.shift {
+                    DebugCommand::StepOut
+                } else {
+                    DebugCommand::StepInto
+                });
+            }
+        });
+    }
+
+    fn controls(&self, ui: &mut egui::Ui) {
+        let paused = self.state.as_ref().is_some_and(|s| s.paused);
+
+        ui.horizontal(|ui| {
+            if paused {
+                if ui.button("Continue").on_hover_text("F5").clicked() {
+                    self.send(DebugCommand::Continue);
+                }
+            } else if ui.button("Pause").on_hover_text("F5").clicked() {
+                self.send(DebugCommand::Pause);
+            }
+
+            if ui.button("Frame").clicked() {
+                self.send(DebugCommand::RunToFrame);
+            }
+            if ui.button("Scanline").clicked() {
+                self.send(DebugCommand::RunToLine);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Step into").on_hover_text("F11").clicked() {
+                self.send(DebugCommand::StepInto);
+            }
+            if ui.button("Step over").on_hover_text("F10").clicked() {
+                self.send(DebugCommand::StepOver);
+            }
+            if ui.button("Step out").on_hover_text("Shift+F11").clicked() {
+                self.send(DebugCommand::StepOut);
+            }
+        });
+
+        if let Some(state) = &self.state {
+            let status = match state.reason {
+                _ if !state.paused => "Running".to_string(),
+                None => "Paused".to_string(),
+                Some(reason) => describe(reason),
+            };
+
+            ui.label(status);
+            ui.label(format!("Frame {}  Cycle {}", state.frame, state.cycles));
+        }
+    }
+
+    fn registers(&self, ui: &mut egui::Ui) {
+        let Some(state) = &self.state else {
+            return;
+        };
+
+        egui::Grid::new("registers").show(ui, |ui| {
+            for (name, r) in [
+                ("AF", Reg::AF),
+                ("BC", Reg::BC),
+                ("DE", Reg::DE),
+                ("HL", Reg::HL),
+                ("SP", Reg::SP),
+                ("PC", Reg::PC),
+            ] {
+                let mut v = r.get(&state.reg);
+
+                ui.label(RichText::new(name).monospace());
+
+                if ui
+                    .add(DragValue::new(&mut v).hexadecimal(4, false, true))
+                    .changed()
+                {
+                    self.send(DebugCommand::SetRegister(r, v));
+                }
+
+                ui.end_row();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            for (name, flag) in [
+                ("Z", Flag::Z),
+                ("N", Flag::N),
+                ("H", Flag::H),
+                ("C", Flag::C),
+            ] {
+                let mut set = state.reg.f & flag as u8 != 0;
+
+                if ui.checkbox(&mut set, name).changed() {
+                    self.send(DebugCommand::SetFlag(flag, set));
+                }
+            }
+        });
+
+        ui.label(format!(
+            "IME {}  {}",
+            if state.ime { "on" } else { "off" },
+            if state.halted { "HALT" } else { "" }
+        ));
+    }
+
+    fn disassembly(&self, ui: &mut egui::Ui) {
+        let Some(state) = &self.state else {
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Disassembly ({} symbols)", state.symbols));
+
+            if ui.small_button("Load symbols").clicked() {
+                let file = rfd::FileDialog::new()
+                    .add_filter("RGBDS symbols", &["sym"])
+                    .pick_file();
+
+                if let Some(file) = file {
+                    self.send(DebugCommand::LoadSymbols(file));
+                }
+            }
+        });
+
+        for line in &state.disasm {
+            if let Some(label) = &line.label {
+                ui.monospace(format!("{label}:"));
+            }
+
+            let bp = state.breakpoints.iter().position(|bp| bp.addr == line.addr);
+            let marker = match (line.addr == state.reg.pc, bp.is_some()) {
+                (true, _) => ">",
+                (false, true) => "*",
+                (false, false) => " ",
+            };
+
+            let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{b:02X}")).collect();
+            let text = format!(
+                "{marker}{:04X}  {:<9}{}",
+                line.addr,
+                bytes.join(" "),
+                line.text
+            );
+
+            let text = if line.addr == state.reg.pc {
+                RichText::new(text).monospace().strong()
+            } else {
+                RichText::new(text).monospace()
+            };
+
+            // Clicking a line toggles a breakpoint on it.
+            if ui
+                .add(egui::Label::new(text).sense(egui::Sense::click()))
+                .clicked()
+            {
+                self.send(match bp {
+                    Some(i) => DebugCommand::RemoveBreakpoint(i),
+                    None => DebugCommand::AddBreakpoint(Breakpoint {
+                        addr: line.addr,
+                        condition: None,
+                    }),
+                });
+            }
+        }
+    }
+
+    fn breakpoints(&mut self, ui: &mut egui::Ui) {
+        ui.label("Breakpoints");
+
+        if let Some(state) = &self.state {
+            for (i, bp) in state.breakpoints.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("x").clicked() {
+                        self.send(DebugCommand::RemoveBreakpoint(i));
+                    }
+
+                    match &bp.condition {
+                        Some(c) => ui.monospace(format!("${:04X} if {}", bp.addr, c.source)),
+                        None => ui.monospace(format!("${:04X}", bp.addr)),
+                    };
+                });
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.bp_addr)
+                    .hint_text("$0150")
+                    .desired_width(50.0),
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.bp_cond)
+                    .hint_text("a == $10")
+                    .desired_width(100.0),
+            );
+
+            if ui.button("Add").clicked() {
+                self.add_breakpoint();
+            }
+        });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+
+    fn add_breakpoint(&mut self) {
+        let Some(addr) = parse_number(&self.bp_addr) else {
+            self.error = Some(format!("bad address `{}`", self.bp_addr));
+            return;
+        };
+
+        let condition = match self.bp_cond.trim() {
+            "" => None,
+            cond => match Condition::parse(cond) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    self.error = Some(e);
+                    return;
+                }
+            },
+        };
+
+        self.error = None;
+        self.bp_addr.clear();
+        self.bp_cond.clear();
+        self.send(DebugCommand::AddBreakpoint(Breakpoint { addr, condition }));
+    }
+
+    fn watchpoints(&mut self, ui: &mut egui::Ui) {
+        ui.label("Watchpoints");
+
+        if let Some(state) = &self.state {
+            for (i, wp) in state.watchpoints.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("x").clicked() {
+                        self.send(DebugCommand::RemoveWatchpoint(i));
+                    }
+
+                    ui.monospace(format!("${:04X}-${:04X} {:?}", wp.start, wp.end, wp.access));
+                });
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.wp_addr)
+                    .hint_text("$C000-$C0FF")
+                    .desired_width(100.0),
+            );
+
+            egui::ComboBox::from_id_source("wp_access")
+                .selected_text(format!("{:?}", self.wp_access))
+                .width(60.0)
+                .show_ui(ui, |ui| {
+                    for access in [Access::Read, Access::Write, Access::ReadWrite] {
+                        ui.selectable_value(&mut self.wp_access, access, format!("{access:?}"));
+                    }
+                });
+
+            if ui.button("Add").clicked() {
+                self.add_watchpoint();
+            }
+        });
+    }
+
+    fn add_watchpoint(&mut self) {
+        let range = match self.wp_addr.split_once('-') {
+            Some((start, end)) => parse_number(start).zip(parse_number(end)),
+            None => parse_number(&self.wp_addr).map(|a| (a, a)),
+        };
+
+        let Some((start, end)) = range.filter(|(start, end)| start <= end) else {
+            self.error = Some(format!("bad range `{}`", self.wp_addr));
+            return;
+        };
+
+        self.error = None;
+        self.wp_addr.clear();
+        self.send(DebugCommand::AddWatchpoint(Watchpoint {
+            start,
+            end,
+            access: self.wp_access,
+        }));
+    }
+
+    fn trace(&mut self, ui: &mut egui::Ui) {
+        let tracing = self.state.as_ref().is_some_and(|s| s.tracing);
+
+        ui.label("Trace");
+
+        if tracing {
+            if ui.button("Stop trace").clicked() {
+                self.send(DebugCommand::Trace(None));
+            }
+            return;
+        }
+
+        egui::Grid::new("trace").show(ui, |ui| {
+            ui.label("Start");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.trace_start)
+                    .hint_text("now, $0150-$01FF or frame 100")
+                    .desired_width(160.0),
+            );
+            ui.end_row();
+
+            ui.label("Stop");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.trace_stop)
+                    .hint_text("never")
+                    .desired_width(160.0),
+            );
+            ui.end_row();
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.trace_compress, "gzip");
+
+            if ui.button("Start trace").clicked() {
+                self.start_trace();
+            }
+        });
+    }
+
+    fn gdb(&mut self, ui: &mut egui::Ui) {
+        let listening = self.state.as_ref().and_then(|s| s.gdb);
+
+        ui.label("GDB server");
+
+        ui.horizontal(|ui| match listening {
+            Some(port) => {
+                ui.label(format!("Listening on 127.0.0.1:{port}"));
+
+                if ui.button("Stop").clicked() {
+                    _ = self.tx.send(EmuCommand::Gdb(None));
+                }
+            }
+            None => {
+                ui.label("Port");
+                ui.add(DragValue::new(&mut self.gdb_port));
+
+                if ui.button("Start").clicked() {
+                    _ = self.tx.send(EmuCommand::Gdb(Some(self.gdb_port)));
+                }
+            }
+        });
+    }
+
+    fn start_trace(&mut self) {
+        let trigger = |s: &str| match s.trim() {
+            "" => Ok(None),
+            s => Trigger::parse(s).map(Some),
+        };
+
+        let (start, stop) = match (trigger(&self.trace_start), trigger(&self.trace_stop)) {
+            (Ok(start), Ok(stop)) => (start, stop),
+            (Err(e), _) | (_, Err(e)) => {
+                self.error = Some(e);
+                return;
+            }
+        };
+
+        let name = if self.trace_compress {
+            "trace.log.gz"
+        } else {
+            "trace.log"
+        };
+
+        let Some(path) = rfd::FileDialog::new().set_file_name(name).save_file() else {
+            return;
+        };
+
+        self.error = None;
+        self.send(DebugCommand::Trace(Some(TraceConfig {
+            path,
+            start,
+            stop,
+            compress: self.trace_compress,
+        })));
+    }
+}
+
+fn describe(reason: BreakReason) -> String {
+    match reason {
+        BreakReason::Pause => "Paused".to_string(),
+        BreakReason::Step => "Stepped".to_string(),
+        BreakReason::Breakpoint(a) => format!("Breakpoint at ${a:04X}"),
+        BreakReason::Watchpoint(hit) => format!(
+            "{:?} of ${:02X} at ${:04X}",
+            hit.access, hit.value, hit.addr
+        ),
+        BreakReason::Frame(n) => format!("Frame {n}"),
+        BreakReason::Line(ly) => format!("Scanline {ly}"),
+    }
+}
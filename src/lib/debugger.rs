@@ -0,0 +1,624 @@
+use std::path::PathBuf;
+
+use crate::{
+    archive::RomFile,
+    cpu::{Cpu, Flag},
+    disasm::{self, Line, Symbols},
+    mmu::{Mmu, Space},
+    registers::Registers,
+    trace::{TraceConfig, Tracer},
+};
+
+/// Instructions shown before and after PC in the disassembly view.
+const DISASM_BEFORE: usize = 8;
+const DISASM_AFTER: usize = 16;
+
+/// Commands for the debugger, from the debugger panel and memory tools.
+pub(crate) enum DebugCommand {
+    Continue,
+    Pause,
+    StepInto,
+    StepOver,
+    StepOut,
+    RunToFrame,
+    RunToLine,
+    AddBreakpoint(Breakpoint),
+    RemoveBreakpoint(usize),
+    AddWatchpoint(Watchpoint),
+    RemoveWatchpoint(usize),
+    SetRegister(Reg, u16),
+    SetFlag(Flag, bool),
+    LoadSymbols(PathBuf),
+    Trace(Option<TraceConfig>),
+    Poke(Space, usize, u8),
+    Freeze(u16, u8),
+    Unfreeze(u16),
+}
+
+/// What the debugger panel gets to see of the machine.
+#[derive(Clone)]
+pub(crate) struct DebugState {
+    pub(crate) reg: Registers,
+    pub(crate) ime: bool,
+    pub(crate) halted: bool,
+    pub(crate) paused: bool,
+    pub(crate) reason: Option<BreakReason>,
+    pub(crate) breakpoints: Vec<Breakpoint>,
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    pub(crate) frame: u64,
+    pub(crate) cycles: u64,
+    pub(crate) disasm: Vec<Line>,
+    pub(crate) symbols: usize,
+    pub(crate) tracing: bool,
+    pub(crate) gdb: Option<u16>,
+    pub(crate) frozen: Vec<(u16, u8)>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum BreakReason {
+    Pause,
+    Step,
+    Breakpoint(u16),
+    Watchpoint(WatchHit),
+    Frame(u64),
+    /// Stopped at the start of this scanline.
+    Line(u8),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Breakpoint {
+    pub(crate) addr: u16,
+    pub(crate) condition: Option<Condition>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn matches(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+/// Breaks when the CPU touches `start..=end`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Watchpoint {
+    pub(crate) start: u16,
+    pub(crate) end: u16,
+    pub(crate) access: Access,
+}
+
+impl Watchpoint {
+    pub(crate) fn hit(&self, a: u16, access: Access) -> bool {
+        (self.start..=self.end).contains(&a) && self.access.matches(access)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct WatchHit {
+    pub(crate) addr: u16,
+    pub(crate) value: u8,
+    pub(crate) access: Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Reg {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Reg {
+    fn from_name(name: &str) -> Option<Reg> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "a" => Reg::A,
+            "f" => Reg::F,
+            "b" => Reg::B,
+            "c" => Reg::C,
+            "d" => Reg::D,
+            "e" => Reg::E,
+            "h" => Reg::H,
+            "l" => Reg::L,
+            "af" => Reg::AF,
+            "bc" => Reg::BC,
+            "de" => Reg::DE,
+            "hl" => Reg::HL,
+            "sp" => Reg::SP,
+            "pc" => Reg::PC,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn get(self, reg: &Registers) -> u16 {
+        match self {
+            Reg::A => reg.a as u16,
+            Reg::F => reg.f as u16,
+            Reg::B => reg.b as u16,
+            Reg::C => reg.c as u16,
+            Reg::D => reg.d as u16,
+            Reg::E => reg.e as u16,
+            Reg::H => reg.h as u16,
+            Reg::L => reg.l as u16,
+            Reg::AF => reg.af(),
+            Reg::BC => reg.bc(),
+            Reg::DE => reg.de(),
+            Reg::HL => reg.hl(),
+            Reg::SP => reg.sp,
+            Reg::PC => reg.pc,
+        }
+    }
+
+    pub(crate) fn set(self, reg: &mut Registers, v: u16) {
+        match self {
+            Reg::A => reg.a = v as u8,
+            Reg::F => reg.f = v as u8 & 0xF0,
+            Reg::B => reg.b = v as u8,
+            Reg::C => reg.c = v as u8,
+            Reg::D => reg.d = v as u8,
+            Reg::E => reg.e = v as u8,
+            Reg::H => reg.h = v as u8,
+            Reg::L => reg.l = v as u8,
+            Reg::AF => reg.setaf(v),
+            Reg::BC => reg.setbc(v),
+            Reg::DE => reg.setde(v),
+            Reg::HL => reg.sethl(v),
+            Reg::SP => reg.sp = v,
+            Reg::PC => reg.pc = v,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Reg(Reg),
+    Const(u16),
+    Mem(Box<Operand>),
+}
+
+impl Operand {
+    fn eval(&self, reg: &Registers, mmu: &Mmu) -> u16 {
+        match self {
+            Operand::Reg(r) => r.get(reg),
+            Operand::Const(v) => *v,
+            Operand::Mem(a) => mmu.peek(a.eval(reg, mmu)) as u16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A breakpoint condition such as `a == $10 && [hl] != 0`.
+///
+/// Operands are registers, numbers (`$ff`, `0xff` or `255`) and memory
+/// reads written as `[operand]`.
+#[derive(Clone, Debug)]
+pub(crate) struct Condition {
+    pub(crate) source: String,
+    terms: Vec<(Operand, Cmp, Operand)>,
+}
+
+impl Condition {
+    pub(crate) fn parse(source: &str) -> Result<Condition, String> {
+        let terms = source
+            .split("&&")
+            .map(parse_comparison)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Condition {
+            source: source.trim().to_string(),
+            terms,
+        })
+    }
+
+    pub(crate) fn eval(&self, reg: &Registers, mmu: &Mmu) -> bool {
+        self.terms.iter().all(|(lhs, cmp, rhs)| {
+            let (l, r) = (lhs.eval(reg, mmu), rhs.eval(reg, mmu));
+
+            match cmp {
+                Cmp::Eq => l == r,
+                Cmp::Ne => l != r,
+                Cmp::Lt => l < r,
+                Cmp::Le => l <= r,
+                Cmp::Gt => l > r,
+                Cmp::Ge => l >= r,
+            }
+        })
+    }
+}
+
+fn parse_comparison(s: &str) -> Result<(Operand, Cmp, Operand), String> {
+    // Two character operators first so `<=` isn't read as `<`.
+    const OPS: [(&str, Cmp); 6] = [
+        ("==", Cmp::Eq),
+        ("!=", Cmp::Ne),
+        ("<=", Cmp::Le),
+        (">=", Cmp::Ge),
+        ("<", Cmp::Lt),
+        (">", Cmp::Gt),
+    ];
+
+    for (tok, cmp) in OPS {
+        if let Some((lhs, rhs)) = s.split_once(tok) {
+            return Ok((parse_operand(lhs)?, cmp, parse_operand(rhs)?));
+        }
+    }
+
+    Err(format!("expected a comparison in `{}`", s.trim()))
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    let s = s.trim();
+
+    if let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return Ok(Operand::Mem(Box::new(parse_operand(inner)?)));
+    }
+
+    if let Some(r) = Reg::from_name(s) {
+        return Ok(Operand::Reg(r));
+    }
+
+    parse_number(s)
+        .map(Operand::Const)
+        .ok_or_else(|| format!("unknown operand `{s}`"))
+}
+
+/// Parses `$ff`, `0xff` and `255`.
+pub(crate) fn parse_number(s: &str) -> Option<u16> {
+    let s = s.trim();
+
+    if let Some(hex) = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// How far the CPU is allowed to run before the debugger takes over again.
+#[derive(Clone, Copy)]
+enum Run {
+    Free,
+    Step,
+    Until { pc: u16, sp: u16 },
+    Out { sp: u16 },
+    Frame(u64),
+    Line { frame: u64, ly: u8 },
+}
+
+pub(crate) struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    symbols: Symbols,
+    run: Run,
+    reason: Option<BreakReason>,
+    // Don't stop on the breakpoint we're resuming from.
+    resumed: bool,
+}
+
+impl Debugger {
+    pub(crate) fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            symbols: Symbols::new(),
+            run: Run::Free,
+            reason: None,
+            resumed: false,
+        }
+    }
+
+    /// Applies `cmd`, returning whether the emulator should be paused.
+    pub(crate) fn command(&mut self, cmd: DebugCommand, cpu: &mut Cpu, paused: bool) -> bool {
+        let pc = cpu.reg.pc;
+        let sp = cpu.reg.sp;
+
+        let run = match cmd {
+            DebugCommand::Continue => Run::Free,
+            DebugCommand::Pause => {
+                self.reason = Some(BreakReason::Pause);
+                return true;
+            }
+            DebugCommand::StepInto => Run::Step,
+            DebugCommand::StepOver => match cpu.mmu.peek(pc) {
+                // CALL, CALL cc
+                0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => Run::Until {
+                    pc: pc.wrapping_add(3),
+                    sp,
+                },
+                // RST
+                op if op & 0b1100_0111 == 0b1100_0111 => Run::Until {
+                    pc: pc.wrapping_add(1),
+                    sp,
+                },
+                _ => Run::Step,
+            },
+            DebugCommand::StepOut => Run::Out { sp },
+            DebugCommand::RunToFrame => Run::Frame(cpu.mmu.ppu.frames),
+            DebugCommand::RunToLine => Run::Line {
+                frame: cpu.mmu.ppu.frames,
+                ly: cpu.mmu.ppu.ly(),
+            },
+            DebugCommand::AddBreakpoint(bp) => {
+                self.breakpoints.push(bp);
+                return paused;
+            }
+            DebugCommand::RemoveBreakpoint(i) => {
+                if i < self.breakpoints.len() {
+                    self.breakpoints.remove(i);
+                }
+                return paused;
+            }
+            DebugCommand::AddWatchpoint(wp) => {
+                cpu.mmu.watchpoints.push(wp);
+                return paused;
+            }
+            DebugCommand::RemoveWatchpoint(i) => {
+                if i < cpu.mmu.watchpoints.len() {
+                    cpu.mmu.watchpoints.remove(i);
+                }
+                return paused;
+            }
+            DebugCommand::SetRegister(r, v) => {
+                r.set(&mut cpu.reg, v);
+                return paused;
+            }
+            DebugCommand::SetFlag(flag, set) => {
+                cpu.reg.flag(flag, set);
+                return paused;
+            }
+            DebugCommand::Trace(config) => {
+                cpu.stop_trace();
+                cpu.tracer = config.and_then(|config| {
+                    Tracer::create(&config)
+                        .inspect_err(|e| {
+                            eprintln!("Couldn't trace to {}: {e}", config.path.display())
+                        })
+                        .ok()
+                });
+                return paused;
+            }
+            DebugCommand::Poke(space, offset, v) => {
+                cpu.mmu.poke_space(space, offset, v);
+                return paused;
+            }
+            DebugCommand::Freeze(addr, v) => {
+                cpu.mmu.frozen.retain(|&(a, _)| a != addr);
+                cpu.mmu.frozen.push((addr, v));
+                return paused;
+            }
+            DebugCommand::Unfreeze(addr) => {
+                cpu.mmu.frozen.retain(|&(a, _)| a != addr);
+                return paused;
+            }
+            DebugCommand::LoadSymbols(path) => {
+                match Symbols::load(&path) {
+                    Ok(symbols) => self.symbols = symbols,
+                    Err(e) => eprintln!("Couldn't load symbols from {}: {e}", path.display()),
+                }
+                return paused;
+            }
+        };
+
+        self.run = run;
+        self.reason = None;
+        self.resumed = true;
+        false
+    }
+
+    pub(crate) fn reason(&self) -> Option<BreakReason> {
+        self.reason
+    }
+
+    pub(crate) fn add_breakpoint(&mut self, bp: Breakpoint) {
+        self.breakpoints.push(bp);
+    }
+
+    /// Removes the unconditional breakpoints at `addr`.
+    pub(crate) fn remove_breakpoint_at(&mut self, addr: u16) {
+        self.breakpoints
+            .retain(|bp| bp.addr != addr || bp.condition.is_some());
+    }
+
+    /// Picks up `game.sym` next to `game.gb`, as RGBDS leaves it.
+    pub(crate) fn rom_loaded(&mut self, rom: &RomFile) {
+        self.symbols = Symbols::load(&rom.sibling("sym")).unwrap_or_default();
+    }
+
+    /// Checked before every `Cpu::step`. Returns true if execution should stop.
+    pub(crate) fn before_step(&mut self, cpu: &Cpu) -> bool {
+        if std::mem::take(&mut self.resumed) {
+            return false;
+        }
+
+        let pc = cpu.reg.pc;
+
+        if let Run::Until { pc: target, sp } = self.run {
+            if pc == target && cpu.reg.sp >= sp {
+                return self.stop(BreakReason::Step);
+            }
+        }
+
+        // A halted CPU steps one M-cycle at a time without moving PC, so
+        // the breakpoint it halted on would be hit over and over.
+        let hit = !cpu.halted
+            && self.breakpoints.iter().any(|bp| {
+                bp.addr == pc
+                    && bp
+                        .condition
+                        .as_ref()
+                        .is_none_or(|c| c.eval(&cpu.reg, &cpu.mmu))
+            });
+
+        if hit {
+            return self.stop(BreakReason::Breakpoint(pc));
+        }
+
+        false
+    }
+
+    /// Checked after every `Cpu::step`. `opcode` is what was at PC beforehand.
+    pub(crate) fn after_step(&mut self, cpu: &mut Cpu, opcode: u8) -> bool {
+        if let Some(hit) = cpu.mmu.watch_hit.take() {
+            return self.stop(BreakReason::Watchpoint(hit));
+        }
+
+        match self.run {
+            Run::Free | Run::Until { .. } => false,
+            Run::Step => self.stop(BreakReason::Step),
+            Run::Out { sp } => {
+                // RET, RETI, RET cc
+                let ret = matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8);
+
+                if ret && cpu.reg.sp > sp {
+                    self.stop(BreakReason::Step)
+                } else {
+                    false
+                }
+            }
+            Run::Frame(frame) => {
+                if cpu.mmu.ppu.frames > frame {
+                    self.stop(BreakReason::Frame(cpu.mmu.ppu.frames))
+                } else {
+                    false
+                }
+            }
+            Run::Line { frame, ly } => {
+                let ppu = &cpu.mmu.ppu;
+
+                if ppu.frames != frame || ppu.ly() != ly {
+                    self.stop(BreakReason::Line(ppu.ly()))
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Whether `before_step`/`after_step` have anything to look at.
+    pub(crate) fn active(&self, cpu: &Cpu) -> bool {
+        !matches!(self.run, Run::Free)
+            || self.resumed
+            || !self.breakpoints.is_empty()
+            || !cpu.mmu.watchpoints.is_empty()
+    }
+
+    fn stop(&mut self, reason: BreakReason) -> bool {
+        self.run = Run::Free;
+        self.reason = Some(reason);
+        true
+    }
+
+    pub(crate) fn state(&self, cpu: &Cpu, paused: bool) -> DebugState {
+        DebugState {
+            reg: cpu.reg.clone(),
+            ime: cpu.ime,
+            halted: cpu.halted,
+            paused,
+            reason: self.reason,
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: cpu.mmu.watchpoints.clone(),
+            frame: cpu.mmu.ppu.frames,
+            cycles: cpu.cycles,
+            disasm: disasm::around(
+                &cpu.mmu,
+                &self.symbols,
+                cpu.reg.pc,
+                DISASM_BEFORE,
+                DISASM_AFTER,
+            ),
+            symbols: self.symbols.len(),
+            tracing: cpu.tracer.is_some(),
+            gdb: None,
+            frozen: cpu.mmu.frozen.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, reg: &Registers, mmu: &Mmu) -> bool {
+        Condition::parse(source).unwrap().eval(reg, mmu)
+    }
+
+    #[test]
+    fn conditions() {
+        let mut cpu = Cpu::new();
+        cpu.reg.a = 0x10;
+        cpu.reg.sethl(0xC000);
+        cpu.mmu.wb(0xC000, 0x42);
+
+        assert!(eval("a == $10", &cpu.reg, &cpu.mmu));
+        assert!(eval("a != 0x11", &cpu.reg, &cpu.mmu));
+        assert!(eval("a <= 16 && a >= 16", &cpu.reg, &cpu.mmu));
+        assert!(eval("[hl] == $42 && hl > $BFFF", &cpu.reg, &cpu.mmu));
+        assert!(eval("[[hl]] == [$42]", &cpu.reg, &cpu.mmu));
+        assert!(!eval("a < $10", &cpu.reg, &cpu.mmu));
+        assert!(!eval("a == $10 && [hl] == 0", &cpu.reg, &cpu.mmu));
+
+        assert_eq!(Condition::parse("  a == 1 ").unwrap().source, "a == 1");
+    }
+
+    #[test]
+    fn malformed_conditions() {
+        for source in [
+            "",
+            "a",
+            "a = 1",
+            "q == 1",
+            "a == $xyz",
+            "[hl == 1",
+            "a == 1 &&",
+        ] {
+            assert!(Condition::parse(source).is_err(), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("$ff"), Some(0xFF));
+        assert_eq!(parse_number("0xC000"), Some(0xC000));
+        assert_eq!(parse_number(" 255 "), Some(255));
+        assert_eq!(parse_number("$10000"), None);
+        assert_eq!(parse_number("ff"), None);
+    }
+
+    #[test]
+    fn halted_cpu_skips_breakpoints() {
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0xC000;
+
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.push(Breakpoint {
+            addr: 0xC000,
+            condition: None,
+        });
+
+        cpu.halted = true;
+        assert!(!debugger.before_step(&cpu));
+
+        cpu.halted = false;
+        assert!(debugger.before_step(&cpu));
+    }
+}
@@ -0,0 +1,301 @@
+use std::{collections::HashMap, io, path::Path};
+
+use bitmatch::bitmatch;
+
+use crate::mmu::Mmu;
+
+const R8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const R16_G1: [&str; 4] = ["bc", "de", "hl", "sp"];
+const R16_G2: [&str; 4] = ["[bc]", "[de]", "[hl+]", "[hl-]"];
+const R16_G3: [&str; 4] = ["bc", "de", "hl", "af"];
+const COND: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ACC: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+const SHIFT: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+/// Labels from an RGBDS `.sym` file, keyed by bank and address.
+#[derive(Default)]
+pub(crate) struct Symbols {
+    labels: HashMap<(u16, u16), String>,
+    by_addr: HashMap<u16, Vec<u16>>,
+}
+
+impl Symbols {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Parses `BB:AAAA Label` lines, skipping `;` comments and anything malformed.
+    pub(crate) fn parse(text: &str) -> Self {
+        let mut symbols = Self::new();
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+
+            let Some((loc, name)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+
+            let Some((bank, addr)) = loc.split_once(':') else {
+                continue;
+            };
+
+            let (Ok(bank), Ok(addr)) =
+                (u16::from_str_radix(bank, 16), u16::from_str_radix(addr, 16))
+            else {
+                continue;
+            };
+
+            symbols.insert(bank, addr, name.trim());
+        }
+
+        symbols
+    }
+
+    fn insert(&mut self, bank: u16, addr: u16, name: &str) {
+        if self.labels.contains_key(&(bank, addr)) {
+            return;
+        }
+
+        self.labels.insert((bank, addr), name.to_string());
+        self.by_addr.entry(addr).or_default().push(bank);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// The label at `addr` as currently mapped. Outside switchable ROM, a
+    /// label in any bank will do, since `.sym` files disagree on those.
+    pub(crate) fn get(&self, bank: u16, addr: u16) -> Option<&str> {
+        if let Some(name) = self.labels.get(&(bank, addr)) {
+            return Some(name);
+        }
+
+        if (0x4000..0x8000).contains(&addr) {
+            return None;
+        }
+
+        let bank = *self.by_addr.get(&addr)?.first()?;
+        self.labels.get(&(bank, addr)).map(String::as_str)
+    }
+}
+
+/// One decoded instruction.
+#[derive(Clone, Debug)]
+pub(crate) struct Line {
+    pub(crate) addr: u16,
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) label: Option<String>,
+    pub(crate) text: String,
+}
+
+/// Decodes the instruction starting with `bytes[0]` at `addr`, returning its
+/// text and length. `name` renders addresses, so symbols can be substituted.
+#[rustfmt::skip]
+#[bitmatch]
+pub(crate) fn decode(bytes: [u8; 3], addr: u16, name: &dyn Fn(u16) -> String) -> (String, u16) {
+    let [op, n8, hi] = bytes;
+    let n16 = u16::from_le_bytes([n8, hi]);
+    let rel = addr.wrapping_add(2).wrapping_add(n8 as i8 as u16);
+    let e8 = if (n8 as i8) < 0 { format!("-${:02X}", (n8 as i8).unsigned_abs()) } else { format!("${n8:02X}") };
+
+    #[bitmatch]
+    match op {
+        "00000000" => ("nop".into(), 1),
+        "00001000" => (format!("ld [{}], sp", name(n16)), 3),
+        "00010000" => ("stop".into(), 1),
+        "00011000" => (format!("jr {}", name(rel)), 2),
+        "001cc000" => (format!("jr {}, {}", COND[c as usize], name(rel)), 2),
+        "00rr0001" => (format!("ld {}, {}", R16_G1[r as usize], name(n16)), 3),
+        "00rr1001" => (format!("add hl, {}", R16_G1[r as usize]), 1),
+        "00rr0010" => (format!("ld {}, a", R16_G2[r as usize]), 1),
+        "00rr1010" => (format!("ld a, {}", R16_G2[r as usize]), 1),
+        "00rr0011" => (format!("inc {}", R16_G1[r as usize]), 1),
+        "00rr1011" => (format!("dec {}", R16_G1[r as usize]), 1),
+        "00rrr100" => (format!("inc {}", R8[r as usize]), 1),
+        "00rrr101" => (format!("dec {}", R8[r as usize]), 1),
+        "00rrr110" => (format!("ld {}, ${n8:02X}", R8[r as usize]), 2),
+        "00iii111" => (ACC[i as usize].into(), 1),
+        "01110110" => ("halt".into(), 1),
+        "01dddsss" => (format!("ld {}, {}", R8[d as usize], R8[s as usize]), 1),
+        "10iiirrr" => (format!("{} a, {}", ALU[i as usize], R8[r as usize]), 1),
+        "110cc000" => (format!("ret {}", COND[c as usize]), 1),
+        "11100000" => (format!("ldh [{}], a", name(0xFF00 | n8 as u16)), 2),
+        "11101000" => (format!("add sp, {e8}"), 2),
+        "11110000" => (format!("ldh a, [{}]", name(0xFF00 | n8 as u16)), 2),
+        "11111000" => (format!("ld hl, sp{}{e8}", if (n8 as i8) < 0 { "" } else { "+" }), 2),
+        "11rr0001" => (format!("pop {}", R16_G3[r as usize]), 1),
+        "11ii1001" => (["ret", "reti", "jp hl", "ld sp, hl"][i as usize].into(), 1),
+        "110cc010" => (format!("jp {}, {}", COND[c as usize], name(n16)), 3),
+        "11100010" => ("ldh [c], a".into(), 1),
+        "11101010" => (format!("ld [{}], a", name(n16)), 3),
+        "11110010" => ("ldh a, [c]".into(), 1),
+        "11111010" => (format!("ld a, [{}]", name(n16)), 3),
+        "11iii011" => match i {
+            0 => (format!("jp {}", name(n16)), 3),
+            1 => (decode_cb(n8), 2),
+            6 => ("di".into(), 1),
+            7 => ("ei".into(), 1),
+            _ => (format!("db ${op:02X}"), 1),
+        },
+        "110cc100" => (format!("call {}, {}", COND[c as usize], name(n16)), 3),
+        "11rr0101" => (format!("push {}", R16_G3[r as usize]), 1),
+        "11001101" => (format!("call {}", name(n16)), 3),
+        "11iii110" => (format!("{} a, ${n8:02X}", ALU[i as usize]), 2),
+        "11eee111" => (format!("rst ${:02X}", e << 3), 1),
+        // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB-0xED, 0xF4, 0xFC, 0xFD
+        _ => (format!("db ${op:02X}"), 1),
+    }
+}
+
+#[bitmatch]
+fn decode_cb(op: u8) -> String {
+    #[bitmatch]
+    match op {
+        "00iiirrr" => format!("{} {}", SHIFT[i as usize], R8[r as usize]),
+        "01bbbrrr" => format!("bit {b}, {}", R8[r as usize]),
+        "10bbbrrr" => format!("res {b}, {}", R8[r as usize]),
+        "11bbbrrr" => format!("set {b}, {}", R8[r as usize]),
+    }
+}
+
+/// Decodes the instruction at `addr` as the CPU currently sees memory.
+pub(crate) fn line(mmu: &Mmu, symbols: &Symbols, addr: u16) -> Line {
+    let bytes = [0, 1, 2].map(|i| mmu.peek(addr.wrapping_add(i)));
+
+    let name = |a: u16| match symbols.get(mmu.bank_of(a), a) {
+        Some(label) => label.to_string(),
+        None => format!("${a:04X}"),
+    };
+
+    let (text, len) = decode(bytes, addr, &name);
+
+    Line {
+        addr,
+        bytes: bytes[..len as usize].to_vec(),
+        label: symbols.get(mmu.bank_of(addr), addr).map(str::to_string),
+        text,
+    }
+}
+
+/// `before` instructions leading up to `pc`, then `after` starting at it.
+///
+/// Code can't be decoded backwards, so this looks for the furthest start
+/// address whose instruction stream lands exactly on `pc`.
+pub(crate) fn around(
+    mmu: &Mmu,
+    symbols: &Symbols,
+    pc: u16,
+    before: usize,
+    after: usize,
+) -> Vec<Line> {
+    let mut lines = Vec::with_capacity(before + after);
+
+    for back in (1..=before as u16 * 3).rev() {
+        let mut addr = pc.wrapping_sub(back);
+        let mut run = Vec::new();
+
+        while addr != pc && pc.wrapping_sub(addr) <= back {
+            let line = line(mmu, symbols, addr);
+            addr = addr.wrapping_add(line.bytes.len() as u16);
+            run.push(line);
+        }
+
+        if addr == pc {
+            let skip = run.len().saturating_sub(before);
+            lines.extend(run.into_iter().skip(skip));
+            break;
+        }
+    }
+
+    let mut addr = pc;
+
+    for _ in 0..after {
+        let line = line(mmu, symbols, addr);
+        addr = addr.wrapping_add(line.bytes.len() as u16);
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: [u8; 3], addr: u16) -> (String, u16) {
+        decode(bytes, addr, &|a| format!("${a:04X}"))
+    }
+
+    #[test]
+    fn instructions() {
+        let cases: [([u8; 3], &str, u16); 16] = [
+            ([0x00, 0, 0], "nop", 1),
+            ([0x01, 0x34, 0x12], "ld bc, $1234", 3),
+            ([0x08, 0x00, 0xC0], "ld [$C000], sp", 3),
+            ([0x18, 0xFE, 0], "jr $0100", 2),
+            ([0x20, 0x10, 0], "jr nz, $0112", 2),
+            ([0x2A, 0, 0], "ld a, [hl+]", 1),
+            ([0x36, 0x80, 0], "ld [hl], $80", 2),
+            ([0x76, 0, 0], "halt", 1),
+            ([0x7E, 0, 0], "ld a, [hl]", 1),
+            ([0xAF, 0, 0], "xor a, a", 1),
+            ([0xCD, 0x50, 0x01], "call $0150", 3),
+            ([0xE0, 0x40, 0], "ldh [$FF40], a", 2),
+            ([0xE8, 0xF0, 0], "add sp, -$10", 2),
+            ([0xF8, 0x05, 0], "ld hl, sp+$05", 2),
+            ([0xFF, 0, 0], "rst $38", 1),
+            ([0xD3, 0, 0], "db $D3", 1),
+        ];
+
+        for (bytes, expected, len) in cases {
+            assert_eq!(text(bytes, 0x0100), (expected.to_string(), len));
+        }
+    }
+
+    #[test]
+    fn cb_instructions() {
+        assert_eq!(text([0xCB, 0x37, 0], 0), ("swap a".to_string(), 2));
+        assert_eq!(text([0xCB, 0x7E, 0], 0), ("bit 7, [hl]".to_string(), 2));
+        assert_eq!(text([0xCB, 0x80, 0], 0), ("res 0, b".to_string(), 2));
+        assert_eq!(text([0xCB, 0xFF, 0], 0), ("set 7, a".to_string(), 2));
+    }
+
+    #[test]
+    fn names() {
+        let name = |a: u16| match a {
+            0x0150 => "Main".to_string(),
+            _ => format!("${a:04X}"),
+        };
+        assert_eq!(decode([0xC3, 0x50, 0x01], 0, &name).0, "jp Main");
+    }
+
+    #[test]
+    fn symbols() {
+        let symbols = Symbols::parse(
+            "; File generated by rgblink\n\
+             00:0150 Main\n\
+             00:0150 Duplicate\n\
+             01:4000 BankOne ; comment\n\
+             02:4000 BankTwo\n\
+             00:C000 wBuffer\n\
+             zz:0000 Bad\n\
+             00:0200\n\
+             0150 NoBank\n",
+        );
+
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.get(0, 0x0150), Some("Main"));
+        assert_eq!(symbols.get(1, 0x4000), Some("BankOne"));
+        assert_eq!(symbols.get(2, 0x4000), Some("BankTwo"));
+        // Switchable ROM needs the right bank, anywhere else doesn't.
+        assert_eq!(symbols.get(3, 0x4000), None);
+        assert_eq!(symbols.get(1, 0xC000), Some("wBuffer"));
+        assert_eq!(symbols.get(0, 0x0200), None);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{fmt, io};
+
+/// Why a ROM, or a file made for one, couldn't be loaded.
+#[derive(Debug)]
+pub(crate) enum Error {
+    Io(io::Error),
+    /// Too short to hold a cartridge header.
+    Truncated(usize),
+    /// The cartridge type byte at $0147.
+    UnsupportedMapper(u8),
+    /// A ROM size byte at $0148 that doesn't name a size.
+    BadRomSize(u8),
+    /// A RAM size byte at $0149 that doesn't name a size.
+    BadRamSize(u8),
+    /// A save state or movie that's damaged or from a newer version.
+    Corrupt,
+    /// Made with a ROM that has this CRC32.
+    WrongRom(u32),
+    /// A movie that starts from a save state, which BizHawk can't share.
+    StartsFromState,
+    /// An IPS, UPS or BPS patch that can't be applied, and why.
+    Patch(String),
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Truncated(len) => write!(
+                f,
+                "the ROM is only {len} bytes, too short for a cartridge header"
+            ),
+            Error::UnsupportedMapper(kind) => {
+                write!(
+                    f,
+                    "cartridge type ${kind:02X} isn't supported, only MBC1 is"
+                )
+            }
+            Error::BadRomSize(code) => write!(f, "the header's ROM size ${code:02X} is invalid"),
+            Error::BadRamSize(code) => write!(f, "the header's RAM size ${code:02X} is invalid"),
+            Error::Corrupt => write!(f, "the file is damaged or from a newer version"),
+            Error::WrongRom(crc) => write!(f, "it was made with a different ROM (CRC32 {crc:08X})"),
+            Error::StartsFromState => write!(
+                f,
+                "only movies that start from power-on can be converted to or from .bk2"
+            ),
+            Error::Patch(why) => write!(f, "the patch couldn't be applied: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Self {
+        Error::Io(e.into())
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    input::{Action, Button, Hotkey},
+    joypad::Buttons,
+};
+
+/// Controller buttons, named by where they are rather than what's printed
+/// on them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PadButton {
+    South,
+    East,
+    North,
+    West,
+    Select,
+    Start,
+    Mode,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+}
+
+impl PadButton {
+    pub(crate) const ALL: [PadButton; 15] = [
+        PadButton::South,
+        PadButton::East,
+        PadButton::North,
+        PadButton::West,
+        PadButton::Select,
+        PadButton::Start,
+        PadButton::Mode,
+        PadButton::LeftBumper,
+        PadButton::RightBumper,
+        PadButton::LeftTrigger,
+        PadButton::RightTrigger,
+        PadButton::DpadUp,
+        PadButton::DpadDown,
+        PadButton::DpadLeft,
+        PadButton::DpadRight,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            PadButton::South => "South",
+            PadButton::East => "East",
+            PadButton::North => "North",
+            PadButton::West => "West",
+            PadButton::Select => "Select",
+            PadButton::Start => "Start",
+            PadButton::Mode => "Mode",
+            PadButton::LeftBumper => "L1",
+            PadButton::RightBumper => "R1",
+            PadButton::LeftTrigger => "L2",
+            PadButton::RightTrigger => "R2",
+            PadButton::DpadUp => "D-pad up",
+            PadButton::DpadDown => "D-pad down",
+            PadButton::DpadLeft => "D-pad left",
+            PadButton::DpadRight => "D-pad right",
+        }
+    }
+}
+
+// Only real controllers have sticks and come and go.
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Axis {
+    /// The left stick, right positive.
+    X,
+    /// The left stick, up positive.
+    Y,
+}
+
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PadEvent {
+    Connected(String),
+    Disconnected(String),
+    Button(PadButton, bool),
+    /// From -1.0 to 1.0.
+    Axis(Axis, f32),
+}
+
+/// Where pad events come from.
+pub(crate) trait Backend {
+    /// The next event, if there is one. Never blocks.
+    fn poll(&mut self) -> Option<PadEvent>;
+}
+
+/// Real controllers, hot-plugged as they come and go.
+#[cfg(feature = "gamepad")]
+pub(crate) struct Gilrs {
+    gilrs: gilrs::Gilrs,
+    // Pads that were plugged in before we started.
+    present: Vec<String>,
+}
+
+#[cfg(feature = "gamepad")]
+impl Gilrs {
+    pub(crate) fn new() -> Option<Self> {
+        let gilrs = gilrs::Gilrs::new()
+            .inspect_err(|e| eprintln!("Couldn't start gamepad support: {e}"))
+            .ok()?;
+
+        let present = gilrs
+            .gamepads()
+            .map(|(_, g)| g.name().to_string())
+            .collect();
+
+        Some(Self { gilrs, present })
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl Backend for Gilrs {
+    fn poll(&mut self) -> Option<PadEvent> {
+        use gilrs::{Axis as GAxis, Button as G, EventType};
+
+        if let Some(name) = self.present.pop() {
+            return Some(PadEvent::Connected(name));
+        }
+
+        let button = |b| {
+            Some(match b {
+                G::South => PadButton::South,
+                G::East => PadButton::East,
+                G::North => PadButton::North,
+                G::West => PadButton::West,
+                G::Select => PadButton::Select,
+                G::Start => PadButton::Start,
+                G::Mode => PadButton::Mode,
+                G::LeftTrigger => PadButton::LeftBumper,
+                G::RightTrigger => PadButton::RightBumper,
+                G::LeftTrigger2 => PadButton::LeftTrigger,
+                G::RightTrigger2 => PadButton::RightTrigger,
+                G::DPadUp => PadButton::DpadUp,
+                G::DPadDown => PadButton::DpadDown,
+                G::DPadLeft => PadButton::DpadLeft,
+                G::DPadRight => PadButton::DpadRight,
+                _ => return None,
+            })
+        };
+
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let name = || self.gilrs.gamepad(id).name().to_string();
+
+            let event = match event {
+                EventType::Connected => PadEvent::Connected(name()),
+                EventType::Disconnected => PadEvent::Disconnected(name()),
+                EventType::ButtonPressed(b, _) => match button(b) {
+                    Some(b) => PadEvent::Button(b, true),
+                    None => continue,
+                },
+                EventType::ButtonReleased(b, _) => match button(b) {
+                    Some(b) => PadEvent::Button(b, false),
+                    None => continue,
+                },
+                EventType::AxisChanged(GAxis::LeftStickX, v, _) => PadEvent::Axis(Axis::X, v),
+                EventType::AxisChanged(GAxis::LeftStickY, v, _) => PadEvent::Axis(Axis::Y, v),
+                _ => continue,
+            };
+
+            return Some(event);
+        }
+
+        None
+    }
+}
+
+/// A pad driven by whoever holds the sender, like the on-screen gamepad.
+pub(crate) struct VirtualGamepad {
+    rx: Receiver<PadEvent>,
+}
+
+impl VirtualGamepad {
+    pub(crate) fn new() -> (Self, Sender<PadEvent>) {
+        let (tx, rx) = mpsc::channel();
+        (Self { rx }, tx)
+    }
+}
+
+impl Backend for VirtualGamepad {
+    fn poll(&mut self) -> Option<PadEvent> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// The `[gamepad]` table in `input.toml`.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct PadMapping {
+    #[serde(default = "default_map")]
+    pub(crate) map: BTreeMap<PadButton, Action>,
+    /// How far, out of 1.0, the stick has to move to press a direction.
+    #[serde(default = "default_deadzone")]
+    pub(crate) deadzone: f32,
+}
+
+impl Default for PadMapping {
+    fn default() -> Self {
+        Self {
+            map: default_map(),
+            deadzone: default_deadzone(),
+        }
+    }
+}
+
+/// A and B go where they are on a Game Boy, right and below.
+fn default_map() -> BTreeMap<PadButton, Action> {
+    BTreeMap::from([
+        (PadButton::East, Action::Button(Button::A)),
+        (PadButton::South, Action::Button(Button::B)),
+        (PadButton::Select, Action::Button(Button::Select)),
+        (PadButton::Start, Action::Button(Button::Start)),
+        (PadButton::DpadUp, Action::Button(Button::Up)),
+        (PadButton::DpadDown, Action::Button(Button::Down)),
+        (PadButton::DpadLeft, Action::Button(Button::Left)),
+        (PadButton::DpadRight, Action::Button(Button::Right)),
+        (PadButton::Mode, Action::Hotkey(Hotkey::Pause)),
+        (PadButton::RightBumper, Action::Hotkey(Hotkey::FrameAdvance)),
+    ])
+}
+
+fn default_deadzone() -> f32 {
+    0.5
+}
+
+/// Every connected pad, merged into one.
+pub(crate) struct Gamepads {
+    backends: Vec<Box<dyn Backend>>,
+    pub(crate) connected: Vec<String>,
+    held: BTreeSet<PadButton>,
+    stick: [f32; 2],
+}
+
+impl Gamepads {
+    pub(crate) fn new() -> Self {
+        #[allow(unused_mut)]
+        let mut backends: Vec<Box<dyn Backend>> = Vec::new();
+
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = Gilrs::new() {
+            backends.push(Box::new(gilrs));
+        }
+
+        Self {
+            backends,
+            connected: Vec::new(),
+            held: BTreeSet::new(),
+            stick: [0.0; 2],
+        }
+    }
+
+    pub(crate) fn add(&mut self, backend: Box<dyn Backend>) {
+        self.backends.push(backend);
+    }
+
+    /// Takes in everything that's happened since the last call.
+    pub(crate) fn update(&mut self) {
+        while let Some(event) = self.backends.iter_mut().find_map(|b| b.poll()) {
+            match event {
+                PadEvent::Connected(name) => self.connected.push(name),
+                PadEvent::Disconnected(name) => {
+                    if let Some(i) = self.connected.iter().position(|n| *n == name) {
+                        self.connected.remove(i);
+                    }
+
+                    // Don't leave buttons stuck down.
+                    self.held.clear();
+                    self.stick = [0.0; 2];
+                }
+                PadEvent::Button(button, true) => _ = self.held.insert(button),
+                PadEvent::Button(button, false) => _ = self.held.remove(&button),
+                PadEvent::Axis(Axis::X, v) => self.stick[0] = v,
+                PadEvent::Axis(Axis::Y, v) => self.stick[1] = v,
+            }
+        }
+    }
+
+    /// The hotkeys being held.
+    pub(crate) fn hotkeys(&self, mapping: &PadMapping) -> Vec<Hotkey> {
+        self.held
+            .iter()
+            .filter_map(|b| match mapping.map.get(b) {
+                Some(Action::Hotkey(hotkey)) => Some(*hotkey),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The Game Boy buttons being held.
+    pub(crate) fn buttons(&self, mapping: &PadMapping) -> Buttons {
+        let mut buttons: Buttons = self
+            .held
+            .iter()
+            .filter_map(|b| match mapping.map.get(b) {
+                Some(Action::Button(button)) => Some(button.flag()),
+                _ => None,
+            })
+            .collect();
+
+        let [x, y] = self.stick;
+        let dz = mapping.deadzone;
+
+        // The stick only adds directions, so a centred one doesn't let go
+        // of the D-pad. The D-pad wins on an axis it's using, as a real one
+        // can't press opposite directions and games can glitch if they are.
+        if !buttons.intersects(Buttons::LEFT | Buttons::RIGHT) {
+            if x > dz {
+                buttons |= Buttons::RIGHT;
+            } else if x < -dz {
+                buttons |= Buttons::LEFT;
+            }
+        }
+        if !buttons.intersects(Buttons::UP | Buttons::DOWN) {
+            if y > dz {
+                buttons |= Buttons::UP;
+            } else if y < -dz {
+                buttons |= Buttons::DOWN;
+            }
+        }
+
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pads with nothing plugged in but a virtual one.
+    fn gamepads() -> (Gamepads, Sender<PadEvent>) {
+        let (pad, tx) = VirtualGamepad::new();
+        let gamepads = Gamepads {
+            backends: vec![Box::new(pad)],
+            connected: Vec::new(),
+            held: BTreeSet::new(),
+            stick: [0.0; 2],
+        };
+        (gamepads, tx)
+    }
+
+    fn send(gamepads: &mut Gamepads, tx: &Sender<PadEvent>, events: &[PadEvent]) {
+        for event in events {
+            tx.send(event.clone()).unwrap();
+        }
+        gamepads.update();
+    }
+
+    #[test]
+    fn buttons() {
+        let (mut pads, tx) = gamepads();
+        let mapping = PadMapping::default();
+
+        send(
+            &mut pads,
+            &tx,
+            &[
+                PadEvent::Connected("Virtual".into()),
+                PadEvent::Button(PadButton::East, true),
+                PadEvent::Button(PadButton::Start, true),
+                PadEvent::Button(PadButton::Mode, true),
+            ],
+        );
+        assert_eq!(pads.connected, ["Virtual"]);
+        assert_eq!(pads.buttons(&mapping), Buttons::A | Buttons::START);
+        assert_eq!(pads.hotkeys(&mapping), [Hotkey::Pause]);
+
+        send(&mut pads, &tx, &[PadEvent::Button(PadButton::Start, false)]);
+        assert_eq!(pads.buttons(&mapping), Buttons::A);
+    }
+
+    #[test]
+    fn stick() {
+        let (mut pads, tx) = gamepads();
+        let mapping = PadMapping::default();
+
+        send(
+            &mut pads,
+            &tx,
+            &[PadEvent::Axis(Axis::X, -0.9), PadEvent::Axis(Axis::Y, 0.9)],
+        );
+        assert_eq!(pads.buttons(&mapping), Buttons::LEFT | Buttons::UP);
+
+        // Inside the deadzone.
+        send(
+            &mut pads,
+            &tx,
+            &[PadEvent::Axis(Axis::X, 0.4), PadEvent::Axis(Axis::Y, -0.4)],
+        );
+        assert_eq!(pads.buttons(&mapping), Buttons::empty());
+
+        send(&mut pads, &tx, &[PadEvent::Axis(Axis::Y, -0.6)]);
+        assert_eq!(pads.buttons(&mapping), Buttons::DOWN);
+    }
+
+    #[test]
+    fn dpad_and_stick() {
+        let (mut pads, tx) = gamepads();
+        let mapping = PadMapping::default();
+
+        send(
+            &mut pads,
+            &tx,
+            &[PadEvent::Button(PadButton::DpadRight, true)],
+        );
+        assert_eq!(pads.buttons(&mapping), Buttons::RIGHT);
+
+        send(&mut pads, &tx, &[PadEvent::Axis(Axis::Y, 1.0)]);
+        assert_eq!(pads.buttons(&mapping), Buttons::RIGHT | Buttons::UP);
+
+        // The D-pad wins over the stick pushed the other way.
+        send(&mut pads, &tx, &[PadEvent::Axis(Axis::X, -1.0)]);
+        assert_eq!(pads.buttons(&mapping), Buttons::RIGHT | Buttons::UP);
+
+        send(
+            &mut pads,
+            &tx,
+            &[PadEvent::Button(PadButton::DpadRight, false)],
+        );
+        assert_eq!(pads.buttons(&mapping), Buttons::LEFT | Buttons::UP);
+    }
+
+    #[test]
+    fn remapped_buttons() {
+        let (mut pads, tx) = gamepads();
+        let mut mapping = PadMapping::default();
+        mapping
+            .map
+            .insert(PadButton::West, Action::Button(Button::B));
+        mapping.map.remove(&PadButton::South);
+
+        send(
+            &mut pads,
+            &tx,
+            &[
+                PadEvent::Button(PadButton::West, true),
+                PadEvent::Button(PadButton::South, true),
+            ],
+        );
+        assert_eq!(pads.buttons(&mapping), Buttons::B);
+    }
+
+    #[test]
+    fn disconnecting_lets_go() {
+        let (mut pads, tx) = gamepads();
+        let mapping = PadMapping::default();
+
+        send(
+            &mut pads,
+            &tx,
+            &[
+                PadEvent::Connected("Virtual".into()),
+                PadEvent::Button(PadButton::East, true),
+                PadEvent::Axis(Axis::X, 1.0),
+                PadEvent::Disconnected("Virtual".into()),
+            ],
+        );
+        assert!(pads.connected.is_empty());
+        assert_eq!(pads.buttons(&mapping), Buttons::empty());
+    }
+}
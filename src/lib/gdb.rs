@@ -0,0 +1,564 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    cpu::Cpu,
+    debugger::{Access, BreakReason, Breakpoint, DebugCommand, Debugger, Reg, Watchpoint},
+};
+
+/// Register order for `g`/`G`/`p`/`P`, all 16 bits little endian.
+const REGS: [Reg; 6] = [Reg::AF, Reg::BC, Reg::DE, Reg::HL, Reg::SP, Reg::PC];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.oxyboy.sm83">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// A GDB remote serial protocol server on a local TCP port.
+///
+/// It's polled from the emulator thread rather than running on its own, so
+/// it can read and write the machine directly. Breakpoints and watchpoints
+/// are the `Debugger`'s, so they show up in the debugger panel too.
+pub(crate) struct GdbStub {
+    pub(crate) port: u16,
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    no_ack: bool,
+    // Sent `c` or `s` and is waiting to hear why the target stopped.
+    waiting: bool,
+}
+
+impl GdbStub {
+    pub(crate) fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbStub {
+            port,
+            listener,
+            client: None,
+        })
+    }
+
+    pub(crate) fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Services the connection. `paused` is the emulator's run state.
+    pub(crate) fn poll(&mut self, cpu: &mut Cpu, debugger: &mut Debugger, paused: &mut bool) {
+        if self.client.is_none() {
+            let Ok((stream, _)) = self.listener.accept() else {
+                return;
+            };
+
+            if stream.set_nonblocking(true).is_err() {
+                return;
+            }
+
+            // GDB expects the target to be stopped when it attaches.
+            *paused = debugger.command(DebugCommand::Pause, cpu, *paused);
+
+            self.client = Some(Client {
+                stream,
+                buf: Vec::new(),
+                no_ack: false,
+                waiting: false,
+            });
+        }
+
+        let Some(client) = &mut self.client else {
+            return;
+        };
+
+        if client.poll(cpu, debugger, paused).is_err() {
+            self.client = None;
+        }
+    }
+}
+
+impl Client {
+    fn poll(
+        &mut self,
+        cpu: &mut Cpu,
+        debugger: &mut Debugger,
+        paused: &mut bool,
+    ) -> io::Result<()> {
+        let mut chunk = [0; 1024];
+
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            match packet {
+                Packet::Interrupt => {
+                    *paused = debugger.command(DebugCommand::Pause, cpu, *paused);
+                }
+                Packet::Command(data) => {
+                    if let Some(reply) = self.handle(&data, cpu, debugger, paused) {
+                        self.send(&reply)?;
+                    }
+                }
+            }
+        }
+
+        if *paused && self.waiting {
+            self.waiting = false;
+            let reply = stop_reply(debugger.reason());
+            self.send(&reply)?;
+        }
+
+        Ok(())
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.buf.first() {
+                None => return Ok(None),
+                Some(b'+' | b'-') => {
+                    self.buf.remove(0);
+                }
+                Some(0x03) => {
+                    self.buf.remove(0);
+                    return Ok(Some(Packet::Interrupt));
+                }
+                Some(b'$') => {
+                    let Some(end) = self.buf.iter().position(|&b| b == b'#') else {
+                        return Ok(None);
+                    };
+
+                    // The two checksum digits haven't arrived yet.
+                    if self.buf.len() < end + 3 {
+                        return Ok(None);
+                    }
+
+                    let data = unescape(&self.buf[1..end]);
+                    self.buf.drain(..end + 3);
+
+                    if !self.no_ack {
+                        self.write(b"+")?;
+                    }
+
+                    return Ok(Some(Packet::Command(data)));
+                }
+                Some(_) => {
+                    self.buf.remove(0);
+                }
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        data: &[u8],
+        cpu: &mut Cpu,
+        debugger: &mut Debugger,
+        paused: &mut bool,
+    ) -> Option<String> {
+        // Split before decoding, as a stray byte decodes to more than one.
+        let Some((&cmd, args)) = data.split_first() else {
+            return Some(String::new());
+        };
+        let args = String::from_utf8_lossy(args);
+        let args = args.as_ref();
+
+        let reply = match cmd {
+            b'?' => stop_reply(debugger.reason()),
+            b'g' => REGS.iter().map(|r| hex_u16(r.get(&cpu.reg))).collect(),
+            b'G' => {
+                for (r, chunk) in REGS.iter().zip(args.as_bytes().chunks(4)) {
+                    if let Some(v) = parse_u16_le(chunk) {
+                        r.set(&mut cpu.reg, v);
+                    }
+                }
+                "OK".into()
+            }
+            b'p' => match usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|i| REGS.get(i))
+            {
+                Some(r) => hex_u16(r.get(&cpu.reg)),
+                None => "E01".into(),
+            },
+            b'P' => write_register(args, cpu).unwrap_or_else(|| "E01".into()),
+            b'm' => read_memory(args, cpu).unwrap_or_else(|| "E01".into()),
+            b'M' => write_memory(args, cpu).unwrap_or_else(|| "E01".into()),
+            b'c' => {
+                self.resume(DebugCommand::Continue, args, cpu, debugger, paused);
+                return None;
+            }
+            b's' => {
+                self.resume(DebugCommand::StepInto, args, cpu, debugger, paused);
+                return None;
+            }
+            b'Z' | b'z' => self.breakpoint(cmd == b'Z', args, cpu, debugger),
+            b'H' | b'T' => "OK".into(),
+            b'D' => {
+                *paused = debugger.command(DebugCommand::Continue, cpu, *paused);
+                "OK".into()
+            }
+            b'k' => {
+                *paused = debugger.command(DebugCommand::Continue, cpu, *paused);
+                return None;
+            }
+            b'q' | b'Q' | b'v' => {
+                let packet = String::from_utf8_lossy(data);
+                return Some(self.query(&packet, cpu, debugger, paused));
+            }
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn query(
+        &mut self,
+        packet: &str,
+        cpu: &mut Cpu,
+        debugger: &mut Debugger,
+        paused: &mut bool,
+    ) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+"
+                .into();
+        }
+
+        if let Some(rest) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return xfer(TARGET_XML, rest);
+        }
+
+        if let Some(action) = packet.strip_prefix("vCont;") {
+            let cmd = if action.starts_with('s') {
+                DebugCommand::StepInto
+            } else {
+                DebugCommand::Continue
+            };
+
+            self.resume(cmd, "", cpu, debugger, paused);
+            return String::new();
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".into()
+            }
+            "qAttached" => "1".into(),
+            "qC" => "QC1".into(),
+            "qfThreadInfo" => "m1".into(),
+            "qsThreadInfo" => "l".into(),
+            "vCont?" => "vCont;c;s".into(),
+            "vMustReplyEmpty" => String::new(),
+            _ => String::new(),
+        }
+    }
+
+    fn resume(
+        &mut self,
+        cmd: DebugCommand,
+        addr: &str,
+        cpu: &mut Cpu,
+        debugger: &mut Debugger,
+        paused: &mut bool,
+    ) {
+        if let Ok(addr) = u16::from_str_radix(addr, 16) {
+            cpu.reg.pc = addr;
+        }
+
+        *paused = debugger.command(cmd, cpu, *paused);
+        self.waiting = true;
+    }
+
+    fn breakpoint(
+        &self,
+        insert: bool,
+        args: &str,
+        cpu: &mut Cpu,
+        debugger: &mut Debugger,
+    ) -> String {
+        let mut parts = args.split(',');
+
+        let (Some(kind), Some(addr), Some(len)) = (parts.next(), parts.next(), parts.next()) else {
+            return "E01".into();
+        };
+
+        let (Ok(addr), Ok(len)) = (u16::from_str_radix(addr, 16), u16::from_str_radix(len, 16))
+        else {
+            return "E01".into();
+        };
+
+        let access = match kind {
+            "0" | "1" => {
+                if insert {
+                    debugger.add_breakpoint(Breakpoint {
+                        addr,
+                        condition: None,
+                    });
+                } else {
+                    debugger.remove_breakpoint_at(addr);
+                }
+                return "OK".into();
+            }
+            "2" => Access::Write,
+            "3" => Access::Read,
+            "4" => Access::ReadWrite,
+            _ => return String::new(),
+        };
+
+        let wp = Watchpoint {
+            start: addr,
+            end: addr.saturating_add(len.max(1) - 1),
+            access,
+        };
+
+        let watchpoints = &mut cpu.mmu.watchpoints;
+
+        if insert {
+            watchpoints.push(wp);
+        } else if let Some(i) = watchpoints
+            .iter()
+            .position(|w| w.start == wp.start && w.end == wp.end && w.access == wp.access)
+        {
+            watchpoints.remove(i);
+        }
+
+        "OK".into()
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.write(format!("${data}#{checksum:02x}").as_bytes())
+    }
+
+    fn write(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(n) => bytes = &bytes[n..],
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+enum Packet {
+    Interrupt,
+    Command(Vec<u8>),
+}
+
+/// `P n=v`: sets register `n`.
+fn write_register(args: &str, cpu: &mut Cpu) -> Option<String> {
+    let (n, v) = args.split_once('=')?;
+    let r = REGS.get(usize::from_str_radix(n, 16).ok()?)?;
+    r.set(&mut cpu.reg, parse_u16_le(v.as_bytes())?);
+    Some("OK".into())
+}
+
+/// `m addr,len`: reads memory as the CPU sees it.
+fn read_memory(args: &str, cpu: &Cpu) -> Option<String> {
+    let (addr, len) = parse_addr_len(args)?;
+
+    Some(
+        (0..len)
+            .map(|i| format!("{:02x}", cpu.mmu.peek(addr.wrapping_add(i))))
+            .collect(),
+    )
+}
+
+/// `M addr,len:bytes`: writes memory, ROM included.
+fn write_memory(args: &str, cpu: &mut Cpu) -> Option<String> {
+    let (range, bytes) = args.split_once(':')?;
+    let (addr, len) = parse_addr_len(range)?;
+    let bytes = parse_hex_bytes(bytes)?;
+
+    for (i, &b) in bytes.iter().take(len as usize).enumerate() {
+        cpu.mmu.poke(addr.wrapping_add(i as u16), b);
+    }
+    Some("OK".into())
+}
+
+fn stop_reply(reason: Option<BreakReason>) -> String {
+    match reason {
+        Some(BreakReason::Pause) | None => format!("S{SIGINT:02x}"),
+        Some(BreakReason::Watchpoint(hit)) => {
+            let kind = match hit.access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::ReadWrite => "awatch",
+            };
+            format!("T{SIGTRAP:02x}{kind}:{:04x};", hit.addr)
+        }
+        Some(BreakReason::Breakpoint(_)) => format!("T{SIGTRAP:02x}swbreak:;"),
+        Some(_) => format!("S{SIGTRAP:02x}"),
+    }
+}
+
+/// Answers a `qXfer` read of `offset,length` out of `doc`.
+fn xfer(doc: &str, range: &str) -> String {
+    let Some((offset, len)) = range.split_once(',') else {
+        return "E01".into();
+    };
+
+    let (Ok(offset), Ok(len)) = (
+        usize::from_str_radix(offset, 16),
+        usize::from_str_radix(len, 16),
+    ) else {
+        return "E01".into();
+    };
+
+    let rest = doc.get(offset.min(doc.len())..).unwrap_or_default();
+
+    if rest.len() > len {
+        format!("m{}", &rest[..len])
+    } else {
+        format!("l{rest}")
+    }
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => out.extend(bytes.next().map(|b| b ^ 0x20)),
+            _ => out.push(b),
+        }
+    }
+
+    out
+}
+
+fn hex_u16(v: u16) -> String {
+    format!("{:02x}{:02x}", v & 0xFF, v >> 8)
+}
+
+fn parse_u16_le(hex: &[u8]) -> Option<u16> {
+    let bytes = parse_hex_bytes(std::str::from_utf8(hex).ok()?)?;
+
+    match bytes[..] {
+        [lo, hi] => Some(u16::from_le_bytes([lo, hi])),
+        _ => None,
+    }
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_addr_len(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        u16::from_str_radix(addr, 16).ok()?,
+        u16::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client on a loopback connection nobody's at the other end of.
+    fn client() -> (Client, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let other = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let client = Client {
+            stream,
+            buf: Vec::new(),
+            no_ack: false,
+            waiting: false,
+        };
+        (client, other)
+    }
+
+    fn reply(packet: &[u8], cpu: &mut Cpu) -> Option<String> {
+        let (mut client, _other) = client();
+        let mut debugger = Debugger::new();
+        let mut paused = true;
+        client.handle(packet, cpu, &mut debugger, &mut paused)
+    }
+
+    #[test]
+    fn registers() {
+        let mut cpu = Cpu::new();
+        cpu.reg.pc = 0x0150;
+
+        assert_eq!(reply(b"p5", &mut cpu).unwrap(), "5001");
+        assert_eq!(reply(b"P3=00c0", &mut cpu).unwrap(), "OK");
+        assert_eq!(cpu.reg.hl(), 0xC000);
+        assert_eq!(reply(b"p6", &mut cpu).unwrap(), "E01");
+    }
+
+    #[test]
+    fn memory() {
+        let mut cpu = Cpu::new();
+
+        assert_eq!(reply(b"Mc000,2:2a3b", &mut cpu).unwrap(), "OK");
+        assert_eq!(reply(b"mc000,3", &mut cpu).unwrap(), "2a3b00");
+        assert_eq!(cpu.mmu.peek(0xC001), 0x3B);
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let mut cpu = Cpu::new();
+
+        for packet in [
+            &b"m"[..],
+            b"mc000",
+            b"mzz,1",
+            b"Mc000,1",
+            b"Mc000,1:z",
+            b"P",
+            b"P3",
+            b"P9=0000",
+            b"P3=12",
+        ] {
+            assert_eq!(
+                reply(packet, &mut cpu).as_deref(),
+                Some("E01"),
+                "{}",
+                String::from_utf8_lossy(packet)
+            );
+        }
+    }
+
+    #[test]
+    fn non_ascii_packets() {
+        let mut cpu = Cpu::new();
+
+        assert_eq!(reply(&[0xFF, b'1'], &mut cpu).unwrap(), "");
+        assert_eq!(reply(&[b'm', 0xC3, 0x28], &mut cpu).unwrap(), "E01");
+        assert_eq!(reply(b"", &mut cpu).unwrap(), "");
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+use crate::{
+    joypad::{Buttons, Joypad},
+    Input,
+};
+
+/// Works out what the joypad sees from the buttons held in the frontend,
+/// turbo and macro playback, and records macros. The joypad is only set at
+/// frame boundaries, so input keeps time with the game whatever speed it's
+/// running at.
+pub(crate) struct Injector {
+    held: Buttons,
+    turbo: Buttons,
+    rate: u32,
+    // Frames since turbo last changed, so auto-fire starts with a press.
+    turbo_frames: u32,
+    playing: VecDeque<Buttons>,
+    recording: Option<Vec<Buttons>>,
+    // What the joypad was last set to.
+    applied: Buttons,
+}
+
+impl Injector {
+    pub(crate) fn new() -> Self {
+        Self {
+            held: Buttons::empty(),
+            turbo: Buttons::empty(),
+            rate: 1,
+            turbo_frames: 0,
+            playing: VecDeque::new(),
+            recording: None,
+            applied: Buttons::empty(),
+        }
+    }
+
+    /// Takes a message from the frontend. Gives back the recording when
+    /// one is stopped.
+    pub(crate) fn receive(&mut self, input: Input) -> Option<Vec<Buttons>> {
+        match input {
+            Input::Buttons(buttons) => self.held = buttons,
+            Input::Turbo(buttons, rate) => {
+                self.turbo = buttons;
+                self.rate = rate.max(1);
+                self.turbo_frames = 0;
+            }
+            Input::Macro(frames) => self.playing = frames.into(),
+            Input::Record(true) => self.recording = Some(Vec::new()),
+            Input::Record(false) => return self.recording.take(),
+        }
+
+        None
+    }
+
+    /// Carries what was held over to a newly loaded ROM's joypad.
+    pub(crate) fn rom_loaded(&mut self, joypad: &mut Joypad) {
+        self.playing.clear();
+        joypad.set(self.applied);
+    }
+
+    /// The buttons for the next frame. Called once a frame.
+    pub(crate) fn frame(&mut self) -> Buttons {
+        if let Some(recording) = &mut self.recording {
+            recording.push(self.held);
+        }
+
+        let turbo = if (self.turbo_frames / self.rate).is_multiple_of(2) {
+            self.turbo
+        } else {
+            Buttons::empty()
+        };
+        self.turbo_frames = self.turbo_frames.wrapping_add(1);
+
+        let playing = self.playing.pop_front().unwrap_or_default();
+
+        self.applied = self.held | turbo | playing;
+        self.applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(injector: &mut Injector, n: usize) -> Vec<Buttons> {
+        (0..n).map(|_| injector.frame()).collect()
+    }
+
+    #[test]
+    fn turbo() {
+        let mut injector = Injector::new();
+        let (a, none) = (Buttons::A, Buttons::empty());
+
+        injector.receive(Input::Turbo(a, 2));
+        assert_eq!(frames(&mut injector, 6), [a, a, none, none, a, a]);
+
+        // Changing it starts over with a press, and 0 is taken as 1.
+        injector.receive(Input::Turbo(a, 0));
+        assert_eq!(frames(&mut injector, 4), [a, none, a, none]);
+
+        injector.receive(Input::Turbo(none, 1));
+        assert_eq!(frames(&mut injector, 2), [none, none]);
+    }
+
+    #[test]
+    fn macros() {
+        let mut injector = Injector::new();
+        let steps = [Buttons::A, Buttons::empty(), Buttons::B | Buttons::UP];
+
+        injector.receive(Input::Macro(steps.to_vec()));
+        assert_eq!(frames(&mut injector, 3), steps);
+        assert_eq!(frames(&mut injector, 2), [Buttons::empty(); 2]);
+    }
+
+    #[test]
+    fn held_buttons_mix_with_macros() {
+        let mut injector = Injector::new();
+
+        injector.receive(Input::Buttons(Buttons::RIGHT));
+        injector.receive(Input::Macro(vec![Buttons::A, Buttons::B]));
+        assert_eq!(
+            frames(&mut injector, 3),
+            [
+                Buttons::RIGHT | Buttons::A,
+                Buttons::RIGHT | Buttons::B,
+                Buttons::RIGHT
+            ]
+        );
+    }
+
+    #[test]
+    fn recording() {
+        let mut injector = Injector::new();
+        assert_eq!(injector.receive(Input::Record(false)), None);
+
+        injector.receive(Input::Record(true));
+        injector.receive(Input::Buttons(Buttons::A));
+        injector.frame();
+        injector.receive(Input::Buttons(Buttons::empty()));
+        injector.frame();
+
+        // Only what's held goes in, not turbo or what a macro plays.
+        injector.receive(Input::Turbo(Buttons::B, 1));
+        injector.receive(Input::Macro(vec![Buttons::START]));
+        injector.receive(Input::Buttons(Buttons::LEFT));
+        injector.frame();
+
+        assert_eq!(
+            injector.receive(Input::Record(false)),
+            Some(vec![Buttons::A, Buttons::empty(), Buttons::LEFT])
+        );
+
+        // Stopped, so nothing more is kept.
+        injector.frame();
+        assert_eq!(injector.receive(Input::Record(false)), None);
+    }
+}
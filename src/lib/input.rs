@@ -0,0 +1,582 @@
+use std::{collections::BTreeMap, fs, io, path::PathBuf, sync::mpsc::Sender};
+
+use eframe::egui::{self, InputState, Key};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config,
+    gamepad::{Gamepads, PadMapping},
+    joypad::Buttons,
+    Input,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub(crate) const ALL: [Button; 8] = [
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    pub(crate) fn flag(self) -> Buttons {
+        match self {
+            Button::Right => Buttons::RIGHT,
+            Button::Left => Buttons::LEFT,
+            Button::Up => Buttons::UP,
+            Button::Down => Buttons::DOWN,
+            Button::A => Buttons::A,
+            Button::B => Buttons::B,
+            Button::Select => Buttons::SELECT,
+            Button::Start => Buttons::START,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Button::Right => "Right",
+            Button::Left => "Left",
+            Button::Up => "Up",
+            Button::Down => "Down",
+            Button::A => "A",
+            Button::B => "B",
+            Button::Select => "Select",
+            Button::Start => "Start",
+        }
+    }
+}
+
+/// Emulator controls that can be bound alongside the buttons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Hotkey {
+    /// Pauses, or continues if paused.
+    Pause,
+    /// Runs to the start of the next frame and pauses.
+    FrameAdvance,
+    /// Runs to the start of the next scanline and pauses.
+    LineAdvance,
+    /// Runs as fast as possible while held.
+    FastForward,
+    /// Auto-fires A while held, or toggles it (see `Turbo::toggle`).
+    TurboA,
+    TurboB,
+}
+
+impl Hotkey {
+    pub(crate) const ALL: [Hotkey; 6] = [
+        Hotkey::Pause,
+        Hotkey::FrameAdvance,
+        Hotkey::LineAdvance,
+        Hotkey::FastForward,
+        Hotkey::TurboA,
+        Hotkey::TurboB,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Hotkey::Pause => "Pause",
+            Hotkey::FrameAdvance => "Frame advance",
+            Hotkey::LineAdvance => "Scanline advance",
+            Hotkey::FastForward => "Fast-forward",
+            Hotkey::TurboA => "Turbo A",
+            Hotkey::TurboB => "Turbo B",
+        }
+    }
+}
+
+/// Something a key or pad button can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Action {
+    Button(Button),
+    Hotkey(Hotkey),
+}
+
+impl Action {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Action::Button(button) => button.name(),
+            Action::Hotkey(hotkey) => hotkey.name(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Turbo {
+    /// Frames each auto-fired press, and each gap between them, lasts.
+    pub(crate) rate: u32,
+    /// Whether a turbo hotkey turns auto-fire on and off, rather than
+    /// auto-firing while it's held.
+    pub(crate) toggle: bool,
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self {
+            rate: 2,
+            toggle: false,
+        }
+    }
+}
+
+/// A recorded button sequence, played back one step a frame.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Macro {
+    pub(crate) name: String,
+    /// The key that plays it, if it has one.
+    #[serde(default)]
+    pub(crate) key: Option<String>,
+    pub(crate) steps: Vec<Step>,
+}
+
+/// Buttons held for a number of frames.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Step {
+    pub(crate) buttons: Vec<Button>,
+    pub(crate) frames: u32,
+}
+
+impl Macro {
+    /// Makes a macro out of one set of buttons a frame, leaving out the
+    /// idle frames at either end.
+    pub(crate) fn from_frames(name: String, frames: &[Buttons]) -> Macro {
+        let start = frames
+            .iter()
+            .position(|b| !b.is_empty())
+            .unwrap_or(frames.len());
+        let end = frames
+            .iter()
+            .rposition(|b| !b.is_empty())
+            .map_or(start, |i| i + 1);
+
+        let steps = frames[start..end]
+            .chunk_by(|a, b| a == b)
+            .map(|run| Step {
+                buttons: Button::ALL
+                    .into_iter()
+                    .filter(|b| run[0].contains(b.flag()))
+                    .collect(),
+                frames: run.len() as u32,
+            })
+            .collect();
+
+        Macro {
+            name,
+            key: None,
+            steps,
+        }
+    }
+
+    /// One set of buttons a frame.
+    pub(crate) fn frames(&self) -> Vec<Buttons> {
+        self.steps
+            .iter()
+            .flat_map(|step| {
+                let buttons = step.buttons.iter().map(|b| b.flag()).collect();
+                std::iter::repeat_n(buttons, step.frames as usize)
+            })
+            .collect()
+    }
+}
+
+/// Key names (as `egui::Key::name` gives them) for each button.
+pub(crate) type KeyMap = BTreeMap<Button, Vec<String>>;
+
+/// What's in `input.toml`. A profile is named after the ROM's header title
+/// and replaces the keys of any button it lists.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Bindings {
+    #[serde(default = "default_keys")]
+    pub(crate) keys: KeyMap,
+    #[serde(default)]
+    pub(crate) profiles: BTreeMap<String, KeyMap>,
+    #[serde(default = "default_hotkeys")]
+    pub(crate) hotkeys: BTreeMap<Hotkey, Vec<String>>,
+    #[serde(default)]
+    pub(crate) gamepad: PadMapping,
+    #[serde(default)]
+    pub(crate) turbo: Turbo,
+    #[serde(default)]
+    pub(crate) macros: Vec<Macro>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            keys: default_keys(),
+            profiles: BTreeMap::new(),
+            hotkeys: default_hotkeys(),
+            gamepad: PadMapping::default(),
+            turbo: Turbo::default(),
+            macros: Vec::new(),
+        }
+    }
+}
+
+fn default_keys() -> KeyMap {
+    [
+        (Button::Up, ["ArrowUp", "K"].as_slice()),
+        (Button::Down, &["ArrowDown", "J"]),
+        (Button::Left, &["ArrowLeft", "H"]),
+        (Button::Right, &["ArrowRight", "L"]),
+        (Button::A, &["Z"]),
+        (Button::B, &["X"]),
+        (Button::Select, &["Space"]),
+        (Button::Start, &["Enter"]),
+    ]
+    .into_iter()
+    .map(|(button, keys)| (button, keys.iter().map(|k| k.to_string()).collect()))
+    .collect()
+}
+
+/// Turbo sits above the key it auto-fires.
+fn default_hotkeys() -> BTreeMap<Hotkey, Vec<String>> {
+    [
+        (Hotkey::Pause, "P"),
+        (Hotkey::FrameAdvance, "N"),
+        (Hotkey::LineAdvance, "M"),
+        (Hotkey::FastForward, "Tab"),
+        (Hotkey::TurboA, "A"),
+        (Hotkey::TurboB, "S"),
+    ]
+    .into_iter()
+    .map(|(hotkey, key)| (hotkey, vec![key.to_string()]))
+    .collect()
+}
+
+impl Bindings {
+    pub(crate) fn load() -> Bindings {
+        let Some(path) = path() else {
+            return Bindings::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Bindings::parse(&text).unwrap_or_else(|e| {
+                eprintln!("Couldn't parse {}: {e}", path.display());
+                Bindings::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Bindings::default(),
+            Err(e) => {
+                eprintln!("Couldn't read {}: {e}", path.display());
+                Bindings::default()
+            }
+        }
+    }
+
+    fn parse(text: &str) -> Result<Bindings, toml::de::Error> {
+        let mut bindings: Bindings = toml::from_str(text)?;
+
+        // Anything left out of the file keeps its default keys.
+        for (button, keys) in default_keys() {
+            bindings.keys.entry(button).or_insert(keys);
+        }
+        for (hotkey, keys) in default_hotkeys() {
+            bindings.hotkeys.entry(hotkey).or_insert(keys);
+        }
+
+        Ok(bindings)
+    }
+
+    pub(crate) fn save(&self) {
+        let Some(path) = path() else {
+            return;
+        };
+
+        let result = toml::to_string(self)
+            .map_err(io::Error::other)
+            .and_then(|text| config::write_atomic(&path, &text));
+
+        if let Err(e) = result {
+            eprintln!("Couldn't save key bindings to {}: {e}", path.display());
+        }
+    }
+
+    /// The keys for `action`. Only buttons have per-ROM profiles.
+    pub(crate) fn action_keys(&self, action: Action, profile: Option<&str>) -> &[String] {
+        match action {
+            Action::Button(button) => self.keys(button, profile),
+            Action::Hotkey(hotkey) => self.hotkeys.get(&hotkey).map_or(&[], |keys| keys),
+        }
+    }
+
+    pub(crate) fn set_keys(&mut self, action: Action, profile: Option<&str>, keys: Vec<String>) {
+        match (action, profile) {
+            (Action::Button(button), Some(title)) => {
+                self.profiles
+                    .entry(title.to_string())
+                    .or_default()
+                    .insert(button, keys);
+            }
+            (Action::Button(button), None) => _ = self.keys.insert(button, keys),
+            (Action::Hotkey(hotkey), _) => _ = self.hotkeys.insert(hotkey, keys),
+        }
+    }
+
+    /// The keys for `button`, with `profile`'s override if it has one.
+    pub(crate) fn keys(&self, button: Button, profile: Option<&str>) -> &[String] {
+        profile
+            .and_then(|p| self.profiles.get(p))
+            .and_then(|keys| keys.get(&button))
+            .or_else(|| self.keys.get(&button))
+            .map_or(&[], |keys| keys)
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    config::path("input.toml")
+}
+
+/// Works out which buttons the held keys press.
+pub(crate) struct Keyboard {
+    pub(crate) bindings: Bindings,
+    /// The loaded ROM's title, which picks its profile.
+    pub(crate) profile: Option<String>,
+}
+
+impl Keyboard {
+    pub(crate) fn new() -> Self {
+        Self {
+            bindings: Bindings::load(),
+            profile: None,
+        }
+    }
+
+    /// The hotkeys being held.
+    pub(crate) fn hotkeys(&self, input: &InputState) -> Vec<Hotkey> {
+        Hotkey::ALL
+            .into_iter()
+            .filter(|&hotkey| {
+                down(
+                    input,
+                    self.bindings.action_keys(Action::Hotkey(hotkey), None),
+                )
+            })
+            .collect()
+    }
+
+    /// The macros whose keys were pressed this frame.
+    pub(crate) fn macros(&self, input: &InputState) -> Vec<&Macro> {
+        self.bindings
+            .macros
+            .iter()
+            .filter(|m| {
+                m.key
+                    .as_deref()
+                    .and_then(Key::from_name)
+                    .is_some_and(|key| input.key_pressed(key))
+            })
+            .collect()
+    }
+
+    /// The buttons being held. A button stays down while any of its keys
+    /// are.
+    pub(crate) fn buttons(&self, input: &InputState) -> Buttons {
+        Button::ALL
+            .into_iter()
+            .filter(|&button| down(input, self.bindings.keys(button, self.profile.as_deref())))
+            .map(Button::flag)
+            .collect()
+    }
+}
+
+fn down(input: &InputState, keys: &[String]) -> bool {
+    keys.iter()
+        .filter_map(|name| Key::from_name(name))
+        .any(|key| input.key_down(key))
+}
+
+/// The keyboard and gamepads together, turned into what the emulator is
+/// sent.
+pub(crate) struct Controls {
+    pub(crate) keyboard: Keyboard,
+    pub(crate) gamepads: Gamepads,
+    tx: Sender<Input>,
+    // The buttons last sent.
+    held: Buttons,
+    hotkeys: Vec<Hotkey>,
+    // Turbo buttons switched on, when turbo toggles.
+    toggled: Buttons,
+    // The last `Input::Turbo`.
+    turbo: (Buttons, u32),
+}
+
+impl Controls {
+    pub(crate) fn new(tx: Sender<Input>, gamepads: Gamepads) -> Self {
+        Self {
+            keyboard: Keyboard::new(),
+            gamepads,
+            tx,
+            held: Buttons::empty(),
+            hotkeys: Vec::new(),
+            toggled: Buttons::empty(),
+            turbo: (Buttons::empty(), 1),
+        }
+    }
+
+    /// Whether a hotkey was held at the last `update`.
+    pub(crate) fn holding(&self, hotkey: Hotkey) -> bool {
+        self.hotkeys.contains(&hotkey)
+    }
+
+    /// Sends the emulator whatever changed since the last frame, and gives
+    /// back the pause and advance hotkeys that were just pressed.
+    /// The keyboard is left out while `keys` is false.
+    pub(crate) fn update(&mut self, ctx: &egui::Context, keys: bool) -> Vec<Hotkey> {
+        self.gamepads.update();
+
+        let bindings = &self.keyboard.bindings;
+        let mut held = self.gamepads.buttons(&bindings.gamepad);
+        let mut hotkeys = self.gamepads.hotkeys(&bindings.gamepad);
+
+        if keys {
+            ctx.input(|i| {
+                held |= self.keyboard.buttons(i);
+                hotkeys.extend(self.keyboard.hotkeys(i));
+
+                for m in self.keyboard.macros(i) {
+                    _ = self.tx.send(Input::Macro(m.frames()));
+                }
+            });
+        }
+
+        if held != self.held {
+            self.held = held;
+            _ = self.tx.send(Input::Buttons(held));
+        }
+
+        let pressed: Vec<Hotkey> = hotkeys
+            .iter()
+            .copied()
+            .filter(|h| !self.hotkeys.contains(h))
+            .collect();
+        self.hotkeys = hotkeys;
+
+        let turbo = bindings.turbo;
+        let mut turbo_buttons = Buttons::empty();
+
+        for (hotkey, button) in [(Hotkey::TurboA, Buttons::A), (Hotkey::TurboB, Buttons::B)] {
+            if turbo.toggle && pressed.contains(&hotkey) {
+                self.toggled.toggle(button);
+            }
+            if !turbo.toggle && self.hotkeys.contains(&hotkey) {
+                turbo_buttons |= button;
+            }
+        }
+
+        if turbo.toggle {
+            turbo_buttons = self.toggled;
+        } else {
+            self.toggled = Buttons::empty();
+        }
+
+        if (turbo_buttons, turbo.rate) != self.turbo {
+            self.turbo = (turbo_buttons, turbo.rate);
+            _ = self.tx.send(Input::Turbo(turbo_buttons, turbo.rate));
+        }
+
+        pressed
+            .into_iter()
+            .filter(|h| {
+                matches!(
+                    h,
+                    Hotkey::Pause | Hotkey::FrameAdvance | Hotkey::LineAdvance
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings() {
+        let bindings = Bindings::parse(
+            r#"
+            [keys]
+            a = ["Q"]
+            start = []
+
+            [profiles."TETRIS"]
+            a = ["W", "E"]
+
+            [hotkeys]
+            fast_forward = ["F"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(bindings.keys(Button::A, None), ["Q"]);
+        assert_eq!(bindings.keys(Button::Start, None), [] as [String; 0]);
+        assert_eq!(bindings.keys(Button::A, Some("TETRIS")), ["W", "E"]);
+        // The profile only replaces what it lists.
+        assert_eq!(bindings.keys(Button::B, Some("TETRIS")), ["X"]);
+        assert_eq!(bindings.keys(Button::A, Some("OTHER")), ["Q"]);
+
+        assert_eq!(
+            bindings.action_keys(Action::Hotkey(Hotkey::FastForward), None),
+            ["F"]
+        );
+        assert_eq!(
+            bindings.action_keys(Action::Hotkey(Hotkey::Pause), None),
+            ["P"]
+        );
+    }
+
+    #[test]
+    fn empty_bindings_are_the_defaults() {
+        let bindings = Bindings::parse("").unwrap();
+
+        assert_eq!(bindings.keys, default_keys());
+        assert_eq!(bindings.hotkeys, default_hotkeys());
+        assert!(bindings.profiles.is_empty());
+    }
+
+    #[test]
+    fn malformed_bindings() {
+        for text in [
+            "[keys]\nturbo = [\"Q\"]",
+            "[keys]\na = \"Q\"",
+            "[hotkeys]\nrewind = [\"R\"]",
+            "[turbo]\nrate = -1",
+            "keys = ",
+        ] {
+            assert!(Bindings::parse(text).is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn set_keys() {
+        let mut bindings = Bindings::default();
+
+        bindings.set_keys(Action::Button(Button::A), Some("TETRIS"), vec!["W".into()]);
+        bindings.set_keys(Action::Button(Button::B), None, vec!["C".into()]);
+
+        assert_eq!(bindings.keys(Button::A, Some("TETRIS")), ["W"]);
+        assert_eq!(bindings.keys(Button::A, None), ["Z"]);
+        assert_eq!(bindings.keys(Button::B, Some("TETRIS")), ["C"]);
+
+        let saved = toml::to_string(&bindings).unwrap();
+        let loaded = Bindings::parse(&saved).unwrap();
+        assert_eq!(loaded.keys, bindings.keys);
+        assert_eq!(loaded.profiles, bindings.profiles);
+    }
+}
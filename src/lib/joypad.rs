@@ -1,40 +1,156 @@
+use crate::{
+    error::Result,
+    state::{Reader, Writer},
+};
+
+bitflags::bitflags! {
+    /// Buttons held down. Directions are the low nibble and the rest the
+    /// high one, in the order P1 reads them.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub(crate) struct Buttons: u8 {
+        const RIGHT  = 0b0000_0001;
+        const LEFT   = 0b0000_0010;
+        const UP     = 0b0000_0100;
+        const DOWN   = 0b0000_1000;
+        const A      = 0b0001_0000;
+        const B      = 0b0010_0000;
+        const SELECT = 0b0100_0000;
+        const START  = 0b1000_0000;
+    }
+}
+
+/// P1/JOYP. Writing bit 4 or 5 low selects the directions or the other
+/// buttons, and a held button in a selected group pulls its line low.
 pub(crate) struct Joypad {
     pub(crate) interrupt: u8,
-    buttons: u8,
-    reg: u8,
+    buttons: Buttons,
+    select: u8,
 }
 
 impl Joypad {
     pub(crate) fn new() -> Self {
         Self {
             interrupt: 0,
-            buttons: 0xFF,
-            reg: 0,
+            buttons: Buttons::empty(),
+            select: 0,
         }
     }
 
-    pub(crate) fn keydown(&mut self, key: u8) {
-        self.buttons &= !key;
-        self.interrupt |= 0x10;
+    /// Sets what's held. Called once a frame.
+    pub(crate) fn set(&mut self, buttons: Buttons) {
+        self.update(|joypad| joypad.buttons = buttons);
     }
 
-    pub(crate) fn keyup(&mut self, key: u8) {
-        self.buttons |= key;
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        w.u8(self.interrupt);
+        w.u8(self.buttons.bits());
+        w.u8(self.select);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut Reader) -> Result<()> {
+        self.interrupt = r.u8()?;
+        self.buttons = Buttons::from_bits_retain(r.u8()?);
+        self.select = r.u8()? & 0x30;
+        Ok(())
     }
 
     pub(crate) fn rb(&self, _a: u16) -> u8 {
-        if (self.reg & 0b0001_0000) == 0 {
-            return self.reg | (self.buttons & 0x0F);
+        0xC0 | self.select | self.lines()
+    }
+
+    pub(crate) fn wb(&mut self, _a: u16, v: u8) {
+        self.update(|joypad| joypad.select = v & 0x30);
+    }
+
+    /// P1's low nibble. With both groups selected a line is low if either
+    /// button on it is held.
+    fn lines(&self) -> u8 {
+        let held = self.buttons.bits();
+        let mut low = 0;
+
+        if self.select & 0x10 == 0 {
+            low |= held & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            low |= held >> 4;
         }
 
-        if (self.reg & 0b0010_0000) == 0 {
-            return self.reg | (self.buttons >> 4);
+        !low & 0x0F
+    }
+
+    /// Requests the interrupt if `change` pulls a line low that was high.
+    fn update(&mut self, change: impl FnOnce(&mut Self)) {
+        let before = self.lines();
+        change(self);
+
+        if before & !self.lines() != 0 {
+            self.interrupt |= 0x10;
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        self.reg
+    fn joypad(buttons: Buttons, select: u8) -> Joypad {
+        let mut joypad = Joypad::new();
+        joypad.wb(0xFF00, select);
+        joypad.set(buttons);
+        joypad.interrupt = 0;
+        joypad
     }
 
-    pub(crate) fn wb(&mut self, _a: u16, v: u8) {
-        self.reg = v;
+    #[test]
+    fn matrix() {
+        let held = Buttons::RIGHT | Buttons::UP | Buttons::B | Buttons::START;
+
+        // Directions, then the others, then both and neither.
+        assert_eq!(joypad(held, 0x20).rb(0xFF00), 0xE0 | 0b1010);
+        assert_eq!(joypad(held, 0x10).rb(0xFF00), 0xD0 | 0b0101);
+        assert_eq!(joypad(held, 0x00).rb(0xFF00), 0xC0);
+        assert_eq!(joypad(held, 0x30).rb(0xFF00), 0xFF);
+
+        assert_eq!(joypad(Buttons::empty(), 0x00).rb(0xFF00), 0xCF);
+        // The low nibble and top bits can't be written.
+        assert_eq!(joypad(Buttons::A, 0xDF).rb(0xFF00), 0xDE);
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut pad = joypad(Buttons::empty(), 0x20);
+
+        // Not selected, so no line falls.
+        pad.set(Buttons::A);
+        assert_eq!(pad.interrupt, 0);
+
+        // Selecting a group with a button already held pulls one low.
+        pad.wb(0xFF00, 0x10);
+        assert_eq!(pad.interrupt, 0x10);
+
+        // Letting go doesn't.
+        pad.interrupt = 0;
+        pad.set(Buttons::empty());
+        assert_eq!(pad.interrupt, 0);
+
+        pad.set(Buttons::START);
+        assert_eq!(pad.interrupt, 0x10);
+
+        // Nor does holding another button on a line that's already low.
+        let mut pad = joypad(Buttons::RIGHT, 0x00);
+        pad.set(Buttons::RIGHT | Buttons::A);
+        assert_eq!(pad.interrupt, 0);
+    }
+
+    #[test]
+    fn save_state() {
+        let joypad = joypad(Buttons::DOWN | Buttons::SELECT, 0x10);
+
+        let mut w = Writer::new();
+        joypad.save_state(&mut w);
+
+        let mut loaded = Joypad::new();
+        loaded.load_state(&mut Reader::new(&w.data)).unwrap();
+        assert_eq!(loaded.rb(0xFF00), joypad.rb(0xFF00));
     }
 }
@@ -1,100 +1,714 @@
+mod archive;
+mod bindings_dialog;
 mod cartridge;
+mod cheat_dialog;
+mod cheats;
+mod cli;
+mod config;
 mod cpu;
+mod debug_panel;
+mod debugger;
+mod disasm;
+mod error;
+mod gamepad;
+mod gdb;
+mod injector;
+mod input;
 mod joypad;
+mod library;
+mod library_dialog;
+mod link;
+mod macro_dialog;
+mod map_viewer;
+mod memory_editor;
 mod mmu;
+mod movie;
+mod movie_dialog;
+mod oam_viewer;
+mod onscreen_pad;
+mod pacer;
+mod patch;
 mod ppu;
+mod ram_search;
 mod registers;
+mod screenshot;
+mod settings_dialog;
+mod state;
 mod timer;
+mod trace;
 mod ui;
+mod vram_viewer;
 
 use crate::cpu::Cpu;
+use archive::RomFile;
+use cheats::{Cheat, Cheats};
+use cli::{Options, Parsed};
+use config::Config;
+use debugger::{DebugCommand, DebugState, Debugger};
 use eframe::egui::{Vec2, ViewportBuilder};
-use mmu::Mmu;
+use error::Error;
+use gdb::GdbStub;
+use injector::Injector;
+use joypad::Buttons;
+use link::Link;
+use mmu::{MemorySnapshot, Mmu, Space};
+use movie::Movies;
+use pacer::Pacer;
+use ppu::VideoSnapshot;
 use std::path::PathBuf;
-use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::process::ExitCode;
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError,
+};
+use std::time::Duration;
+use trace::{TraceConfig, Tracer};
 use ui::Ui;
 
-enum Press {
-    Down(u8),
-    Up(u8),
+/// What the frontend tells the emulator about input. Everything takes
+/// effect at the next frame.
+enum Input {
+    /// The buttons held down now.
+    Buttons(Buttons),
+    /// Buttons to auto-fire, and how many frames each press lasts.
+    Turbo(Buttons, u32),
+    /// Plays back one set of buttons a frame.
+    Macro(Vec<Buttons>),
+    /// Starts or stops recording a macro, which comes back as
+    /// `Event::Recorded`.
+    Record(bool),
+}
+
+/// What the frontend asks of the emulator thread, other than input.
+enum EmuCommand {
+    /// Resets into a ROM.
+    Load(RomFile),
+    Debug(DebugCommand),
+    /// Starts the GDB server on a port, or stops it.
+    Gdb(Option<u16>),
+    /// Whether to send `Event::Video` snapshots for the viewers.
+    Video(bool),
+    /// Which space to send `Event::Memory` snapshots of, if any.
+    Memory(Option<Space>),
+    /// Asks for an `Event::Ram` copy of the bus for RAM search.
+    RamSnapshot,
+    /// Replaces the cheat list, which then gets saved for the ROM.
+    Cheats(Vec<Cheat>),
+    /// Runs at this multiple of full speed, or `None` for flat out.
+    Speed(Option<f32>),
+    /// Saves to or loads from the ROM's save state.
+    SaveState,
+    LoadState,
+    /// From the settings window. The boot ROM takes effect from the next
+    /// ROM loaded.
+    Settings {
+        boot_rom: Option<PathBuf>,
+        save_dir: Option<PathBuf>,
+    },
+    /// Starts at the next frame.
+    Movie(movie::Command),
+    /// The window has closed, so the emulator thread should finish up.
+    Quit,
+}
+
+/// Things the emulator thread reports back to the frontend.
+enum Event {
+    Lockup(cpu::Lockup),
+    /// A ROM has loaded. Carries its header title.
+    Loaded(RomFile, String),
+    /// A ROM couldn't be loaded. Whatever was running keeps going.
+    LoadFailed(RomFile, Error),
+    Debug(Box<DebugState>),
+    Video(Box<VideoSnapshot>),
+    Memory(Box<MemorySnapshot>),
+    Ram(Vec<u8>),
+    /// The saved cheats for a newly loaded ROM.
+    Cheats(Vec<Cheat>),
+    /// A finished macro recording, one set of buttons a frame.
+    Recorded(Vec<Buttons>),
+    /// Where the movie is up to, every frame one is running. `None` once it
+    /// stops.
+    Movie(Option<movie::Status>),
+    MovieFailed(PathBuf, Error),
+    /// A save state couldn't be written or loaded.
+    StateFailed(PathBuf, Error),
+    /// The boot ROM from the settings couldn't be used, and the old one
+    /// stays.
+    BootRomFailed(String),
+    /// Frames emulated a second, sent once a second.
+    Fps(f32),
 }
 
 pub struct Emulator {
     pub(crate) paused: bool,
+    pub(crate) debugger: Debugger,
+    pub(crate) gdb: Option<GdbStub>,
+    pub(crate) video: bool,
+    pub(crate) memory: Option<Space>,
+    pub(crate) cheats: Cheats,
+    pub(crate) input: Injector,
+    pub(crate) movies: Movies,
+    pub(crate) pacer: Pacer,
+    // A frame has finished and the joypad wants setting for the next one.
+    new_frame: bool,
+    /// Run before every ROM if there is one.
+    pub(crate) boot_rom: Vec<u8>,
+    /// Where save states go, if not next to the ROM.
+    pub(crate) save_dir: Option<PathBuf>,
+    /// The ROM that's running.
+    pub(crate) rom: Option<RomFile>,
+    pub(crate) link: Option<Link>,
+    // From the command line, for once the emulator thread starts.
+    startup: Option<(RomFile, Option<PathBuf>)>,
+    startup_state: Option<(PathBuf, Vec<u8>)>,
 }
 
 impl Emulator {
-    fn new() -> Self {
-        Self { paused: false }
+    /// Sets up what the command line asked for, or says what's wrong with
+    /// it.
+    fn new(options: &Options) -> Result<Self, String> {
+        let boot_rom = match &options.boot_rom {
+            Some(path) => read_boot_rom(path)?,
+            None => Vec::new(),
+        };
+
+        let startup_state = match &options.load_state {
+            Some(path) => {
+                let data = std::fs::read(path)
+                    .map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+                Some((path.clone(), data))
+            }
+            None => None,
+        };
+
+        let link = match &options.link {
+            Some(addr) => Some(
+                Link::open(addr)
+                    .map_err(|e| format!("couldn't open the link cable on {addr}: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let mut pacer = Pacer::new();
+        pacer.set_speed(options.speed);
+
+        Ok(Self {
+            paused: false,
+            debugger: Debugger::new(),
+            gdb: None,
+            video: false,
+            memory: None,
+            cheats: Cheats::default(),
+            input: Injector::new(),
+            movies: Movies::new(),
+            pacer,
+            new_frame: false,
+            boot_rom,
+            save_dir: options.save_dir.clone(),
+            rom: None,
+            link,
+            startup: options
+                .rom
+                .clone()
+                .map(|rom| (RomFile::new(rom), options.patch.clone())),
+            startup_state,
+        })
     }
 
     pub(crate) fn run_cpu(
         &mut self,
         cpu: &mut Cpu,
         sender: SyncSender<Vec<u8>>,
-        receiver: Receiver<Press>,
-        fnreceiver: Receiver<PathBuf>,
+        receiver: Receiver<Input>,
+        commands: Receiver<EmuCommand>,
+        events: Sender<Event>,
     ) {
-        let file = if std::env::args().len() == 2 {
-            PathBuf::from(std::env::args().nth(1).unwrap())
-        } else {
-            loop {
-                if let Ok(file) = fnreceiver.try_recv() {
-                    break file;
-                };
+        if let Some((file, patch)) = self.startup.take() {
+            if self.load_rom(cpu, file, patch, &events) {
+                self.load_startup_state(cpu, &events);
             }
-        };
+        }
 
-        cpu.mmu = Mmu::from(file);
+        let mut lockup_reported = false;
 
         loop {
-            if let Ok(file) = fnreceiver.try_recv() {
-                cpu.reset();
-                cpu.mmu = Mmu::from(file);
+            // Nothing can run until a ROM loads.
+            let idle = self.paused || self.rom.is_none();
+
+            let cmd = if idle {
+                // Sleeps until a command comes, waking now and then for
+                // input. GDB waits for each reply before sending its next
+                // packet, so it gets answered sooner.
+                let timeout = match &self.gdb {
+                    Some(gdb) if gdb.connected() => Duration::from_millis(1),
+                    _ => Duration::from_millis(10),
+                };
+                match commands.recv_timeout(timeout) {
+                    Ok(cmd) => Some(cmd),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            } else {
+                match commands.try_recv() {
+                    Ok(cmd) => Some(cmd),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            };
+
+            match cmd {
+                Some(EmuCommand::Quit) => return,
+                Some(EmuCommand::Load(file)) => {
+                    // A new ROM can lock up and be reported all over again.
+                    let loaded = self.load_rom(cpu, file, None, &events);
+                    lockup_reported &= !loaded;
+                }
+                Some(cmd) => self.command(cmd, cpu, &events),
+                None => {}
             }
 
             if self.paused {
+                self.poll_gdb(cpu, &events);
+            }
+
+            // Taken while paused too, so buttons pressed then make it into
+            // the frame being advanced to.
+            if let Ok(input) = receiver.try_recv() {
+                if let Some(recorded) = self.input.receive(input) {
+                    _ = events.send(Event::Recorded(recorded));
+                }
+            }
+
+            if self.paused || self.rom.is_none() {
                 continue;
             }
 
-            cpu.docycle();
+            // The joypad is set as a frame starts rather than as the last one
+            // ends, which is where frame advance pauses.
+            if std::mem::take(&mut self.new_frame) {
+                let held = self.input.frame();
+                let buttons = self.movies.frame(cpu, held, &events);
+                cpu.mmu.joypad.set(buttons);
+            }
+
+            if self.debugger.active(cpu) {
+                if self.debugger.before_step(cpu) {
+                    self.paused = true;
+                    self.send_debug_state(cpu, &events);
+                    continue;
+                }
+
+                let opcode = cpu.mmu.peek(cpu.reg.pc);
+
+                cpu.step();
+
+                if self.debugger.after_step(cpu, opcode) {
+                    self.paused = true;
+                    self.send_debug_state(cpu, &events);
+                }
+            } else {
+                cpu.step();
+            }
+
+            if let Some(link) = &mut self.link {
+                link.poll(&mut cpu.mmu, cpu.cycles);
+            }
 
             if cpu.mmu.ppu.updated {
+                if let Some(fps) = self.pacer.frame(cpu.cycles) {
+                    _ = events.send(Event::Fps(fps));
+                }
+
+                // The frontend only shows the latest frame, so if it hasn't
+                // taken the last one yet this one can go.
                 let data = cpu.get_gpu_data().to_vec();
-                sender.send(data).unwrap();
+                if let Err(TrySendError::Disconnected(_)) = sender.try_send(data) {
+                    return;
+                }
                 cpu.mmu.ppu.updated = false;
+
+                self.cheats.write_ram(&mut cpu.mmu);
+                self.new_frame = true;
+
+                if let Some(lockup) = cpu.lockup.filter(|_| !lockup_reported) {
+                    _ = events.send(Event::Lockup(lockup));
+                    lockup_reported = true;
+                }
+
+                self.send_debug_state(cpu, &events);
+                self.poll_gdb(cpu, &events);
             }
+        }
+    }
 
-            if let Ok(press) = receiver.try_recv() {
-                match press {
-                    Press::Down(key) => cpu.mmu.joypad.keydown(key),
-                    Press::Up(key) => cpu.mmu.joypad.keyup(key),
+    /// Does what the frontend asked. Loading and quitting are up to the
+    /// run loop.
+    fn command(&mut self, cmd: EmuCommand, cpu: &mut Cpu, events: &Sender<Event>) {
+        match cmd {
+            EmuCommand::Debug(cmd) => {
+                self.paused = self.debugger.command(cmd, cpu, self.paused);
+                self.send_debug_state(cpu, events);
+            }
+            EmuCommand::Gdb(port) => {
+                self.gdb = port.and_then(|port| {
+                    GdbStub::listen(port)
+                        .inspect_err(|e| eprintln!("Couldn't start GDB server on port {port}: {e}"))
+                        .ok()
+                });
+                self.send_debug_state(cpu, events);
+            }
+            EmuCommand::Video(on) => {
+                self.video = on;
+                self.send_debug_state(cpu, events);
+            }
+            EmuCommand::Memory(space) => {
+                self.memory = space;
+                self.send_debug_state(cpu, events);
+            }
+            EmuCommand::RamSnapshot => {
+                _ = events.send(Event::Ram(cpu.mmu.space(Space::Bus)));
+            }
+            EmuCommand::Cheats(list) => {
+                self.cheats.list = list;
+                self.cheats.install(&mut cpu.mmu);
+                self.cheats.save();
+            }
+            EmuCommand::Speed(speed) => self.pacer.set_speed(speed),
+            EmuCommand::SaveState => self.save_state(cpu, events),
+            EmuCommand::LoadState => {
+                self.load_state(cpu, events);
+                self.send_debug_state(cpu, events);
+            }
+            EmuCommand::Settings { boot_rom, save_dir } => {
+                self.save_dir = save_dir;
+                match boot_rom.as_deref().map(read_boot_rom).transpose() {
+                    Ok(rom) => self.boot_rom = rom.unwrap_or_default(),
+                    Err(e) => {
+                        eprintln!("Couldn't change the boot ROM: {e}");
+                        _ = events.send(Event::BootRomFailed(e));
+                    }
                 }
             }
+            EmuCommand::Movie(command) => self.movies.command(command),
+            EmuCommand::Load(_) | EmuCommand::Quit => {}
+        }
+    }
+
+    /// Resets into `file`, or reports why it couldn't be loaded and leaves
+    /// the CPU alone.
+    fn load_rom(
+        &mut self,
+        cpu: &mut Cpu,
+        file: RomFile,
+        patch: Option<PathBuf>,
+        events: &Sender<Event>,
+    ) -> bool {
+        let mmu = match Mmu::from(&file, patch) {
+            Ok(mmu) => mmu,
+            Err(e) => {
+                eprintln!("Couldn't load {}: {e}", file.path.display());
+                _ = events.send(Event::LoadFailed(file, e));
+                return false;
+            }
+        };
+
+        cpu.reset();
+        cpu.mmu = mmu;
+        if !self.boot_rom.is_empty() {
+            cpu.boot(self.boot_rom.clone());
+        }
+        self.movies.rom_loaded(cpu, events);
+        self.input.rom_loaded(&mut cpu.mmu.joypad);
+        self.debugger.rom_loaded(&file);
+
+        _ = events.send(Event::Loaded(file.clone(), cpu.mmu.cart.title()));
+
+        self.cheats = Cheats::load(&file);
+        self.cheats.install(&mut cpu.mmu);
+        _ = events.send(Event::Cheats(self.cheats.list.clone()));
+        self.rom = Some(file);
+        true
+    }
+
+    /// Loads `--load-state` into the ROM that's just started.
+    fn load_startup_state(&mut self, cpu: &mut Cpu, events: &Sender<Event>) -> bool {
+        let Some((path, data)) = self.startup_state.take() else {
+            return true;
+        };
+
+        match state::load(cpu, &data) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Couldn't load {}: {e}", path.display());
+                _ = events.send(Event::StateFailed(path, e));
+                false
+            }
+        }
+    }
+
+    fn save_state(&mut self, cpu: &Cpu, events: &Sender<Event>) {
+        let Some(rom) = &self.rom else {
+            return;
+        };
+        let path = state::path(rom, self.save_dir.as_deref());
+
+        let saved = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir),
+            _ => Ok(()),
+        }
+        .and_then(|_| std::fs::write(&path, state::save(cpu)));
+
+        if let Err(e) = saved {
+            eprintln!("Couldn't save {}: {e}", path.display());
+            _ = events.send(Event::StateFailed(path, e.into()));
+        }
+    }
+
+    fn load_state(&mut self, cpu: &mut Cpu, events: &Sender<Event>) {
+        let Some(rom) = &self.rom else {
+            return;
+        };
+        let path = state::path(rom, self.save_dir.as_deref());
+
+        let loaded = std::fs::read(&path)
+            .map_err(Error::from)
+            .and_then(|data| state::load(cpu, &data));
+
+        match loaded {
+            // A movie can't carry on from somewhere else.
+            Ok(()) => self.movies.stop(events),
+            Err(e) => {
+                eprintln!("Couldn't load {}: {e}", path.display());
+                _ = events.send(Event::StateFailed(path, e));
+            }
+        }
+    }
+
+    /// Runs `frames` frames as fast as possible without a window, then
+    /// saves the last one to `screenshot`.
+    fn run_headless(
+        &mut self,
+        cpu: &mut Cpu,
+        frames: u64,
+        screenshot: Option<&std::path::Path>,
+    ) -> ExitCode {
+        // Nobody's listening, but loading reports to someone.
+        let (events, _) = mpsc::channel();
+
+        let Some((file, patch)) = self.startup.take() else {
+            return ExitCode::FAILURE;
+        };
+        if !self.load_rom(cpu, file, patch, &events) || !self.load_startup_state(cpu, &events) {
+            return ExitCode::FAILURE;
+        }
+
+        let mut done = 0;
+        while done < frames {
+            cpu.step();
+
+            if let Some(link) = &mut self.link {
+                link.poll(&mut cpu.mmu, cpu.cycles);
+            }
+
+            if cpu.mmu.ppu.updated {
+                cpu.mmu.ppu.updated = false;
+                self.cheats.write_ram(&mut cpu.mmu);
+                done += 1;
+            }
+        }
+
+        if let Some(lockup) = cpu.lockup {
+            eprintln!(
+                "The CPU locked up on illegal opcode ${:02X} at ${:04X}",
+                lockup.opcode, lockup.pc
+            );
+        }
+
+        cpu.stop_trace();
+
+        if let Some(path) = screenshot {
+            if let Err(e) = screenshot::save(path, &cpu.mmu.ppu.buffer) {
+                eprintln!("Couldn't save {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
         }
+
+        ExitCode::SUCCESS
     }
 
-    pub fn start() {
-        let mut emu = Emulator::new();
+    fn poll_gdb(&mut self, cpu: &mut Cpu, events: &Sender<Event>) {
+        let Some(gdb) = &mut self.gdb else {
+            return;
+        };
+
+        let paused = self.paused;
+        gdb.poll(cpu, &mut self.debugger, &mut self.paused);
+
+        if paused != self.paused {
+            self.send_debug_state(cpu, events);
+        }
+    }
+
+    fn send_debug_state(&self, cpu: &Cpu, events: &Sender<Event>) {
+        let mut state = self.debugger.state(cpu, self.paused);
+        state.gdb = self.gdb.as_ref().map(|gdb| gdb.port);
+        _ = events.send(Event::Debug(Box::new(state)));
+
+        if self.video {
+            let video = cpu.mmu.ppu.snapshot(cpu.mmu.cart.cgb());
+            _ = events.send(Event::Video(Box::new(video)));
+        }
+
+        if let Some(space) = self.memory {
+            let memory = MemorySnapshot {
+                space,
+                data: cpu.mmu.space(space),
+                banks: cpu.mmu.banks(),
+            };
+            _ = events.send(Event::Memory(Box::new(memory)));
+        }
+    }
+
+    /// Runs whatever the command line says to.
+    pub fn start() -> ExitCode {
+        let mut config = Config::load();
+
+        let options = match cli::parse(std::env::args().skip(1), &config) {
+            Ok(Parsed::Run(options)) => options,
+            Ok(Parsed::Help) => {
+                print!("{}", cli::HELP);
+                return ExitCode::SUCCESS;
+            }
+            Ok(Parsed::Version) => {
+                println!("oxyboy {}", env!("CARGO_PKG_VERSION"));
+                return ExitCode::SUCCESS;
+            }
+            Err(e) => {
+                eprintln!("oxyboy: {e}\nTry 'oxyboy --help' for more.");
+                return ExitCode::from(2);
+            }
+        };
+
+        let mut emu = match Emulator::new(&options) {
+            Ok(emu) => emu,
+            Err(e) => {
+                eprintln!("oxyboy: {e}");
+                return ExitCode::FAILURE;
+            }
+        };
+
+        let mut cpu = Cpu::new();
+
+        if let Some(path) = &options.trace {
+            let config = TraceConfig {
+                path: path.clone(),
+                start: None,
+                stop: None,
+                compress: path.extension().is_some_and(|ext| ext == "gz"),
+            };
+            match Tracer::create(&config) {
+                Ok(tracer) => cpu.tracer = Some(tracer),
+                Err(e) => {
+                    eprintln!("oxyboy: couldn't create {}: {e}", path.display());
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+
+        if options.headless {
+            let frames = options.frames.unwrap_or(0);
+            return emu.run_headless(&mut cpu, frames, options.screenshot.as_deref());
+        }
 
         let (graphics_tx, graphics_rx) = mpsc::sync_channel(1);
         let (joypad_tx, joypad_rx) = mpsc::channel();
-        let (filename_tx, filename_rx) = mpsc::channel::<PathBuf>();
+        let (emu_tx, emu_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
 
         let native_options = eframe::NativeOptions {
             viewport: ViewportBuilder::default()
-                .with_inner_size(Vec2::new(160.0 * 4.0, 144.0 * 4.0)),
+                .with_inner_size(Vec2::new(160.0, 144.0) * options.scale)
+                .with_fullscreen(options.fullscreen),
             ..Default::default()
         };
 
-        let mut cpu = Cpu::new();
-        std::thread::spawn(move || emu.run_cpu(&mut cpu, graphics_tx, joypad_rx, filename_rx));
+        // There's no sound yet, so this only shows in the settings window.
+        config.mute = options.mute;
+
+        let emulator = std::thread::spawn(move || {
+            emu.run_cpu(&mut cpu, graphics_tx, joypad_rx, emu_rx, event_tx);
+            cpu
+        });
 
-        eframe::run_native(
+        let ran = eframe::run_native(
             "Oxyboy",
             native_options,
-            Box::new(|_cc| Ok(Box::new(Ui::new(graphics_rx, joypad_tx, filename_tx)))),
-        )
-        .unwrap();
+            Box::new(move |_cc| {
+                Ok(Box::new(Ui::new(
+                    graphics_rx,
+                    joypad_tx,
+                    emu_tx,
+                    event_rx,
+                    options.speed,
+                    config,
+                )))
+            }),
+        );
+
+        // The window's gone, which stops the emulator, and the trace needs
+        // finishing off once it has.
+        match emulator.join() {
+            Ok(mut cpu) => cpu.stop_trace(),
+            Err(_) => return ExitCode::FAILURE,
+        }
+
+        match ran {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("oxyboy: {e}");
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+/// Reads a boot ROM, checking it's the size of a DMG one.
+fn read_boot_rom(path: &std::path::Path) -> Result<Vec<u8>, String> {
+    let rom = std::fs::read(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+
+    match rom.len() {
+        0x100 => Ok(rom),
+        0x900 => Err(format!(
+            "{} is a CGB boot ROM, and CGB hardware isn't emulated yet",
+            path.display()
+        )),
+        len => Err(format!(
+            "{} isn't a boot ROM: it should be 256 bytes, not {len}",
+            path.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_roms() {
+        let path = std::env::temp_dir().join(format!("oxyboy-{}-boot.bin", std::process::id()));
+        let read = |len| {
+            std::fs::write(&path, vec![0; len]).unwrap();
+            read_boot_rom(&path)
+        };
+
+        assert_eq!(read(0x100).unwrap().len(), 0x100);
+        assert!(read(0x900).unwrap_err().contains("is a CGB boot ROM"));
+        assert!(read(0x200)
+            .unwrap_err()
+            .contains("should be 256 bytes, not 512"));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_boot_rom(&path)
+            .unwrap_err()
+            .starts_with("couldn't read"));
     }
 }
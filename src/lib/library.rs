@@ -0,0 +1,165 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::{self, RomFile},
+    cartridge, config, state,
+};
+
+/// How many ROMs File → Recent remembers.
+const RECENT: usize = 10;
+
+/// What's in `library.toml`: the ROMs loaded lately, newest first, and the
+/// folder the library shows.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Library {
+    pub(crate) recent: Vec<RomFile>,
+    pub(crate) folder: Option<PathBuf>,
+}
+
+impl Library {
+    pub(crate) fn load() -> Library {
+        let Some(path) = config::path("library.toml") else {
+            return Library::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+                eprintln!("Couldn't parse {}: {e}", path.display());
+                Library::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Library::default(),
+            Err(e) => {
+                eprintln!("Couldn't read {}: {e}", path.display());
+                Library::default()
+            }
+        }
+    }
+
+    pub(crate) fn save(&self) {
+        let Some(path) = config::path("library.toml") else {
+            return;
+        };
+
+        let result = toml::to_string(self)
+            .map_err(io::Error::other)
+            .and_then(|text| config::write_atomic(&path, &text));
+
+        if let Err(e) = result {
+            eprintln!("Couldn't save the ROM library to {}: {e}", path.display());
+        }
+    }
+
+    /// Puts `rom` at the top of the recent list.
+    pub(crate) fn loaded(&mut self, rom: &RomFile) {
+        self.recent.retain(|r| r != rom);
+        self.recent.insert(0, rom.clone());
+        self.recent.truncate(RECENT);
+        self.save();
+    }
+}
+
+/// A ROM found in the library folder and what its header says.
+pub(crate) struct Entry {
+    pub(crate) rom: RomFile,
+    pub(crate) title: String,
+    /// The cartridge type byte.
+    pub(crate) mapper: u8,
+    /// See `cartridge::cgb_flag`.
+    pub(crate) cgb: u8,
+    /// The screen from its newest save state.
+    pub(crate) thumbnail: Option<Vec<u8>>,
+}
+
+impl Entry {
+    /// The title, or the file name if the header hasn't got one.
+    pub(crate) fn label(&self) -> String {
+        match self.title.as_str() {
+            "" => self.rom.name(),
+            title => title.to_string(),
+        }
+    }
+}
+
+/// Reads every ROM under `folder` on another thread, sorted by title.
+/// Thumbnails come from save states in `save_dir`, or next to the ROMs.
+pub(crate) fn scan(folder: PathBuf, save_dir: Option<PathBuf>) -> Receiver<Vec<Entry>> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut entries = Vec::new();
+        let mut dirs = vec![folder];
+
+        while let Some(dir) = dirs.pop() {
+            let files = match fs::read_dir(&dir) {
+                Ok(files) => files,
+                Err(e) => {
+                    eprintln!("Couldn't read {}: {e}", dir.display());
+                    continue;
+                }
+            };
+
+            for file in files.flatten() {
+                let path = file.path();
+
+                // Linked folders are left alone in case they loop.
+                if file.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    dirs.push(path);
+                } else if archive::is_loadable(&path) {
+                    add(&mut entries, &path, save_dir.as_deref());
+                }
+            }
+        }
+
+        entries.sort_by_cached_key(|e| (e.label().to_lowercase(), e.rom.name()));
+        _ = tx.send(entries);
+    });
+
+    rx
+}
+
+/// Adds the ROM at `path`, or every ROM in it if it's an archive.
+fn add(entries: &mut Vec<Entry>, path: &Path, save_dir: Option<&Path>) {
+    let roms = match archive::entries(path) {
+        Ok(names) if !names.is_empty() => names
+            .into_iter()
+            .map(|name| RomFile {
+                path: path.to_path_buf(),
+                entry: Some(name),
+            })
+            .collect(),
+        Ok(_) => vec![RomFile::new(path.to_path_buf())],
+        // An archive without a ROM in it.
+        Err(_) => return,
+    };
+
+    for rom in roms {
+        let Ok(data) = rom.read() else {
+            continue;
+        };
+        // Too short to have a header, so not a ROM.
+        if data.len() < 0x150 {
+            continue;
+        }
+
+        entries.push(Entry {
+            title: cartridge::title(&data),
+            mapper: data[0x147],
+            cgb: cartridge::cgb_flag(&data),
+            thumbnail: thumbnail(&rom, save_dir),
+            rom,
+        });
+    }
+}
+
+/// The screen from the ROM's save state.
+fn thumbnail(rom: &RomFile, save_dir: Option<&Path>) -> Option<Vec<u8>> {
+    let data = fs::read(state::path(rom, save_dir)).ok()?;
+    state::screen(&data).ok()
+}
@@ -0,0 +1,198 @@
+use std::sync::mpsc::Receiver;
+
+use eframe::egui::{self, ColorImage, Image, RichText, TextureHandle};
+
+use crate::{
+    archive::RomFile,
+    cartridge,
+    config::{Colour, Config},
+    library::{self, Entry, Library},
+    ppu::{SCREEN_H, SCREEN_W},
+    ui::SCREEN_TEXTURE,
+};
+
+/// Thumbnails are half size.
+const THUMBNAIL: egui::Vec2 = egui::vec2(SCREEN_W as f32 / 2.0, SCREEN_H as f32 / 2.0);
+
+/// The ROMs in a folder, with what their headers say and the screen from
+/// their last save state. Picking one loads it.
+pub(crate) struct LibraryDialog {
+    pub(crate) open: bool,
+    entries: Vec<Entry>,
+    // Made from `entries` as they're drawn, in this palette.
+    thumbnails: Vec<Option<TextureHandle>>,
+    palette: Option<[Colour; 4]>,
+    scanning: Option<Receiver<Vec<Entry>>>,
+    scanned: bool,
+    filter: String,
+}
+
+impl LibraryDialog {
+    pub(crate) fn new() -> Self {
+        Self {
+            open: false,
+            entries: Vec::new(),
+            thumbnails: Vec::new(),
+            palette: None,
+            scanning: None,
+            scanned: false,
+            filter: String::new(),
+        }
+    }
+
+    /// Gives back the ROM picked, if one was.
+    pub(crate) fn show(
+        &mut self,
+        ctx: &egui::Context,
+        library: &mut Library,
+        config: &Config,
+    ) -> Option<RomFile> {
+        if !self.open {
+            return None;
+        }
+
+        if !self.scanned {
+            self.scan(library, config);
+        }
+
+        if let Some(entries) = self.scanning.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.thumbnails = entries.iter().map(|_| None).collect();
+            self.entries = entries;
+            self.scanning = None;
+        }
+
+        if self.palette != Some(config.palette) {
+            self.thumbnails.fill(None);
+            self.palette = Some(config.palette);
+        }
+
+        let mut open = self.open;
+        let mut picked = None;
+
+        egui::Window::new("Library")
+            .open(&mut open)
+            .default_height(480.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    match &library.folder {
+                        Some(folder) => ui.monospace(folder.display().to_string()),
+                        None => ui.weak("No folder chosen"),
+                    };
+
+                    if ui.button("Choose folder...").clicked() {
+                        if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                            library.folder = Some(folder);
+                            library.save();
+                            self.scan(library, config);
+                        }
+                    }
+
+                    let rescan = library.folder.is_some() && self.scanning.is_none();
+                    if ui
+                        .add_enabled(rescan, egui::Button::new("Rescan"))
+                        .clicked()
+                    {
+                        self.scan(library, config);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.filter).hint_text("Filter"));
+
+                    if self.scanning.is_some() {
+                        ui.spinner();
+                        ui.label("Scanning...");
+                    } else {
+                        ui.weak(format!("{} ROMs", self.entries.len()));
+                    }
+                });
+
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    picked = self.grid(ui, config);
+                });
+            });
+
+        self.open = open;
+        picked
+    }
+
+    fn scan(&mut self, library: &Library, config: &Config) {
+        self.scanned = true;
+
+        if let Some(folder) = &library.folder {
+            self.scanning = Some(library::scan(folder.clone(), config.save_dir.clone()));
+        }
+    }
+
+    fn grid(&mut self, ui: &mut egui::Ui, config: &Config) -> Option<RomFile> {
+        let filter = self.filter.to_lowercase();
+        let mut picked = None;
+
+        egui::Grid::new("library").striped(true).show(ui, |ui| {
+            for (entry, texture) in self.entries.iter().zip(&mut self.thumbnails) {
+                let name = entry.rom.name();
+                let title = entry.label();
+
+                let shown = [&title, &name]
+                    .iter()
+                    .any(|s| s.to_lowercase().contains(&filter));
+                if !shown {
+                    continue;
+                }
+
+                thumbnail(ui, entry, texture, config);
+
+                ui.vertical(|ui| {
+                    let link = ui
+                        .link(RichText::new(title).strong())
+                        .on_hover_text(entry.rom.path.display().to_string());
+                    if link.clicked() {
+                        picked = Some(entry.rom.clone());
+                    }
+                    ui.weak(&name);
+                });
+
+                let mapper = cartridge::mapper_name(entry.mapper);
+                if cartridge::supported(entry.mapper) {
+                    ui.label(mapper);
+                } else {
+                    ui.weak(mapper).on_hover_text("Not emulated yet");
+                }
+
+                ui.label(match entry.cgb {
+                    0xC0 => "CGB only",
+                    0x80 => "CGB",
+                    _ => "",
+                });
+
+                ui.end_row();
+            }
+        });
+
+        picked
+    }
+}
+
+/// Draws the thumbnail, or an empty box if there's no save state.
+fn thumbnail(
+    ui: &mut egui::Ui,
+    entry: &Entry,
+    texture: &mut Option<TextureHandle>,
+    config: &Config,
+) {
+    let Some(screen) = &entry.thumbnail else {
+        let (rect, _) = ui.allocate_exact_size(THUMBNAIL, egui::Sense::hover());
+        ui.painter()
+            .rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+        return;
+    };
+
+    let texture = texture.get_or_insert_with(|| {
+        let image = ColorImage::from_rgb([SCREEN_W, SCREEN_H], &config.colourise(screen));
+        ui.ctx().load_texture("Thumbnail", image, SCREEN_TEXTURE)
+    });
+
+    ui.add(Image::from_texture(&*texture).fit_to_exact_size(THUMBNAIL));
+}
@@ -0,0 +1,148 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::mmu::Mmu;
+
+/// How often, in cycles, the connection is checked. About a scanline.
+const POLL_CYCLES: u64 = 456;
+
+/// Sends our byte from the side doing the clocking.
+const SEND: u8 = 0;
+/// Answers `SEND` with our byte.
+const REPLY: u8 = 1;
+
+/// A link cable to another emulator over TCP. Whichever side starts a
+/// transfer on its internal clock sends its byte, and the other side swaps
+/// it for its own. Messages are a kind byte followed by the serial byte.
+pub(crate) struct Link {
+    listener: Option<TcpListener>,
+    stream: Option<TcpStream>,
+    // Part of a message.
+    partial: Vec<u8>,
+    next_poll: u64,
+}
+
+impl Link {
+    /// Connects to `addr`, or listens there if nobody is.
+    pub(crate) fn open(addr: &str) -> io::Result<Self> {
+        let (listener, stream) = match TcpStream::connect(addr) {
+            Ok(stream) => (None, Some(stream)),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                eprintln!("Waiting for the other end of the link cable on {addr}");
+                (Some(listener), None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut link = Self {
+            listener,
+            stream: None,
+            partial: Vec::new(),
+            next_poll: 0,
+        };
+
+        if let Some(stream) = stream {
+            link.connected(stream)?;
+        }
+
+        Ok(link)
+    }
+
+    fn connected(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        self.stream = Some(stream);
+        self.partial.clear();
+        Ok(())
+    }
+
+    /// Sends and receives whatever's waiting. Call after every step; it
+    /// only does anything once every `POLL_CYCLES`.
+    pub(crate) fn poll(&mut self, mmu: &mut Mmu, cycles: u64) {
+        if cycles < self.next_poll {
+            return;
+        }
+        self.next_poll = cycles + POLL_CYCLES;
+
+        if self.stream.is_none() {
+            self.accept();
+        }
+
+        if let Err(e) = self.exchange(mmu) {
+            eprintln!("The link cable disconnected: {e}");
+            self.stream = None;
+        }
+    }
+
+    fn accept(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                eprintln!("Link cable connected to {addr}");
+                if let Err(e) = self.connected(stream) {
+                    eprintln!("Couldn't set up the link cable: {e}");
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => eprintln!("Couldn't accept a link cable connection: {e}"),
+        }
+    }
+
+    fn exchange(&mut self, mmu: &mut Mmu) -> io::Result<()> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(());
+        };
+
+        if std::mem::take(&mut mmu.serial_started) {
+            stream.write_all(&[SEND, mmu.serial_data[0]])?;
+        }
+
+        let mut buf = [0; 64];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.partial.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let messages = self.partial.len() / 2 * 2;
+
+        for message in self.partial.drain(..messages).collect::<Vec<_>>().chunks(2) {
+            let [kind, byte] = [message[0], message[1]];
+            let control = mmu.serial_data[1];
+
+            match kind {
+                SEND => {
+                    stream.write_all(&[REPLY, mmu.serial_data[0]])?;
+                    mmu.serial_data[0] = byte;
+
+                    // Only finishes if we were waiting on the other clock.
+                    if control & 0x81 == 0x80 {
+                        finish(mmu);
+                    }
+                }
+                REPLY => {
+                    mmu.serial_data[0] = byte;
+                    finish(mmu);
+                }
+                _ => return Err(io::Error::other("garbled message")),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn finish(mmu: &mut Mmu) {
+    mmu.serial_data[1] &= 0x7F;
+    mmu.intf |= 0x08;
+}
@@ -0,0 +1,157 @@
+use std::sync::mpsc::Sender;
+
+use eframe::egui::{self, Key};
+
+use crate::{
+    bindings_dialog::pressed_key,
+    input::{Keyboard, Macro},
+    joypad::Buttons,
+    Input,
+};
+
+/// Turbo settings, and recording macros and binding them to keys. Changes
+/// are saved straight away.
+pub(crate) struct MacroDialog {
+    pub(crate) open: bool,
+    tx: Sender<Input>,
+    recording: bool,
+    // Waiting for a key for this macro.
+    capturing: Option<usize>,
+}
+
+impl MacroDialog {
+    pub(crate) fn new(tx: Sender<Input>) -> Self {
+        Self {
+            open: false,
+            tx,
+            recording: false,
+            capturing: None,
+        }
+    }
+
+    /// Whether the next key press is for the dialog rather than the game.
+    pub(crate) fn capturing(&self) -> bool {
+        self.open && self.capturing.is_some()
+    }
+
+    /// Adds a finished recording to the list.
+    pub(crate) fn recorded(&mut self, frames: &[Buttons], keyboard: &mut Keyboard) {
+        let macros = &mut keyboard.bindings.macros;
+        let recorded = Macro::from_frames(format!("Macro {}", macros.len() + 1), frames);
+
+        if recorded.steps.is_empty() {
+            return;
+        }
+
+        macros.push(recorded);
+        keyboard.bindings.save();
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context, keyboard: &mut Keyboard) {
+        let mut open = self.open;
+        let mut changed = false;
+
+        if let Some(i) = self.capturing {
+            changed |= self.capture(ctx, keyboard, i);
+        }
+
+        egui::Window::new("Turbo and macros")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let turbo = &mut keyboard.bindings.turbo;
+
+                ui.horizontal(|ui| {
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut turbo.rate).range(1..=30))
+                        .changed();
+                    ui.label("frames per turbo press");
+                });
+                changed |= ui
+                    .checkbox(&mut turbo.toggle, "Turbo keys toggle auto-fire")
+                    .changed();
+
+                ui.separator();
+                changed |= self.list(ui, keyboard);
+
+                let label = if self.recording {
+                    "Stop recording"
+                } else {
+                    "Record"
+                };
+
+                if ui.button(label).clicked() {
+                    self.recording = !self.recording;
+                    _ = self.tx.send(Input::Record(self.recording));
+                }
+            });
+
+        self.open = open;
+
+        if !self.open {
+            self.capturing = None;
+        }
+
+        if changed {
+            keyboard.bindings.save();
+        }
+    }
+
+    fn list(&mut self, ui: &mut egui::Ui, keyboard: &mut Keyboard) -> bool {
+        let macros = &mut keyboard.bindings.macros;
+        let mut changed = false;
+        let mut remove = None;
+
+        egui::Grid::new("macros").striped(true).show(ui, |ui| {
+            for (i, m) in macros.iter_mut().enumerate() {
+                changed |= ui
+                    .add(egui::TextEdit::singleline(&mut m.name).desired_width(100.0))
+                    .lost_focus();
+
+                let frames: u32 = m.steps.iter().map(|s| s.frames).sum();
+                ui.label(format!("{frames} frames"));
+
+                if self.capturing == Some(i) {
+                    ui.label("Press a key");
+                } else if ui
+                    .button(m.key.as_deref().unwrap_or("Bind key"))
+                    .on_hover_text("Esc unbinds")
+                    .clicked()
+                {
+                    self.capturing = Some(i);
+                }
+
+                if ui.button("Play").clicked() {
+                    _ = self.tx.send(Input::Macro(m.frames()));
+                }
+                if ui.small_button("x").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+
+        if let Some(i) = remove {
+            macros.remove(i);
+            self.capturing = None;
+            changed = true;
+        }
+
+        changed
+    }
+
+    fn capture(&mut self, ctx: &egui::Context, keyboard: &mut Keyboard, i: usize) -> bool {
+        let Some(key) = pressed_key(ctx) else {
+            return false;
+        };
+
+        self.capturing = None;
+
+        let Some(m) = keyboard.bindings.macros.get_mut(i) else {
+            return false;
+        };
+
+        m.key = (key != Key::Escape).then(|| key.name().to_string());
+        true
+    }
+}
@@ -0,0 +1,181 @@
+use eframe::egui::{self, Color32, ColorImage, Rect, Sense, Stroke, TextureHandle, Vec2};
+
+use crate::{
+    ppu::{map_tile, shade, tile_pixel, Lcdc, VideoSnapshot, SCREEN_H, SCREEN_W},
+    ui::SCREEN_TEXTURE,
+};
+
+const MAP_SIZE: usize = 256;
+const SCALE: f32 = 2.0;
+
+const VIEWPORT: Color32 = Color32::from_rgb(0xE0, 0x30, 0x30);
+const WINDOW: Color32 = Color32::from_rgb(0x30, 0x80, 0xE0);
+
+/// Shows a whole 32x32 tile map, with the screen's view of it drawn on top.
+pub(crate) struct MapViewer {
+    pub(crate) open: bool,
+    // None follows whichever map the background uses.
+    map: Option<u16>,
+    texture: Option<TextureHandle>,
+}
+
+impl MapViewer {
+    pub(crate) fn new() -> Self {
+        Self {
+            open: false,
+            map: None,
+            texture: None,
+        }
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context, video: Option<&VideoSnapshot>) {
+        let mut open = self.open;
+
+        egui::Window::new("Tile maps")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let Some(video) = video else {
+                    ui.label("Waiting for the emulator...");
+                    return;
+                };
+
+                let lcdc = Lcdc::from_bits_retain(video.lcdc);
+                let bg_map = if lcdc.contains(Lcdc::BG_TILEMAP) {
+                    0x9C00
+                } else {
+                    0x9800
+                };
+                let win_map = if lcdc.contains(Lcdc::WIN_TILEMAP) {
+                    0x9C00
+                } else {
+                    0x9800
+                };
+
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.map, None, "Background");
+                    ui.selectable_value(&mut self.map, Some(0x9800), "$9800");
+                    ui.selectable_value(&mut self.map, Some(0x9C00), "$9C00");
+                });
+
+                let map = self.map.unwrap_or(bg_map);
+
+                ui.label(format!(
+                    "BG ${bg_map:04X}, window ${win_map:04X}, tiles at ${:04X}",
+                    if lcdc.contains(Lcdc::TILE_DATA) {
+                        0x8000
+                    } else {
+                        0x8800
+                    }
+                ));
+
+                let image = render(video, map);
+
+                let texture = match &mut self.texture {
+                    Some(texture) => {
+                        texture.set(image, SCREEN_TEXTURE);
+                        texture
+                    }
+                    None => {
+                        self.texture
+                            .insert(ctx.load_texture("Tile map", image, SCREEN_TEXTURE))
+                    }
+                };
+
+                let size = Vec2::splat(MAP_SIZE as f32 * SCALE);
+                let (rect, response) = ui.allocate_exact_size(size, Sense::hover());
+                egui::Image::from_texture(&*texture).paint_at(ui, rect);
+
+                let painter = ui.painter_at(rect);
+
+                if map == bg_map {
+                    // The viewport wraps around the map, so draw it from each
+                    // corner and let the painter clip what's off the edge.
+                    for dy in [0.0, -(MAP_SIZE as f32)] {
+                        for dx in [0.0, -(MAP_SIZE as f32)] {
+                            let min = Vec2::new(video.scx as f32 + dx, video.scy as f32 + dy);
+                            let screen = Rect::from_min_size(
+                                rect.min + min * SCALE,
+                                Vec2::new(SCREEN_W as f32, SCREEN_H as f32) * SCALE,
+                            );
+                            painter.rect_stroke(screen, 0.0, Stroke::new(2.0, VIEWPORT));
+                        }
+                    }
+                }
+
+                let window_shown = lcdc.contains(Lcdc::WIN_ON)
+                    && video.winx <= 166
+                    && (video.winy as usize) < SCREEN_H;
+
+                if map == win_map && window_shown {
+                    // The window draws its map from the top left corner.
+                    let visible = Vec2::new(
+                        (SCREEN_W + 7) as f32 - video.winx as f32,
+                        (SCREEN_H - video.winy as usize) as f32,
+                    );
+                    let window = Rect::from_min_size(rect.min, visible * SCALE);
+                    painter.rect_stroke(window, 0.0, Stroke::new(2.0, WINDOW));
+                }
+
+                match response.hover_pos() {
+                    Some(pos) => {
+                        let cell = (pos - rect.min) / (8.0 * SCALE);
+                        let (x, y) = (cell.x as u16 % 32, cell.y as u16 % 32);
+                        describe_tile(ui, video, map, x, y);
+                    }
+                    None => {
+                        ui.label("Hover a tile to see its attributes");
+                    }
+                }
+            });
+
+        self.open = open;
+    }
+}
+
+fn describe_tile(ui: &mut egui::Ui, video: &VideoSnapshot, map: u16, x: u16, y: u16) {
+    let entry = map + y * 32 + x;
+    let tile_num = video.vram[(entry - 0x8000) as usize];
+    let tile = map_tile(&video.vram, video.lcdc, map, x, y);
+
+    ui.label(format!(
+        "({x}, {y}) at ${entry:04X}: tile ${tile_num:02X}, data at ${:04X}",
+        0x8000 + tile
+    ));
+
+    // The attribute map is in bank 1, behind the tile map.
+    if video.cgb {
+        let attr = video.vram[0x2000 + (entry - 0x8000) as usize];
+        ui.label(format!(
+            "Palette {}, bank {}{}{}{}",
+            attr & 0b111,
+            (attr >> 3) & 1,
+            if attr & 0x20 != 0 { ", x flip" } else { "" },
+            if attr & 0x40 != 0 { ", y flip" } else { "" },
+            if attr & 0x80 != 0 { ", priority" } else { "" },
+        ));
+    }
+}
+
+/// Draws the map through BGP using the current tile data addressing.
+fn render(video: &VideoSnapshot, map: u16) -> ColorImage {
+    let mut rgb = vec![0; MAP_SIZE * MAP_SIZE * 3];
+
+    for tiley in 0..32 {
+        for tilex in 0..32 {
+            let tile = map_tile(&video.vram, video.lcdc, map, tilex, tiley);
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    let colour = shade(video.palb, tile_pixel(&video.vram, tile, x, y));
+                    let px = tilex as usize * 8 + x as usize;
+                    let py = tiley as usize * 8 + y as usize;
+                    let i = (py * MAP_SIZE + px) * 3;
+                    rgb[i..i + 3].fill(colour);
+                }
+            }
+        }
+    }
+
+    ColorImage::from_rgb([MAP_SIZE, MAP_SIZE], &rgb)
+}
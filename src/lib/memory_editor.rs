@@ -0,0 +1,319 @@
+use std::sync::mpsc::Sender;
+
+use eframe::egui::{self, Color32, ComboBox, DragValue, Key, RichText, Sense, TextStyle};
+
+use crate::{
+    debugger::{parse_number, DebugCommand},
+    mmu::{MemorySnapshot, Space},
+    EmuCommand,
+};
+
+const ROW_LEN: usize = 16;
+const SELECTED: Color32 = Color32::from_rgb(0xE0, 0xA0, 0x30);
+
+/// A hex view of any `Space`, edited through `Mmu::poke`.
+pub(crate) struct MemoryEditor {
+    pub(crate) open: bool,
+    tx: Sender<EmuCommand>,
+    space: Space,
+    // The space the emulator has been asked to send, if any.
+    requested: Option<Space>,
+    selected: Option<usize>,
+    scroll_to: Option<usize>,
+    goto: String,
+    search: String,
+    edit: String,
+    error: Option<String>,
+}
+
+impl MemoryEditor {
+    pub(crate) fn new(tx: Sender<EmuCommand>) -> Self {
+        Self {
+            open: false,
+            tx,
+            space: Space::Bus,
+            requested: None,
+            selected: None,
+            scroll_to: None,
+            goto: String::new(),
+            search: String::new(),
+            edit: String::new(),
+            error: None,
+        }
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context, memory: Option<&MemorySnapshot>) {
+        let mut open = self.open;
+
+        egui::Window::new("Memory")
+            .open(&mut open)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                let memory = memory.filter(|m| m.space == self.space);
+
+                self.space_picker(ui, memory);
+
+                let Some(memory) = memory else {
+                    ui.label("Waiting for the emulator...");
+                    return;
+                };
+
+                self.toolbar(ui, memory);
+
+                if let Some(e) = &self.error {
+                    ui.colored_label(Color32::RED, e);
+                }
+
+                ui.separator();
+                self.hex(ui, memory);
+            });
+
+        self.open = open;
+
+        let wanted = self.open.then_some(self.space);
+
+        if wanted != self.requested {
+            _ = self.tx.send(EmuCommand::Memory(wanted));
+            self.requested = wanted;
+        }
+    }
+
+    fn space_picker(&mut self, ui: &mut egui::Ui, memory: Option<&MemorySnapshot>) {
+        let banks = memory.map(|m| m.banks).unwrap_or_default();
+
+        ui.horizontal(|ui| {
+            let before = self.space;
+
+            ComboBox::from_id_source("space")
+                .selected_text(kind(self.space))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.space, Space::Bus, "Bus");
+                    ui.selectable_value(&mut self.space, Space::Rom(0), "ROM");
+                    ui.selectable_value(&mut self.space, Space::Sram(0), "SRAM");
+                    ui.selectable_value(&mut self.space, Space::Wram(0), "WRAM");
+                });
+
+            match &mut self.space {
+                Space::Bus => {}
+                Space::Rom(bank) => {
+                    ui.label("Bank");
+                    ui.add(DragValue::new(bank).range(0..=banks.rom.max(1) - 1));
+                }
+                Space::Sram(bank) => {
+                    ui.label("Bank");
+                    ui.add(DragValue::new(bank).range(0..=banks.sram.max(1) - 1));
+                }
+                Space::Wram(bank) => {
+                    ui.label("Bank");
+                    ui.add(DragValue::new(bank).range(0..=banks.wram.max(1) - 1));
+                }
+            }
+
+            if self.space != before {
+                self.selected = None;
+                self.error = None;
+            }
+        });
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui, memory: &MemorySnapshot) {
+        ui.horizontal(|ui| {
+            let goto = ui.add(
+                egui::TextEdit::singleline(&mut self.goto)
+                    .hint_text("$C000")
+                    .desired_width(60.0),
+            );
+
+            if ui.button("Go to").clicked()
+                || (goto.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)))
+            {
+                match parse_number(&self.goto) {
+                    Some(addr) => self.go_to_addr(addr, memory),
+                    None => self.error = Some(format!("Bad address {:?}", self.goto)),
+                }
+            }
+
+            let search = ui.add(
+                egui::TextEdit::singleline(&mut self.search)
+                    .hint_text("3E 01 or \"text\"")
+                    .desired_width(120.0),
+            );
+
+            if ui.button("Find next").clicked()
+                || (search.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)))
+            {
+                self.find_next(memory);
+            }
+        });
+
+        let Some(offset) = self.selected.filter(|&o| o < memory.data.len()) else {
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            let addr = memory.space.base() as usize + offset;
+            ui.monospace(format!("${addr:04X} ="));
+
+            let edit = ui.add(egui::TextEdit::singleline(&mut self.edit).desired_width(30.0));
+
+            if edit.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                match u8::from_str_radix(self.edit.trim().trim_start_matches('$'), 16) {
+                    Ok(v) => {
+                        _ = self.tx.send(EmuCommand::Debug(DebugCommand::Poke(
+                            memory.space,
+                            offset,
+                            v,
+                        )));
+                        self.error = None;
+                    }
+                    Err(_) => self.error = Some(format!("Bad byte {:?}", self.edit)),
+                }
+            }
+
+            if ui.button("Follow pointer").clicked() {
+                self.follow_pointer(offset, memory);
+            }
+        });
+    }
+
+    fn hex(&mut self, ui: &mut egui::Ui, memory: &MemorySnapshot) {
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        let rows = memory.data.len().div_ceil(ROW_LEN);
+
+        let mut scroll = egui::ScrollArea::vertical().auto_shrink(false);
+
+        if let Some(offset) = self.scroll_to.take() {
+            let spacing = ui.spacing().item_spacing.y;
+            scroll =
+                scroll.vertical_scroll_offset((offset / ROW_LEN) as f32 * (row_height + spacing));
+        }
+
+        scroll.show_rows(ui, row_height, rows, |ui, visible| {
+            for row in visible {
+                let start = row * ROW_LEN;
+                let bytes = &memory.data[start..(start + ROW_LEN).min(memory.data.len())];
+
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 4.0;
+
+                    let addr = memory.space.base() as usize + start;
+                    ui.monospace(RichText::new(format!("{addr:04X}")).weak());
+
+                    for (i, &b) in bytes.iter().enumerate() {
+                        let offset = start + i;
+                        let mut text = RichText::new(format!("{b:02X}")).monospace();
+
+                        if self.selected == Some(offset) {
+                            text = text.color(SELECTED).strong();
+                        }
+
+                        if ui
+                            .add(egui::Label::new(text).sense(Sense::click()))
+                            .clicked()
+                        {
+                            self.select(offset, memory);
+                        }
+                    }
+
+                    let ascii: String = bytes
+                        .iter()
+                        .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                        .collect();
+                    ui.monospace(ascii);
+                });
+            }
+        });
+    }
+
+    fn select(&mut self, offset: usize, memory: &MemorySnapshot) {
+        self.selected = Some(offset);
+        self.edit = format!("{:02X}", memory.data[offset]);
+    }
+
+    fn go_to_addr(&mut self, addr: u16, memory: &MemorySnapshot) {
+        let offset = (addr as usize).checked_sub(memory.space.base() as usize);
+
+        match offset.filter(|&o| o < memory.data.len()) {
+            Some(offset) => {
+                self.select(offset, memory);
+                self.scroll_to = Some(offset);
+                self.error = None;
+            }
+            None => self.error = Some(format!("${addr:04X} isn't in this view")),
+        }
+    }
+
+    /// Jumps to the little endian word at `offset`, on the bus.
+    fn follow_pointer(&mut self, offset: usize, memory: &MemorySnapshot) {
+        let (Some(&lo), Some(&hi)) = (memory.data.get(offset), memory.data.get(offset + 1)) else {
+            self.error = Some("No pointer past the end".into());
+            return;
+        };
+
+        let addr = u16::from_le_bytes([lo, hi]);
+
+        if memory.space == Space::Bus {
+            self.go_to_addr(addr, memory);
+            return;
+        }
+
+        // The bus snapshot hasn't arrived yet, so select it blind.
+        self.space = Space::Bus;
+        self.selected = Some(addr as usize);
+        self.scroll_to = Some(addr as usize);
+        self.edit.clear();
+    }
+
+    fn find_next(&mut self, memory: &MemorySnapshot) {
+        let Some(needle) = parse_pattern(&self.search) else {
+            self.error = Some(format!("Bad search {:?}", self.search));
+            return;
+        };
+
+        let data = &memory.data;
+        let from = self.selected.map_or(0, |o| o + 1);
+
+        let found = (from..data.len())
+            .chain(0..from.min(data.len()))
+            .find(|&i| data[i..].starts_with(&needle));
+
+        match found {
+            Some(offset) => {
+                self.select(offset, memory);
+                self.scroll_to = Some(offset);
+                self.error = None;
+            }
+            None => self.error = Some("Not found".into()),
+        }
+    }
+}
+
+fn kind(space: Space) -> &'static str {
+    match space {
+        Space::Bus => "Bus",
+        Space::Rom(_) => "ROM",
+        Space::Sram(_) => "SRAM",
+        Space::Wram(_) => "WRAM",
+    }
+}
+
+/// Either `"text"` or hex bytes, optionally space separated.
+fn parse_pattern(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+
+    if let Some(text) = s.strip_prefix('"') {
+        let text = text.strip_suffix('"').unwrap_or(text);
+        return (!text.is_empty()).then(|| text.as_bytes().to_vec());
+    }
+
+    let hex: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
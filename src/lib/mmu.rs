@@ -1,6 +1,52 @@
 use std::path::PathBuf;
 
-use crate::{cartridge::Cartridge, joypad::Joypad, ppu::Ppu, timer::Timer};
+use crate::{
+    archive::RomFile,
+    cartridge::Cartridge,
+    debugger::{Access, WatchHit, Watchpoint},
+    error::Result,
+    joypad::Joypad,
+    ppu::Ppu,
+    state::{Reader, Writer},
+    timer::Timer,
+};
+
+/// Memory the memory editor can look at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Space {
+    /// Everything the CPU can see, as it sees it.
+    Bus,
+    Rom(u16),
+    Sram(u8),
+    Wram(u8),
+}
+
+impl Space {
+    /// The address the start of the space shows up at on the bus.
+    pub(crate) fn base(self) -> u16 {
+        match self {
+            Space::Bus | Space::Rom(0) => 0x0000,
+            Space::Rom(_) => 0x4000,
+            Space::Sram(_) => 0xA000,
+            Space::Wram(0) => 0xC000,
+            Space::Wram(_) => 0xD000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Banks {
+    pub(crate) rom: usize,
+    pub(crate) sram: usize,
+    pub(crate) wram: usize,
+}
+
+/// A copy of one `Space` for the memory editor.
+pub(crate) struct MemorySnapshot {
+    pub(crate) space: Space,
+    pub(crate) data: Vec<u8>,
+    pub(crate) banks: Banks,
+}
 
 pub(crate) struct Mmu {
     pub(crate) cart: Cartridge,
@@ -10,6 +56,15 @@ pub(crate) struct Mmu {
     pub(crate) serial_data: [u8; 2],
     pub(crate) inte: u8,
     pub(crate) intf: u8,
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    pub(crate) watch_hit: Option<WatchHit>,
+    /// Addresses held at a value no matter what gets written there.
+    pub(crate) frozen: Vec<(u16, u8)>,
+    pub(crate) boot_rom: Vec<u8>,
+    /// The boot ROM is over the cartridge until it writes to $FF50.
+    pub(crate) booting: bool,
+    /// A transfer was started on the internal clock, for the link cable.
+    pub(crate) serial_started: bool,
 }
 
 impl Mmu {
@@ -22,24 +77,81 @@ impl Mmu {
             timer: Timer::new(),
             inte: 0,
             intf: 0,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            frozen: Vec::new(),
+            boot_rom: Vec::new(),
+            booting: false,
+            serial_started: false,
         }
     }
 
-    pub(crate) fn from(cart: PathBuf) -> Self {
-        Self {
-            cart: Cartridge::from(cart),
+    pub(crate) fn from(cart: &RomFile, patch: Option<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            cart: Cartridge::from(cart, patch)?,
             serial_data: [0, 0],
             ppu: Ppu::new(),
             joypad: Joypad::new(),
             timer: Timer::new(),
             inte: 0,
             intf: 0,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            frozen: Vec::new(),
+            boot_rom: Vec::new(),
+            booting: false,
+            serial_started: false,
+        })
+    }
+
+    /// The machine state behind the bus. Watchpoints and frozen addresses
+    /// belong to the debugger and stay as they are.
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.serial_data);
+        w.u8(self.inte);
+        w.u8(self.intf);
+        w.bool(self.booting);
+
+        self.cart.save_state(w);
+        self.timer.save_state(w);
+        self.ppu.save_state(w);
+        self.joypad.save_state(w);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut Reader) -> Result<()> {
+        r.fill(&mut self.serial_data)?;
+        self.inte = r.u8()?;
+        self.intf = r.u8()?;
+        self.booting = r.bool()? && !self.boot_rom.is_empty();
+
+        self.cart.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.joypad.load_state(r)
+    }
+
+    /// A CPU read. Unlike `peek`, this can trip a watchpoint.
+    #[inline(always)]
+    pub(crate) fn rb(&mut self, a: u16) -> u8 {
+        let v = self.peek(a);
+
+        if !self.watchpoints.is_empty() {
+            self.watch(a, v, Access::Read);
         }
+
+        v
     }
 
     #[inline(always)]
-    pub(crate) fn rb(&self, a: u16) -> u8 {
+    pub(crate) fn peek(&self, a: u16) -> u8 {
+        if !self.frozen.is_empty() {
+            if let Some(&(_, v)) = self.frozen.iter().find(|&&(f, _)| f == a) {
+                return v;
+            }
+        }
+
         match a {
+            0x0000..0x0100 if self.booting => self.boot_rom[a as usize],
             0x0000..0x8000 => self.cart.mbc.read(a),
             0x8000..0xA000 => self.ppu.rb(a),
             0xA000..0xC000 => self.cart.mbc.read(a),
@@ -61,8 +173,83 @@ impl Mmu {
         }
     }
 
+    /// A CPU write. Unlike `poke`, this can trip a watchpoint.
     #[inline(always)]
     pub(crate) fn wb(&mut self, a: u16, v: u8) {
+        if !self.watchpoints.is_empty() {
+            self.watch(a, v, Access::Write);
+        }
+
+        self.write(a, v);
+    }
+
+    /// A debugger write. ROM and cartridge RAM are changed directly rather
+    /// than through the mapper, so nothing gets bank switched.
+    pub(crate) fn poke(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..0x8000 | 0xA000..0xC000 => self.cart.mbc.poke(a, v),
+            // I/O registers don't exist apart from what writing them does.
+            _ => self.write(a, v),
+        }
+    }
+
+    /// A copy of `space`, or as much of it as exists.
+    pub(crate) fn space(&self, space: Space) -> Vec<u8> {
+        match space {
+            Space::Bus => (0..=0xFFFF).map(|a| self.peek(a)).collect(),
+            _ => {
+                let (memory, range) = self.raw(space);
+                memory.get(range).unwrap_or_default().to_vec()
+            }
+        }
+    }
+
+    pub(crate) fn poke_space(&mut self, space: Space, offset: usize, v: u8) {
+        if space == Space::Bus {
+            self.poke(offset as u16, v);
+            return;
+        }
+
+        let range = self.raw(space).1;
+        let memory = match space {
+            Space::Rom(_) => &mut self.cart.mbc.rom[..],
+            Space::Sram(_) => &mut self.cart.mbc.ram[..],
+            Space::Wram(_) => &mut self.cart.wram[..],
+            Space::Bus => unreachable!(),
+        };
+
+        if let Some(b) = memory
+            .get_mut(range.start + offset)
+            .filter(|_| offset < range.len())
+        {
+            *b = v;
+        }
+    }
+
+    /// How many banks of each kind the cartridge has.
+    pub(crate) fn banks(&self) -> Banks {
+        Banks {
+            rom: self.cart.mbc.rom.len().div_ceil(0x4000),
+            sram: self.cart.mbc.ram.len() / 0x2000,
+            wram: if self.cart.cgb() { 8 } else { 2 },
+        }
+    }
+
+    fn raw(&self, space: Space) -> (&[u8], std::ops::Range<usize>) {
+        let (memory, bank, len) = match space {
+            Space::Rom(bank) => (&self.cart.mbc.rom[..], bank as usize, 0x4000),
+            Space::Sram(bank) => (&self.cart.mbc.ram[..], bank as usize, 0x2000),
+            Space::Wram(bank) => (&self.cart.wram[..], bank as usize, 0x1000),
+            Space::Bus => (&[][..], 0, 0),
+        };
+
+        let start = (bank * len).min(memory.len());
+        let end = (start + len).min(memory.len());
+        (memory, start..end)
+    }
+
+    #[inline(always)]
+    fn write(&mut self, a: u16, v: u8) {
         match a {
             0x0000..0x8000 => self.cart.mbc.write(a, v),
             0x8000..0xA000 => self.ppu.wb(a, v),
@@ -75,7 +262,11 @@ impl Mmu {
             0xFF00..0xFF80 => match a {
                 0xFF00 => self.joypad.wb(a, v),
                 0xFF01 => self.serial_data[0] = v,
-                0xFF02 => self.serial_data[1] = v,
+                0xFF02 => {
+                    self.serial_data[1] = v;
+                    self.serial_started = v & 0x81 == 0x81;
+                }
+                0xFF50 if v != 0 => self.booting = false,
                 0xFF04..=0xFF07 => self.timer.wb(a, v),
                 0xFF40..=0xFF4B => self.ppu.wb(a, v),
                 0xFF0F => self.intf = v,
@@ -86,26 +277,33 @@ impl Mmu {
         };
     }
 
-    fn dma(&mut self, value: u8) {
-        let base = (value as u16) << 8;
-
-        for i in 0..0xA0 {
-            let b = self.rb(base + i);
-            self.wb(0xFE00 + i, b);
+    /// The bank `a` currently maps to, as RGBDS numbers them.
+    pub(crate) fn bank_of(&self, a: u16) -> u16 {
+        match a {
+            0x4000..0x8000 => self.cart.mbc.rom_bank,
+            0xA000..0xC000 => self.cart.mbc.ram_bank as u16,
+            0xD000..0xE000 => 1,
+            _ => 0,
         }
     }
 
-    #[inline(always)]
-    pub(crate) fn rw(&self, address: u16) -> u16 {
-        let low = self.rb(address) as u16;
-        let high = self.rb(address + 1) as u16;
-        low | high << 8
+    fn watch(&mut self, a: u16, value: u8, access: Access) {
+        if self.watchpoints.iter().any(|w| w.hit(a, access)) {
+            self.watch_hit = Some(WatchHit {
+                addr: a,
+                value,
+                access,
+            });
+        }
     }
 
-    #[inline(always)]
-    pub(crate) fn ww(&mut self, a: u16, v: u16) {
-        self.wb(a, (v & 0xFF) as u8);
-        self.wb(a + 1, (v >> 8) as u8);
+    fn dma(&mut self, value: u8) {
+        let base = (value as u16) << 8;
+
+        for i in 0..0xA0 {
+            let b = self.peek(base + i);
+            self.ppu.wb(0xFE00 + i, b);
+        }
     }
 
     #[inline(always)]
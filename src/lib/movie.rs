@@ -0,0 +1,633 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+};
+
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+use crate::{
+    cpu::Cpu,
+    error::{Error, Result},
+    joypad::Buttons,
+    patch::crc32,
+    state::{self, Reader, Writer},
+    Event,
+};
+
+const MAGIC: &[u8; 4] = b"OXYM";
+const VERSION: u8 = 1;
+
+/// Extension of our own movie files.
+pub(crate) const EXTENSION: &str = "oxm";
+
+/// How often, in frames, the machine state is hashed.
+const HASH_INTERVAL: usize = 60;
+
+/// How often, in frames, a recording is written out in case we crash.
+const AUTOSAVE_INTERVAL: usize = 3600;
+
+/// BizHawk's Game Boy buttons, in the order its input log has them.
+const BK2_BUTTONS: [(&str, char, Buttons); 8] = [
+    ("Up", 'U', Buttons::UP),
+    ("Down", 'D', Buttons::DOWN),
+    ("Left", 'L', Buttons::LEFT),
+    ("Right", 'R', Buttons::RIGHT),
+    ("Start", 'S', Buttons::START),
+    ("Select", 's', Buttons::SELECT),
+    ("B", 'B', Buttons::B),
+    ("A", 'A', Buttons::A),
+];
+
+/// Buttons held on every frame from a known start, enough to play a game
+/// back exactly.
+pub(crate) struct Movie {
+    pub(crate) rom_crc: u32,
+    pub(crate) title: String,
+    /// How many times recording has picked up again from a playback.
+    pub(crate) rerecords: u32,
+    /// The save state it starts from. `None` is power-on.
+    pub(crate) start: Option<Vec<u8>>,
+    pub(crate) frames: Vec<Buttons>,
+    /// A hash of the machine state every `HASH_INTERVAL` frames.
+    pub(crate) hashes: Vec<u32>,
+}
+
+impl Movie {
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let mut r = Reader::new(&data);
+
+        if r.take(4)? != MAGIC || r.u8()? != VERSION {
+            return Err(Error::Corrupt);
+        }
+
+        let rom_crc = r.u32()?;
+        let title = String::from_utf8_lossy(&r.vec()?).into_owned();
+        let rerecords = r.u32()?;
+        let start = match r.bool()? {
+            true => Some(r.vec()?),
+            false => None,
+        };
+        let frames = r
+            .vec()?
+            .into_iter()
+            .map(Buttons::from_bits_retain)
+            .collect();
+        let hashes = (0..r.u32()?).map(|_| r.u32()).collect::<Result<_>>()?;
+
+        Ok(Self {
+            rom_crc,
+            title,
+            rerecords,
+            start,
+            frames,
+            hashes,
+        })
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let mut w = Writer::new();
+        w.bytes(MAGIC);
+        w.u8(VERSION);
+        w.u32(self.rom_crc);
+        w.vec(self.title.as_bytes());
+        w.u32(self.rerecords);
+        w.bool(self.start.is_some());
+        if let Some(start) = &self.start {
+            w.vec(start);
+        }
+
+        let frames: Vec<u8> = self.frames.iter().map(|b| b.bits()).collect();
+        w.vec(&frames);
+        w.u32(self.hashes.len() as u32);
+        for &hash in &self.hashes {
+            w.u32(hash);
+        }
+
+        std::fs::write(path, w.data)?;
+        Ok(())
+    }
+
+    /// Reads the input log out of a BizHawk movie. It has no hashes, and
+    /// only syncs as far as BizHawk's core and ours agree.
+    pub(crate) fn read_bk2(path: &Path, cpu: &Cpu) -> Result<Self> {
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+
+        let mut header = String::new();
+        zip.by_name("Header.txt")?.read_to_string(&mut header)?;
+        if header
+            .lines()
+            .any(|l| l.trim() == "StartsFromSavestate True")
+        {
+            return Err(Error::StartsFromState);
+        }
+
+        let mut log = String::new();
+        zip.by_name("Input Log.txt")?.read_to_string(&mut log)?;
+
+        let keys: Vec<&str> = log
+            .lines()
+            .find_map(|l| l.strip_prefix("LogKey:"))
+            .ok_or(Error::Corrupt)?
+            .split('|')
+            .map(|k| k.trim_start_matches('#'))
+            .filter(|k| !k.is_empty())
+            .collect();
+
+        let frames = log
+            .lines()
+            .filter_map(|l| l.strip_prefix('|')?.strip_suffix('|'))
+            .map(|l| {
+                let mut buttons = Buttons::empty();
+
+                for (key, c) in keys.iter().zip(l.chars()) {
+                    let flag = BK2_BUTTONS.iter().find(|(name, ..)| name == key);
+
+                    if let Some((.., flag)) = flag.filter(|_| c != '.') {
+                        buttons |= *flag;
+                    }
+                }
+
+                buttons
+            })
+            .collect();
+
+        let rerecords = header
+            .lines()
+            .find_map(|l| l.strip_prefix("rerecordCount "))
+            .and_then(|n| n.trim().parse().ok())
+            .unwrap_or(0);
+
+        Ok(Self {
+            rom_crc: cpu.mmu.cart.crc,
+            title: cpu.mmu.cart.title(),
+            rerecords,
+            start: None,
+            frames,
+            hashes: Vec::new(),
+        })
+    }
+
+    pub(crate) fn write_bk2(&self, path: &Path) -> Result<()> {
+        if self.start.is_some() {
+            return Err(Error::StartsFromState);
+        }
+
+        let mut zip = ZipWriter::new(File::create(path)?);
+        let options = SimpleFileOptions::default();
+
+        zip.start_file("Header.txt", options)?;
+        writeln!(zip, "MovieVersion BizHawk v2.0.0")?;
+        writeln!(zip, "Platform GB")?;
+        writeln!(zip, "Core Gambatte")?;
+        writeln!(zip, "GameName {}", self.title)?;
+        writeln!(zip, "rerecordCount {}", self.rerecords)?;
+
+        zip.start_file("Input Log.txt", options)?;
+        writeln!(zip, "[Input]")?;
+        let names: Vec<&str> = BK2_BUTTONS.iter().map(|(name, ..)| *name).collect();
+        writeln!(zip, "LogKey:#{}|Power|", names.join("|"))?;
+
+        for &frame in &self.frames {
+            let line: String = BK2_BUTTONS
+                .iter()
+                .map(|&(_, c, flag)| if frame.contains(flag) { c } else { '.' })
+                .collect();
+            writeln!(zip, "|{line}.|")?;
+        }
+        writeln!(zip, "[/Input]")?;
+
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Record,
+    /// Playing back, but pressing a button takes over and records from
+    /// there.
+    Playback,
+    /// Playing back, ignoring the controls.
+    ReadOnly,
+}
+
+/// What the frontend can ask of movies. They start at the next frame.
+pub(crate) enum Command {
+    /// Records into a new file, from power-on or from where the game is now.
+    Record {
+        path: PathBuf,
+        power_on: bool,
+    },
+    /// Plays a movie, or a BizHawk `.bk2`.
+    Play {
+        path: PathBuf,
+        read_only: bool,
+    },
+    ReadOnly(bool),
+    Stop,
+}
+
+/// Where a movie is up to, for the frame counter.
+#[derive(Clone, Debug)]
+pub(crate) struct Status {
+    pub(crate) mode: Mode,
+    pub(crate) frame: usize,
+    pub(crate) length: usize,
+    pub(crate) rerecords: u32,
+    /// The first frame whose hash didn't match the recording's.
+    pub(crate) desync: Option<usize>,
+}
+
+/// A movie being recorded or played.
+struct Session {
+    movie: Movie,
+    path: PathBuf,
+    mode: Mode,
+    frame: usize,
+    desync: Option<usize>,
+    // Recorded into since it was last written out.
+    dirty: bool,
+}
+
+impl Session {
+    /// The buttons for the next frame, given the ones held down.
+    fn frame(&mut self, cpu: &Cpu, held: Buttons) -> Buttons {
+        if self.frame.is_multiple_of(HASH_INTERVAL) {
+            self.hash(cpu);
+        }
+
+        if self.mode == Mode::Playback && !held.is_empty() {
+            self.mode = Mode::Record;
+            self.movie.rerecords += 1;
+            self.movie
+                .hashes
+                .truncate(self.frame.div_ceil(HASH_INTERVAL));
+        }
+
+        let buttons = match self.mode {
+            Mode::Record => {
+                self.movie.frames.truncate(self.frame);
+                self.movie.frames.push(held);
+                self.dirty = true;
+                held
+            }
+            // Past the end the controls work again.
+            _ => match self.movie.frames.get(self.frame) {
+                Some(&buttons) => buttons,
+                None => return held,
+            },
+        };
+
+        self.frame += 1;
+
+        if self.mode == Mode::Record && self.frame.is_multiple_of(AUTOSAVE_INTERVAL) {
+            self.save();
+        }
+
+        buttons
+    }
+
+    /// Keeps the hash when recording and checks it when playing back.
+    fn hash(&mut self, cpu: &Cpu) {
+        let hash = crc32(&state::save(cpu));
+        let i = self.frame / HASH_INTERVAL;
+
+        if self.mode == Mode::Record {
+            self.movie.hashes.truncate(i);
+            self.movie.hashes.push(hash);
+        } else if self.desync.is_none() && self.movie.hashes.get(i).is_some_and(|&h| h != hash) {
+            eprintln!("Movie desynced by frame {}", self.frame);
+            self.desync = Some(self.frame);
+        }
+    }
+
+    fn save(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        match self.movie.write(&self.path) {
+            Ok(()) => self.dirty = false,
+            Err(e) => eprintln!("Couldn't save movie to {}: {e}", self.path.display()),
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            mode: self.mode,
+            frame: self.frame,
+            length: self.movie.frames.len(),
+            rerecords: self.movie.rerecords,
+            desync: self.desync,
+        }
+    }
+}
+
+/// Records and plays movies from the emulator thread, at frame boundaries
+/// so they stay deterministic.
+pub(crate) struct Movies {
+    session: Option<Session>,
+    pending: Option<Command>,
+    // The loaded ROM as it was at power-on.
+    power_on: Vec<u8>,
+}
+
+impl Movies {
+    pub(crate) fn new() -> Self {
+        Self {
+            session: None,
+            pending: None,
+            power_on: Vec::new(),
+        }
+    }
+
+    pub(crate) fn command(&mut self, command: Command) {
+        self.pending = Some(command);
+    }
+
+    /// Stops whatever was going on and remembers the new ROM's power-on
+    /// state. Call before anything has run.
+    pub(crate) fn rom_loaded(&mut self, cpu: &Cpu, events: &Sender<Event>) {
+        self.pending = None;
+        self.stop(events);
+        self.power_on = state::save(cpu);
+    }
+
+    /// The buttons the joypad should see next frame, given the ones held.
+    /// Called once a frame.
+    pub(crate) fn frame(
+        &mut self,
+        cpu: &mut Cpu,
+        held: Buttons,
+        events: &Sender<Event>,
+    ) -> Buttons {
+        if let Some(command) = self.pending.take() {
+            self.run(command, cpu, events);
+        }
+
+        let Some(session) = &mut self.session else {
+            return held;
+        };
+
+        let buttons = session.frame(cpu, held);
+        _ = events.send(Event::Movie(Some(session.status())));
+        buttons
+    }
+
+    fn run(&mut self, command: Command, cpu: &mut Cpu, events: &Sender<Event>) {
+        match command {
+            Command::Record { path, power_on } => {
+                self.stop(events);
+
+                if power_on {
+                    if let Err(e) = state::load(cpu, &self.power_on) {
+                        eprintln!("Couldn't go back to power-on to record: {e}");
+                        _ = events.send(Event::MovieFailed(path, e));
+                        return;
+                    }
+                }
+
+                let movie = Movie {
+                    rom_crc: cpu.mmu.cart.crc,
+                    title: cpu.mmu.cart.title(),
+                    rerecords: 0,
+                    start: (!power_on).then(|| state::save(cpu)),
+                    frames: Vec::new(),
+                    hashes: Vec::new(),
+                };
+
+                self.start(movie, path, Mode::Record);
+            }
+            Command::Play { path, read_only } => {
+                self.stop(events);
+
+                match self.open(&path, cpu) {
+                    Ok(movie) => {
+                        let mode = if read_only {
+                            Mode::ReadOnly
+                        } else {
+                            Mode::Playback
+                        };
+
+                        // Anything recorded over a BizHawk movie is ours.
+                        self.start(movie, path.with_extension(EXTENSION), mode);
+                    }
+                    Err(e) => {
+                        eprintln!("Couldn't play {}: {e}", path.display());
+                        _ = events.send(Event::MovieFailed(path, e));
+                    }
+                }
+            }
+            Command::ReadOnly(read_only) => {
+                let Some(session) = &mut self.session else {
+                    return;
+                };
+
+                session.mode = match (session.mode, read_only) {
+                    (_, true) => Mode::ReadOnly,
+                    (Mode::ReadOnly, false) => Mode::Playback,
+                    (mode, false) => mode,
+                };
+                session.save();
+            }
+            Command::Stop => self.stop(events),
+        }
+    }
+
+    /// Reads a movie and puts the machine where it starts.
+    fn open(&self, path: &Path, cpu: &mut Cpu) -> Result<Movie> {
+        let bk2 = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("bk2"));
+
+        let movie = if bk2 {
+            Movie::read_bk2(path, cpu)?
+        } else {
+            Movie::read(path)?
+        };
+
+        if movie.rom_crc != cpu.mmu.cart.crc {
+            return Err(Error::WrongRom(movie.rom_crc));
+        }
+
+        state::load(cpu, movie.start.as_ref().unwrap_or(&self.power_on))?;
+        Ok(movie)
+    }
+
+    fn start(&mut self, movie: Movie, path: PathBuf, mode: Mode) {
+        self.session = Some(Session {
+            movie,
+            path,
+            mode,
+            frame: 0,
+            desync: None,
+            dirty: mode == Mode::Record,
+        });
+    }
+
+    pub(crate) fn stop(&mut self, events: &Sender<Event>) {
+        if let Some(mut session) = self.session.take() {
+            session.save();
+            _ = events.send(Event::Movie(None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// A path in the temp directory, unique to this test run.
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("oxyboy-{}-{name}", std::process::id()))
+    }
+
+    fn movie() -> Movie {
+        Movie {
+            rom_crc: 0x1234_5678,
+            title: "TETRIS".into(),
+            rerecords: 3,
+            start: None,
+            frames: vec![
+                Buttons::empty(),
+                Buttons::A | Buttons::RIGHT,
+                Buttons::START,
+                Buttons::all(),
+            ],
+            hashes: vec![0xDEAD_BEEF],
+        }
+    }
+
+    #[test]
+    fn movies() {
+        let path = temp("movie.oxm");
+        let mut movie = movie();
+        movie.start = Some(vec![1, 2, 3]);
+        movie.write(&path).unwrap();
+
+        let read = Movie::read(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert_eq!(read.rom_crc, movie.rom_crc);
+        assert_eq!(read.title, movie.title);
+        assert_eq!(read.rerecords, movie.rerecords);
+        assert_eq!(read.start, movie.start);
+        assert_eq!(read.frames, movie.frames);
+        assert_eq!(read.hashes, movie.hashes);
+    }
+
+    #[test]
+    fn malformed_movies() {
+        let path = temp("malformed.oxm");
+        movie().write(&path).unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        for bad in [&b"OXYN\x01"[..], b"OXYM\x02", &data[..data.len() - 1]] {
+            std::fs::write(&path, bad).unwrap();
+            assert!(matches!(Movie::read(&path), Err(Error::Corrupt)));
+        }
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn bk2() {
+        let path = temp("movie.bk2");
+        movie().write_bk2(&path).unwrap();
+
+        let cpu = Cpu::new();
+        let read = Movie::read_bk2(&path, &cpu).unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert_eq!(read.frames, movie().frames);
+        assert_eq!(read.rerecords, 3);
+        assert_eq!(read.rom_crc, cpu.mmu.cart.crc);
+        assert!(read.start.is_none());
+        assert!(read.hashes.is_empty());
+    }
+
+    /// Writes a `.bk2` with this header and input log.
+    fn write_bk2(path: &Path, header: &str, log: &str) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        let options = SimpleFileOptions::default();
+
+        zip.start_file("Header.txt", options).unwrap();
+        zip.write_all(header.as_bytes()).unwrap();
+        zip.start_file("Input Log.txt", options).unwrap();
+        zip.write_all(log.as_bytes()).unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn bizhawk_bk2() {
+        let path = temp("bizhawk.bk2");
+        // Columns BizHawk's own GB cores use, in a different order.
+        write_bk2(
+            &path,
+            "MovieVersion BizHawk v2.0.0\nrerecordCount 12\n",
+            "[Input]\nLogKey:#A|B|Up|Down|Left|Right|Select|Start|Power|\n\
+             |A..D....|\n|........|\n|.....R.S.|\n[/Input]\n",
+        );
+
+        let read = Movie::read_bk2(&path, &Cpu::new()).unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            read.frames,
+            [
+                Buttons::A | Buttons::DOWN,
+                Buttons::empty(),
+                Buttons::RIGHT | Buttons::START,
+            ]
+        );
+        assert_eq!(read.rerecords, 12);
+    }
+
+    #[test]
+    fn malformed_bk2() {
+        let cpu = Cpu::new();
+        let path = temp("malformed.bk2");
+
+        write_bk2(&path, "StartsFromSavestate True\n", "LogKey:#A|\n|A|\n");
+        assert!(matches!(
+            Movie::read_bk2(&path, &cpu),
+            Err(Error::StartsFromState)
+        ));
+
+        write_bk2(&path, "", "[Input]\n|A|\n");
+        assert!(matches!(Movie::read_bk2(&path, &cpu), Err(Error::Corrupt)));
+
+        std::fs::write(&path, "not a zip").unwrap();
+        assert!(Movie::read_bk2(&path, &cpu).is_err());
+        _ = std::fs::remove_file(&path);
+
+        let mut from_state = movie();
+        from_state.start = Some(Vec::new());
+        assert!(matches!(
+            from_state.write_bk2(&path),
+            Err(Error::StartsFromState)
+        ));
+    }
+
+    #[test]
+    fn recording_without_a_power_on_state() {
+        let mut cpu = Cpu::new();
+        let (events, rx) = mpsc::channel();
+        let path = temp("unloaded.oxm");
+
+        // Nothing's been loaded, so there's no power-on state to go back to.
+        let mut movies = Movies::new();
+        movies.command(Command::Record {
+            path: path.clone(),
+            power_on: true,
+        });
+        assert_eq!(movies.frame(&mut cpu, Buttons::A, &events), Buttons::A);
+
+        assert!(matches!(rx.try_recv(), Ok(Event::MovieFailed(p, _)) if p == path));
+        assert!(movies.session.is_none());
+    }
+}
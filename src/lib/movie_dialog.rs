@@ -0,0 +1,166 @@
+use std::{path::PathBuf, sync::mpsc::Sender};
+
+use eframe::egui::{self, Align2, Color32, FontId, Rect};
+
+use crate::{
+    movie::{Command, Mode, Movie, Status, EXTENSION},
+    EmuCommand,
+};
+
+/// Recording and playing back movies, and converting them to BizHawk's
+/// format.
+pub(crate) struct MovieDialog {
+    pub(crate) open: bool,
+    tx: Sender<EmuCommand>,
+    read_only: bool,
+    status: Option<Status>,
+}
+
+impl MovieDialog {
+    pub(crate) fn new(tx: Sender<EmuCommand>) -> Self {
+        Self {
+            open: false,
+            tx,
+            read_only: true,
+            status: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, status: Option<Status>) {
+        if let Some(status) = &status {
+            self.read_only = status.mode == Mode::ReadOnly;
+        }
+
+        self.status = status;
+    }
+
+    /// Shows the window. Gives back an error for the frontend to show.
+    pub(crate) fn show(&mut self, ctx: &egui::Context) -> Option<String> {
+        let mut open = self.open;
+        let mut error = None;
+
+        egui::Window::new("Movie")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                match &self.status {
+                    Some(status) => {
+                        ui.label(describe(status));
+                        ui.label(format!("{} rerecords", status.rerecords));
+
+                        if let Some(frame) = status.desync {
+                            ui.colored_label(Color32::RED, format!("Desynced by frame {frame}"));
+                        }
+                    }
+                    None => _ = ui.label("No movie"),
+                }
+
+                if ui.checkbox(&mut self.read_only, "Read-only").changed() {
+                    self.send(Command::ReadOnly(self.read_only));
+                }
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if ui.button("Record from power-on").clicked() {
+                        self.record(true);
+                    }
+                    if ui.button("Record from here").clicked() {
+                        self.record(false);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Play").clicked() {
+                        let file = rfd::FileDialog::new()
+                            .add_filter("movie", &[EXTENSION, "bk2"])
+                            .pick_file();
+
+                        if let Some(path) = file {
+                            self.send(Command::Play {
+                                path,
+                                read_only: self.read_only,
+                            });
+                        }
+                    }
+
+                    let running = self.status.is_some();
+                    if ui.add_enabled(running, egui::Button::new("Stop")).clicked() {
+                        self.send(Command::Stop);
+                    }
+
+                    if ui.button("Export .bk2").clicked() {
+                        error = export();
+                    }
+                });
+            });
+
+        self.open = open;
+        error
+    }
+
+    /// Draws the frame counter in the corner of the screen.
+    pub(crate) fn counter(&self, ui: &egui::Ui, screen: Rect) {
+        let Some(status) = &self.status else {
+            return;
+        };
+
+        let color = match status.desync {
+            Some(_) => Color32::RED,
+            None => Color32::WHITE,
+        };
+
+        let painter = ui.painter();
+        let text = painter.layout_no_wrap(describe(status), FontId::monospace(14.0), color);
+        let rect = Align2::LEFT_TOP
+            .anchor_size(screen.min + egui::vec2(4.0, 4.0), text.size())
+            .expand(2.0);
+
+        painter.rect_filled(rect, 2.0, Color32::from_black_alpha(160));
+        painter.galley(rect.min + egui::vec2(2.0, 2.0), text, color);
+    }
+
+    fn record(&self, power_on: bool) {
+        let file = rfd::FileDialog::new()
+            .add_filter("movie", &[EXTENSION])
+            .save_file();
+
+        if let Some(path) = file {
+            self.send(Command::Record {
+                path: path.with_extension(EXTENSION),
+                power_on,
+            });
+        }
+    }
+
+    fn send(&self, command: Command) {
+        _ = self.tx.send(EmuCommand::Movie(command));
+    }
+}
+
+fn describe(status: &Status) -> String {
+    let Status { frame, length, .. } = *status;
+
+    match status.mode {
+        Mode::Record => format!("Recording {frame}"),
+        _ if frame >= length => format!("Finished {frame}/{length}"),
+        Mode::Playback => format!("Playing {frame}/{length}"),
+        Mode::ReadOnly => format!("Playing {frame}/{length} (read-only)"),
+    }
+}
+
+/// Converts a movie to BizHawk's `.bk2`.
+fn export() -> Option<String> {
+    let from = rfd::FileDialog::new()
+        .add_filter("movie", &[EXTENSION])
+        .pick_file()?;
+    let to: PathBuf = rfd::FileDialog::new()
+        .add_filter("BizHawk movie", &["bk2"])
+        .set_file_name(from.with_extension("bk2").file_name()?.to_string_lossy())
+        .save_file()?;
+
+    Movie::read(&from)
+        .and_then(|movie| movie.write_bk2(&to))
+        .err()
+        .map(|e| format!("Couldn't export {}: {e}", from.display()))
+}
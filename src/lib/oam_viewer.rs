@@ -0,0 +1,168 @@
+use eframe::egui::{
+    self, Color32, ColorImage, DragValue, Rect, RichText, Sense, TextureHandle, Vec2,
+};
+
+use crate::{
+    ppu::{shade, sprites_on_line, tile_pixel, Lcdc, VideoSnapshot, MAX_LINE_SPRITES, SCREEN_H},
+    ui::SCREEN_TEXTURE,
+};
+
+const SPRITES: usize = 40;
+const PREVIEW_SCALE: f32 = 2.0;
+
+const DRAWN: Color32 = Color32::from_rgb(0x40, 0xC0, 0x40);
+const DROPPED: Color32 = Color32::from_rgb(0xE0, 0x30, 0x30);
+
+/// Lists every OAM entry, and which ones the 10 sprite limit hides on a line.
+pub(crate) struct OamViewer {
+    pub(crate) open: bool,
+    line: u8,
+    texture: Option<TextureHandle>,
+}
+
+impl OamViewer {
+    pub(crate) fn new() -> Self {
+        Self {
+            open: false,
+            line: 0,
+            texture: None,
+        }
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context, video: Option<&VideoSnapshot>) {
+        let mut open = self.open;
+
+        egui::Window::new("OAM").open(&mut open).show(ctx, |ui| {
+            let Some(video) = video else {
+                ui.label("Waiting for the emulator...");
+                return;
+            };
+
+            let tall = Lcdc::from_bits_retain(video.lcdc).contains(Lcdc::SPRITE_SIZE);
+            let height: usize = if tall { 16 } else { 8 };
+
+            ui.horizontal(|ui| {
+                ui.label("Scanline");
+                ui.add(DragValue::new(&mut self.line).range(0..=SCREEN_H - 1));
+                ui.label(RichText::new("drawn").color(DRAWN));
+                ui.label(RichText::new("dropped").color(DROPPED));
+            });
+
+            let on_line: Vec<usize> =
+                sprites_on_line(&video.oam, self.line as i32, height as i32).collect();
+
+            if on_line.len() > MAX_LINE_SPRITES {
+                ui.label(format!(
+                    "{} sprites on line {}, {} dropped",
+                    on_line.len(),
+                    self.line,
+                    on_line.len() - MAX_LINE_SPRITES
+                ));
+            }
+
+            let image = render(video, height);
+
+            let texture = match &mut self.texture {
+                Some(texture) => {
+                    texture.set(image, SCREEN_TEXTURE);
+                    texture
+                }
+                None => self
+                    .texture
+                    .insert(ctx.load_texture("OAM", image, SCREEN_TEXTURE)),
+            };
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("oam").striped(true).show(ui, |ui| {
+                    for heading in ["#", "", "X", "Y", "Tile", "Flags"] {
+                        ui.label(heading);
+                    }
+                    ui.end_row();
+
+                    for i in 0..SPRITES {
+                        let entry = &video.oam[i * 4..i * 4 + 4];
+
+                        let index = RichText::new(i.to_string()).monospace();
+                        let index = match on_line.iter().position(|&s| s == i) {
+                            Some(n) if n < MAX_LINE_SPRITES => index.color(DRAWN),
+                            Some(_) => index.color(DROPPED).strong(),
+                            None => index,
+                        };
+                        ui.label(index);
+
+                        let size = Vec2::new(8.0, height as f32) * PREVIEW_SCALE;
+                        let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+                        let uv = Rect::from_min_max(
+                            egui::pos2(i as f32 / SPRITES as f32, 0.0),
+                            egui::pos2((i + 1) as f32 / SPRITES as f32, 1.0),
+                        );
+                        egui::Image::from_texture(&*texture)
+                            .uv(uv)
+                            .paint_at(ui, rect);
+
+                        ui.monospace(format!("{:3}", entry[1] as i32 - 8));
+                        ui.monospace(format!("{:3}", entry[0] as i32 - 16));
+                        ui.monospace(format!("${:02X}", entry[2]));
+                        ui.label(flags(entry[3], video.cgb));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+
+        self.open = open;
+    }
+}
+
+fn flags(attr: u8, cgb: bool) -> String {
+    let mut flags = vec![if attr & 0x10 != 0 { "OBP1" } else { "OBP0" }.to_string()];
+
+    if attr & 0x80 != 0 {
+        flags.push("behind BG".into());
+    }
+    if attr & 0x20 != 0 {
+        flags.push("x flip".into());
+    }
+    if attr & 0x40 != 0 {
+        flags.push("y flip".into());
+    }
+    if cgb {
+        flags.push(format!(
+            "bank {}, palette {}",
+            (attr >> 3) & 1,
+            attr & 0b111
+        ));
+    }
+
+    flags.join(", ")
+}
+
+/// All 40 sprites side by side, flipped and coloured the way they'd be drawn.
+fn render(video: &VideoSnapshot, height: usize) -> ColorImage {
+    let width = SPRITES * 8;
+    let mut rgb = vec![0; width * height * 3];
+
+    for i in 0..SPRITES {
+        let tile = video.oam[i * 4 + 2] as usize;
+        let attr = video.oam[i * 4 + 3];
+        let tile = if height == 16 { tile & 0xFE } else { tile };
+        let pal = if attr & 0x10 != 0 {
+            video.pal1
+        } else {
+            video.pal0
+        };
+
+        for y in 0..height {
+            let row = if attr & 0x40 != 0 { height - 1 - y } else { y };
+
+            for x in 0..8 {
+                let col = if attr & 0x20 != 0 { 7 - x } else { x };
+                let colnr = tile_pixel(&video.vram, tile * 16, col as u8, row as u8);
+                let i = (y * width + i * 8 + x) * 3;
+                rgb[i..i + 3].fill(shade(pal, colnr));
+            }
+        }
+    }
+
+    ColorImage::from_rgb([width, height], &rgb)
+}
@@ -0,0 +1,86 @@
+use std::{collections::BTreeSet, sync::mpsc::Sender};
+
+use eframe::egui::{self, vec2};
+
+use crate::gamepad::{PadButton, PadEvent};
+
+/// A gamepad you click on. It goes through the gamepad mapping like a real
+/// one, with its buttons where the default mapping expects them.
+pub(crate) struct OnScreenPad {
+    pub(crate) open: bool,
+    tx: Sender<PadEvent>,
+    held: BTreeSet<PadButton>,
+}
+
+impl OnScreenPad {
+    pub(crate) fn new(tx: Sender<PadEvent>) -> Self {
+        Self {
+            open: false,
+            tx,
+            held: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context) {
+        let mut open = self.open;
+
+        egui::Window::new("On-screen gamepad")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    egui::Grid::new("dpad").show(ui, |ui| {
+                        ui.label("");
+                        self.button(ui, "^", PadButton::DpadUp);
+                        ui.end_row();
+                        self.button(ui, "<", PadButton::DpadLeft);
+                        ui.label("");
+                        self.button(ui, ">", PadButton::DpadRight);
+                        ui.end_row();
+                        ui.label("");
+                        self.button(ui, "v", PadButton::DpadDown);
+                        ui.end_row();
+                    });
+
+                    ui.add_space(24.0);
+
+                    ui.vertical(|ui| {
+                        ui.horizontal(|ui| {
+                            self.button(ui, "B", PadButton::South);
+                            self.button(ui, "A", PadButton::East);
+                        });
+                        ui.horizontal(|ui| {
+                            self.button(ui, "Select", PadButton::Select);
+                            self.button(ui, "Start", PadButton::Start);
+                        });
+                    });
+                });
+            });
+
+        // Let go of everything when closed, or it stays held.
+        if !open {
+            for button in std::mem::take(&mut self.held) {
+                _ = self.tx.send(PadEvent::Button(button, false));
+            }
+        }
+
+        self.open = open;
+    }
+
+    /// Held for as long as the pointer is down on it.
+    fn button(&mut self, ui: &mut egui::Ui, label: &str, button: PadButton) {
+        let down = ui
+            .add(egui::Button::new(label).min_size(vec2(32.0, 32.0)))
+            .is_pointer_button_down_on();
+
+        if down != self.held.contains(&button) {
+            if down {
+                self.held.insert(button);
+            } else {
+                self.held.remove(&button);
+            }
+
+            _ = self.tx.send(PadEvent::Button(button, down));
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+/// T-cycles a second.
+const CLOCK_HZ: f64 = 4_194_304.0;
+
+/// Frames a second, at 70224 cycles each.
+pub(crate) const FRAME_HZ: f64 = CLOCK_HZ / 70224.0;
+
+/// Sleeping overshoots by up to about this much, so the rest is spun.
+const SPIN: Duration = Duration::from_millis(2);
+
+/// Falling further behind than this (after a pause, say) starts afresh
+/// rather than running flat out to catch up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// Keeps emulated time in step with real time, at some multiple of the
+/// real hardware's speed.
+pub(crate) struct Pacer {
+    /// `None` runs as fast as the host can.
+    speed: Option<f64>,
+    // When the CPU's cycle count was at some value. Everything is timed
+    // from here, and `None` starts afresh on the next frame.
+    start: Option<(Instant, u64)>,
+    // For the frame rate readout.
+    counted: Instant,
+    frames: u32,
+}
+
+impl Pacer {
+    pub(crate) fn new() -> Self {
+        Self {
+            speed: Some(1.0),
+            start: None,
+            counted: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    pub(crate) fn set_speed(&mut self, speed: Option<f32>) {
+        self.speed = speed.map(|s| s.max(0.01) as f64);
+        self.start = None;
+    }
+
+    /// Waits until a frame ending at `cycles` is due. Once a second, gives
+    /// back how many frames a second are being emulated.
+    pub(crate) fn frame(&mut self, cycles: u64) -> Option<f32> {
+        if let Some(due) = self.due(cycles, Instant::now()) {
+            if let Some(sleep) = due.checked_duration_since(Instant::now() + SPIN) {
+                std::thread::sleep(sleep);
+            }
+
+            while Instant::now() < due {
+                std::hint::spin_loop();
+            }
+        }
+
+        self.count(Instant::now())
+    }
+
+    /// When a frame ending at `cycles` should finish, if it's at `now`.
+    /// `None` means straight away.
+    fn due(&mut self, cycles: u64, now: Instant) -> Option<Instant> {
+        let speed = self.speed?;
+
+        let due = self.start.and_then(|(start, from)| {
+            // Also catches the count going backwards, like on loading a state.
+            let secs = cycles.checked_sub(from)? as f64 / CLOCK_HZ / speed;
+            Some(start + Duration::from_secs_f64(secs))
+        });
+
+        let due = due.filter(|&due| now < due + MAX_LAG);
+        if due.is_none() {
+            self.start = Some((now, cycles));
+        }
+        due
+    }
+
+    /// Counts a frame finished at `now`, giving back the frame rate once a
+    /// second.
+    fn count(&mut self, now: Instant) -> Option<f32> {
+        self.frames += 1;
+        let elapsed = now.saturating_duration_since(self.counted);
+
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+
+        let fps = self.frames as f32 / elapsed.as_secs_f32();
+        self.counted = now;
+        self.frames = 0;
+
+        // Time spent paused isn't slowness.
+        (elapsed <= Duration::from_secs(2)).then_some(fps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: u64 = 70224;
+
+    fn secs(cycles: u64, speed: f64) -> Duration {
+        Duration::from_secs_f64(cycles as f64 / CLOCK_HZ / speed)
+    }
+
+    #[test]
+    fn speeds() {
+        let mut pacer = Pacer::new();
+        let t = Instant::now();
+
+        // The first frame starts the clock.
+        assert_eq!(pacer.due(0, t), None);
+        assert_eq!(pacer.due(FRAME, t), Some(t + secs(FRAME, 1.0)));
+        assert_eq!(pacer.due(3 * FRAME, t), Some(t + secs(3 * FRAME, 1.0)));
+
+        pacer.set_speed(Some(2.0));
+        assert_eq!(pacer.due(FRAME, t), None);
+        assert_eq!(pacer.due(2 * FRAME, t), Some(t + secs(FRAME, 2.0)));
+
+        pacer.set_speed(Some(0.25));
+        assert_eq!(pacer.due(0, t), None);
+        assert_eq!(pacer.due(FRAME, t), Some(t + secs(FRAME, 0.25)));
+
+        pacer.set_speed(None);
+        assert_eq!(pacer.due(0, t), None);
+        assert_eq!(pacer.due(FRAME, t), None);
+    }
+
+    #[test]
+    fn falling_behind() {
+        let mut pacer = Pacer::new();
+        let t = Instant::now();
+        pacer.due(0, t);
+
+        // A little behind catches up, by the frame being due already.
+        let late = t + Duration::from_millis(50);
+        assert_eq!(pacer.due(FRAME, late), Some(t + secs(FRAME, 1.0)));
+
+        // Too far behind starts again from now instead.
+        let later = t + Duration::from_millis(500);
+        assert_eq!(pacer.due(2 * FRAME, later), None);
+        assert_eq!(pacer.due(3 * FRAME, later), Some(later + secs(FRAME, 1.0)));
+
+        // So does the cycle count going backwards.
+        assert_eq!(pacer.due(0, later), None);
+        assert_eq!(pacer.due(FRAME, later), Some(later + secs(FRAME, 1.0)));
+    }
+
+    #[test]
+    fn frame_rate() {
+        let mut pacer = Pacer::new();
+        let t = pacer.counted;
+
+        for i in 1..60 {
+            assert_eq!(pacer.count(t + Duration::from_millis(i * 16)), None);
+        }
+        assert_eq!(pacer.count(t + Duration::from_secs(1)), Some(60.0));
+
+        // Counting starts over from there.
+        let t = t + Duration::from_secs(1);
+        for i in 1..30 {
+            assert_eq!(pacer.count(t + Duration::from_millis(i * 30)), None);
+        }
+        let fps = pacer.count(t + Duration::from_millis(1500)).unwrap();
+        assert_eq!(fps, 20.0);
+
+        // More than two seconds was a pause, so it isn't reported.
+        let t = t + Duration::from_millis(1500);
+        assert_eq!(pacer.count(t + Duration::from_secs(5)), None);
+        assert_eq!(pacer.frames, 0);
+    }
+}
@@ -0,0 +1,530 @@
+use std::path::PathBuf;
+
+use crate::{
+    archive::RomFile,
+    error::{Error, Result},
+};
+
+/// Patch formats, in the order a same-named patch is looked for.
+const EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+/// `game.ips`, `game.ups` or `game.bps` next to `game.gb`.
+pub(crate) fn find(rom: &RomFile) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|ext| rom.sibling(ext))
+        .find(|path| path.is_file())
+}
+
+/// The biggest ROM there is, which is as big as MBC5 goes.
+pub(crate) const MAX_ROM: usize = 8 << 20;
+
+/// Applies an IPS, UPS or BPS patch, going by its header.
+pub(crate) fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if let Some(records) = patch.strip_prefix(b"PATCH") {
+        ips(rom, records)
+    } else if patch.starts_with(b"UPS1") {
+        ups(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        bps(rom, patch)
+    } else {
+        Err(bad("not an IPS, UPS or BPS patch"))
+    }
+}
+
+fn bad(why: &str) -> Error {
+    Error::Patch(why.into())
+}
+
+fn ips(rom: &[u8], mut records: &[u8]) -> Result<Vec<u8>> {
+    let mut out = rom.to_vec();
+    let truncated = || bad("patch ends in the middle of a record");
+
+    loop {
+        let Some((offset, rest)) = records.split_first_chunk::<3>() else {
+            return Err(truncated());
+        };
+
+        if offset == b"EOF" {
+            // Some patches shrink the ROM by putting its new length here.
+            if let Some(len) = rest.first_chunk::<3>() {
+                out.truncate(u32::from_be_bytes([0, len[0], len[1], len[2]]) as usize);
+            }
+            return Ok(out);
+        }
+
+        let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]) as usize;
+        let (&size, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+        let size = u16::from_be_bytes(size) as usize;
+
+        // A zero size is run length encoded.
+        let (data, rest) = if size == 0 {
+            let (&run, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+            let (&[v], rest) = rest.split_first_chunk::<1>().ok_or_else(truncated)?;
+            (vec![v; u16::from_be_bytes(run) as usize], rest)
+        } else {
+            let data = rest.get(..size).ok_or_else(truncated)?;
+            (data.to_vec(), &rest[size..])
+        };
+
+        if out.len() < offset + data.len() {
+            out.resize(offset + data.len(), 0);
+        }
+        out[offset..offset + data.len()].copy_from_slice(&data);
+
+        records = rest;
+    }
+}
+
+fn ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let footer = Footer::read(patch)?;
+    footer.check_source(rom)?;
+
+    let mut r = Reader::new(&patch[4..patch.len() - 12]);
+    let source_len = r.number()?;
+    let target_len = r.target_len()?;
+
+    if source_len != rom.len() {
+        return Err(Error::Patch(format!(
+            "patch is for a {source_len} byte ROM, this one is {} bytes",
+            rom.len()
+        )));
+    }
+
+    let mut out = rom.to_vec();
+    out.resize(target_len, 0);
+
+    let mut pos = 0usize;
+
+    while !r.done() {
+        pos = pos
+            .checked_add(r.number()?)
+            .filter(|&pos| pos <= out.len())
+            .ok_or_else(past_end)?;
+
+        // XOR bytes in until a zero, which ends the hunk.
+        loop {
+            let x = r.byte()?;
+
+            if x == 0 {
+                break;
+            }
+
+            *out.get_mut(pos).ok_or_else(past_end)? ^= x;
+            pos += 1;
+        }
+
+        // The zero takes up a byte too.
+        pos += 1;
+    }
+
+    footer.check_target(&out)?;
+    Ok(out)
+}
+
+fn bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let footer = Footer::read(patch)?;
+    footer.check_source(rom)?;
+
+    let mut r = Reader::new(&patch[4..patch.len() - 12]);
+    let _source_len = r.number()?;
+    let target_len = r.target_len()?;
+    let metadata_len = r.number()?;
+    r.skip(metadata_len)?;
+
+    let mut out = Vec::with_capacity(target_len);
+    let mut source_pos = 0usize;
+    let mut target_pos = 0usize;
+    let outside = || bad("patch copies from outside the ROM");
+
+    while !r.done() {
+        let action = r.number()?;
+        let len = (action >> 2) + 1;
+
+        if len > target_len - out.len() {
+            return Err(past_end());
+        }
+
+        match action & 3 {
+            // SourceRead
+            0 => {
+                let at = out.len();
+                out.extend_from_slice(rom.get(at..at + len).ok_or_else(outside)?);
+            }
+            // TargetRead
+            1 => {
+                for _ in 0..len {
+                    out.push(r.byte()?);
+                }
+            }
+            // SourceCopy
+            2 => {
+                source_pos = r.relative(source_pos)?;
+                let end = source_pos.checked_add(len).ok_or_else(outside)?;
+                out.extend_from_slice(rom.get(source_pos..end).ok_or_else(outside)?);
+                source_pos = end;
+            }
+            // TargetCopy, which can overlap what it's writing.
+            _ => {
+                target_pos = r.relative(target_pos)?;
+
+                for _ in 0..len {
+                    let b = *out.get(target_pos).ok_or_else(outside)?;
+                    out.push(b);
+                    target_pos += 1;
+                }
+            }
+        }
+    }
+
+    footer.check_target(&out)?;
+    Ok(out)
+}
+
+fn past_end() -> Error {
+    bad("patch writes past the end of the ROM")
+}
+
+/// The checksums UPS and BPS patches end with.
+struct Footer {
+    source: u32,
+    target: u32,
+}
+
+impl Footer {
+    fn read(patch: &[u8]) -> Result<Footer> {
+        if patch.len() < 16 {
+            return Err(bad("patch is too short"));
+        }
+
+        let word = |at: usize| u32::from_le_bytes(patch[at..at + 4].try_into().unwrap());
+        let end = patch.len();
+
+        if crc32(&patch[..end - 4]) != word(end - 4) {
+            return Err(bad("patch is corrupt (checksum mismatch)"));
+        }
+
+        Ok(Footer {
+            source: word(end - 12),
+            target: word(end - 8),
+        })
+    }
+
+    fn check_source(&self, rom: &[u8]) -> Result<()> {
+        let crc = crc32(rom);
+
+        if crc != self.source {
+            return Err(Error::Patch(format!(
+                "patch is for a different ROM (expected CRC32 {:08X}, got {crc:08X})",
+                self.source
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn check_target(&self, out: &[u8]) -> Result<()> {
+        if crc32(out) != self.target {
+            return Err(bad("patched ROM doesn't match the patch's checksum"));
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| bad("patch ends unexpectedly"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        if n > self.data.len() - self.pos {
+            return Err(bad("patch ends unexpectedly"));
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    /// The variable length numbers UPS and BPS share.
+    fn number(&mut self) -> Result<usize> {
+        let too_big = || bad("number in patch is too big");
+        let mut n = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let b = self.byte()?;
+            n = ((b & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|v| n.checked_add(v))
+                .ok_or_else(too_big)?;
+
+            if b & 0x80 != 0 {
+                return Ok(n);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or_else(too_big)?;
+            n = n.checked_add(shift).ok_or_else(too_big)?;
+        }
+    }
+
+    /// The patched ROM's size, which is the most a patch can write.
+    fn target_len(&mut self) -> Result<usize> {
+        let len = self.number()?;
+
+        if len > MAX_ROM {
+            return Err(Error::Patch(format!(
+                "patch makes a {len} byte ROM, bigger than any cartridge"
+            )));
+        }
+
+        Ok(len)
+    }
+
+    /// A BPS copy offset, signed in the low bit, relative to `pos`.
+    fn relative(&mut self, pos: usize) -> Result<usize> {
+        let n = self.number()?;
+        let delta = n >> 1;
+
+        let pos = if n & 1 != 0 {
+            pos.checked_sub(delta)
+        } else {
+            pos.checked_add(delta)
+        };
+
+        pos.ok_or_else(|| bad("patch copies from outside the ROM"))
+    }
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+
+        table[i] = c;
+        i += 1;
+    }
+
+    table
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &b| {
+        CRC_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: &[u8] = b"Hello, world! This is a ROM.";
+
+    fn number(out: &mut Vec<u8>, mut n: usize) {
+        loop {
+            let x = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(0x80 | x);
+                return;
+            }
+            out.push(x);
+            n -= 1;
+        }
+    }
+
+    /// Puts the header on `body` and the checksums after it.
+    fn finish(magic: &[u8], body: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = magic.to_vec();
+        patch.extend_from_slice(body);
+        patch.extend_from_slice(&crc32(ROM).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    /// Why the patch failed.
+    fn why(result: Result<Vec<u8>>) -> String {
+        match result {
+            Err(Error::Patch(why)) => why,
+            Err(e) => panic!("not a patch error: {e}"),
+            Ok(_) => panic!("patch applied"),
+        }
+    }
+
+    #[test]
+    fn ips() {
+        let mut patch = b"PATCH".to_vec();
+        // "world" to "there"
+        patch.extend_from_slice(&[0, 0, 7, 0, 5]);
+        patch.extend_from_slice(b"there");
+        // Eight run length encoded bytes past the end.
+        patch.extend_from_slice(&[0, 0, 30, 0, 0, 0, 8, b'!']);
+        patch.extend_from_slice(b"EOF");
+
+        let out = apply(ROM, &patch).unwrap();
+        assert_eq!(&out[..13], b"Hello, there!");
+        assert_eq!(
+            &out[28..],
+            [0, 0, b'!', b'!', b'!', b'!', b'!', b'!', b'!', b'!']
+        );
+
+        // And then shrunk.
+        patch.extend_from_slice(&[0, 0, 5]);
+        assert_eq!(apply(ROM, &patch).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn ups() {
+        let mut target = ROM.to_vec();
+        target[7..12].copy_from_slice(b"there");
+        target.push(b'!');
+
+        let mut body = Vec::new();
+        number(&mut body, ROM.len());
+        number(&mut body, target.len());
+        number(&mut body, 7);
+        body.extend(target[7..12].iter().zip(&ROM[7..12]).map(|(a, b)| a ^ b));
+        body.push(0);
+        number(&mut body, ROM.len() - 13);
+        body.extend_from_slice(&[b'!', 0]);
+
+        let patch = finish(b"UPS1", &body, &target);
+        assert_eq!(apply(ROM, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps() {
+        let target = b"Hello, Hello, Hello, ROM.".to_vec();
+
+        let mut body = Vec::new();
+        number(&mut body, ROM.len());
+        number(&mut body, target.len());
+        number(&mut body, 0);
+        // SourceRead "Hello, "
+        number(&mut body, (7 - 1) << 2);
+        // TargetCopy "Hello, Hello, " from the start, overlapping itself.
+        number(&mut body, ((14 - 1) << 2) | 3);
+        number(&mut body, 0);
+        // SourceCopy "ROM." from 24.
+        number(&mut body, ((4 - 1) << 2) | 2);
+        number(&mut body, 24 << 1);
+
+        let patch = finish(b"BPS1", &body, &target);
+        assert_eq!(apply(ROM, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn malformed_patches() {
+        assert_eq!(
+            why(apply(ROM, b"NOT A PATCH")),
+            "not an IPS, UPS or BPS patch"
+        );
+        assert_eq!(
+            why(apply(ROM, b"PATCH\0\0\x07\0\x05the")),
+            "patch ends in the middle of a record"
+        );
+        assert_eq!(why(apply(ROM, b"UPS1")), "patch is too short");
+
+        let mut corrupt = finish(b"BPS1", &[0x80 | 28, 0x80 | 28, 0x80], ROM);
+        corrupt[5] ^= 1;
+        assert_eq!(
+            why(apply(ROM, &corrupt)),
+            "patch is corrupt (checksum mismatch)"
+        );
+
+        let other = finish(b"BPS1", &[0x80 | 5, 0x80 | 5, 0x80], ROM);
+        assert!(why(apply(b"Other", &other)).starts_with("patch is for a different ROM"));
+    }
+
+    #[test]
+    fn patches_that_overflow() {
+        // UPS skipping so far the position wraps.
+        let mut body = Vec::new();
+        number(&mut body, ROM.len());
+        number(&mut body, ROM.len());
+        number(&mut body, 0);
+        body.extend_from_slice(&[1, 0]);
+        number(&mut body, usize::MAX - 1);
+        body.extend_from_slice(&[1, 0]);
+        assert_eq!(
+            why(apply(ROM, &finish(b"UPS1", &body, ROM))),
+            "patch writes past the end of the ROM"
+        );
+
+        // UPS writing past the end.
+        let mut body = Vec::new();
+        number(&mut body, ROM.len());
+        number(&mut body, ROM.len());
+        number(&mut body, ROM.len());
+        body.extend_from_slice(&[1, 0]);
+        assert_eq!(
+            why(apply(ROM, &finish(b"UPS1", &body, ROM))),
+            "patch writes past the end of the ROM"
+        );
+
+        // BPS SourceCopy from so far on that the end wraps.
+        let mut body = Vec::new();
+        number(&mut body, ROM.len());
+        number(&mut body, ROM.len());
+        number(&mut body, 0);
+        number(&mut body, 2);
+        number(&mut body, usize::MAX - 1);
+        assert_eq!(
+            why(apply(ROM, &finish(b"BPS1", &body, ROM))),
+            "patch copies from outside the ROM"
+        );
+
+        // BPS TargetCopy far longer than the target.
+        let mut body = Vec::new();
+        number(&mut body, ROM.len());
+        number(&mut body, ROM.len());
+        number(&mut body, 0);
+        number(&mut body, 0);
+        number(&mut body, usize::MAX);
+        number(&mut body, 0);
+        assert_eq!(
+            why(apply(ROM, &finish(b"BPS1", &body, ROM))),
+            "patch writes past the end of the ROM"
+        );
+
+        // A target too big for any cartridge.
+        let mut body = Vec::new();
+        number(&mut body, ROM.len());
+        number(&mut body, usize::MAX);
+        assert!(
+            why(apply(ROM, &finish(b"BPS1", &body, ROM))).ends_with("bigger than any cartridge")
+        );
+    }
+}
@@ -1,8 +1,16 @@
 use std::cmp::Ordering;
 
+use crate::{
+    error::Result,
+    state::{Reader, Writer},
+};
+
 const VRAM_LEN: usize = 0x4000;
 const VOAM_LEN: usize = 0xA0;
 
+/// The PPU gives up on a line after finding this many sprites on it.
+pub(crate) const MAX_LINE_SPRITES: usize = 10;
+
 pub const SCREEN_W: usize = 160;
 pub const SCREEN_H: usize = 144;
 
@@ -34,6 +42,23 @@ bitflags::bitflags! {
     }
 }
 
+/// A copy of the PPU's memory and registers for the debug viewers.
+#[derive(Clone)]
+pub(crate) struct VideoSnapshot {
+    pub(crate) vram: Vec<u8>,
+    pub(crate) oam: [u8; VOAM_LEN],
+    pub(crate) lcdc: u8,
+    pub(crate) scy: u8,
+    pub(crate) scx: u8,
+    pub(crate) winy: u8,
+    pub(crate) winx: u8,
+    pub(crate) palb: u8,
+    pub(crate) pal0: u8,
+    pub(crate) pal1: u8,
+    /// The cartridge asks for a Game Boy Color, so both VRAM banks matter.
+    pub(crate) cgb: bool,
+}
+
 pub(crate) struct Ppu {
     lcds: Lcds,
     lcdc: Lcdc,
@@ -64,6 +89,7 @@ pub(crate) struct Ppu {
 
     pub buffer: [u8; 69120],
     pub updated: bool,
+    pub frames: u64,
     pub interrupt: u8,
 }
 
@@ -99,10 +125,69 @@ impl Ppu {
             bgprio: [Priority::Normal; SCREEN_W],
             buffer: [0; SCREEN_W * SCREEN_H * 3],
             updated: false,
+            frames: 0,
             interrupt: 0,
         }
     }
 
+    /// The scanline being drawn, or 144 to 153 in vblank.
+    pub(crate) fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    /// Everything but the screen, which save states keep apart, and what's
+    /// worked out from the rest: the palettes' colours and the per-line
+    /// sprite priorities.
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        w.u8(self.lcds.bits());
+        w.u8(self.lcdc.bits());
+        for v in [self.ly, self.lyc, self.scy, self.scx, self.winy, self.winx] {
+            w.u8(v);
+        }
+        w.u32(self.clock);
+        w.bool(self.wy_trigger);
+        w.u32(self.wy_pos as u32);
+
+        for v in [self.palbr, self.pal0r, self.pal1r] {
+            w.u8(v);
+        }
+
+        w.bytes(&self.vram);
+        w.bytes(&self.voam);
+        w.u64(self.frames);
+        w.u8(self.interrupt);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut Reader) -> Result<()> {
+        self.lcds = Lcds::from_bits_retain(r.u8()?);
+        self.lcdc = Lcdc::from_bits_retain(r.u8()?);
+        for v in [
+            &mut self.ly,
+            &mut self.lyc,
+            &mut self.scy,
+            &mut self.scx,
+            &mut self.winy,
+            &mut self.winx,
+        ] {
+            *v = r.u8()?;
+        }
+        self.ly %= 154;
+        self.clock = r.u32()?;
+        self.wy_trigger = r.bool()?;
+        self.wy_pos = r.u32()? as i32;
+
+        for v in [&mut self.palbr, &mut self.pal0r, &mut self.pal1r] {
+            *v = r.u8()?;
+        }
+        self.update_pal();
+
+        r.fill(&mut self.vram)?;
+        r.fill(&mut self.voam)?;
+        self.frames = r.u64()?;
+        self.interrupt = r.u8()?;
+        Ok(())
+    }
+
     pub(crate) fn do_cycle(&mut self, ticks: u32) {
         // This check makes some games not work for some reason
         // if !self.lcdc(Lcdc::LCD_ON) {
@@ -159,6 +244,7 @@ impl Ppu {
                 self.wy_trigger = false;
                 self.interrupt |= 0x01;
                 self.updated = true;
+                self.frames += 1;
                 self.lcds(Lcds::M1_INTE)
             }
             2 => self.lcds(Lcds::M2_INTE),
@@ -199,6 +285,22 @@ impl Ppu {
         }
     }
 
+    pub(crate) fn snapshot(&self, cgb: bool) -> VideoSnapshot {
+        VideoSnapshot {
+            vram: self.vram.to_vec(),
+            oam: self.voam,
+            lcdc: self.lcdc.bits(),
+            scy: self.scy,
+            scx: self.scx,
+            winy: self.winy,
+            winx: self.winx,
+            palb: self.palbr,
+            pal0: self.pal0r,
+            pal1: self.pal1r,
+            cgb,
+        }
+    }
+
     fn lcds(&self, bit: Lcds) -> bool {
         self.lcds.contains(bit)
     }
@@ -232,7 +334,8 @@ impl Ppu {
             0xFF41 => self.lcds = Lcds::from_bits_retain(v),
             0xFF42 => self.scy = v,
             0xFF43 => self.scx = v,
-
+            // LY is read only.
+            0xFF44 => {}
             0xFF45 => {
                 self.lyc = v;
                 self.coincidence();
@@ -317,36 +420,11 @@ impl Ppu {
                 0x9800
             };
 
-            let tilex = bgtilex;
-            let tiley = bgtiley;
             let pixely = bgy & 0b111;
             let pixelx = bgx & 0b111;
 
-            let tile_num = self.rb(tilemapbase + tiley * 32 + tilex);
-
-            let tilebase = if self.lcdc(Lcdc::TILE_DATA) {
-                0x8000
-            } else {
-                0x8800
-            };
-
-            let tileaddress = if tilebase == 0x8000 {
-                0x8000u16 + tile_num as u16 * 16
-            } else {
-                0x8800u16 + ((tile_num as i8 as i16 + 128) * 16) as u16
-            };
-
-            let addr = tileaddress + (pixely as u16 * 2);
-
-            let b1 = self.rb(addr);
-            let b2 = self.rb(addr + 1);
-
-            let bit = 7 - pixelx;
-
-            let lo = (b1 >> bit) & 1;
-            let hi = (b2 >> bit) & 1;
-
-            let colnr = (hi << 1) | lo;
+            let tile = map_tile(&self.vram, self.lcdc.bits(), tilemapbase, bgtilex, bgtiley);
+            let colnr = tile_pixel(&self.vram, tile, pixelx as u8, pixely);
 
             self.bgprio[x] = if colnr == 0 {
                 Priority::Color0
@@ -390,34 +468,11 @@ impl Ppu {
 
             let tiley = wintiley;
             let tilex = winx as u16 / 8;
-            let pixely = winy as u16 & 0x07;
+            let pixely = winy as u8 & 0x07;
             let pixelx = winx as u8 & 0x07;
 
-            let tile_num = self.rb(tilemapbase + tiley * 32 + tilex);
-
-            let tilebase = if self.lcdc(Lcdc::TILE_DATA) {
-                0x8000
-            } else {
-                0x8800
-            };
-
-            let tileaddress = if tilebase == 0x8000 {
-                0x8000u16 + tile_num as u16 * 16
-            } else {
-                0x8800u16 + ((tile_num as i8 as i16 + 128) * 16) as u16
-            };
-
-            let addr = tileaddress + (pixely * 2);
-
-            let b1 = self.rb(addr);
-            let b2 = self.rb(addr + 1);
-
-            let bit = 7 - pixelx;
-
-            let lo = (b1 >> bit) & 1;
-            let hi = (b2 >> bit) & 1;
-
-            let colnr = (hi << 1) | lo;
+            let tile = map_tile(&self.vram, self.lcdc.bits(), tilemapbase, tilex, tiley);
+            let colnr = tile_pixel(&self.vram, tile, pixelx, pixely);
 
             self.bgprio[x] = if colnr == 0 {
                 Priority::Color0
@@ -439,28 +494,17 @@ impl Ppu {
 
         let sprite_size = if self.lcdc(Lcdc::SPRITE_SIZE) { 16 } else { 8 };
 
-        let mut sprites_to_draw = [(0, 0, 0); 10];
+        let mut sprites_to_draw = [(0, 0, 0); MAX_LINE_SPRITES];
 
         let mut sidx = 0;
 
-        for i in 0..40 {
-            let spriteaddr = 0xFE00 + (i as u16) * 4;
-
-            let spritey = self.rb(spriteaddr) as u16 as i32 - 16;
-
-            if line < spritey || line >= spritey + sprite_size {
-                continue;
-            }
-
-            let spritex = self.rb(spriteaddr + 1) as u16 as i32 - 8;
+        for i in sprites_on_line(&self.voam, line, sprite_size).take(MAX_LINE_SPRITES) {
+            let spritey = self.voam[i * 4] as i32 - 16;
+            let spritex = self.voam[i * 4 + 1] as i32 - 8;
 
-            sprites_to_draw[sidx] = (spritex, spritey, i);
+            sprites_to_draw[sidx] = (spritex, spritey, i as u8);
 
             sidx += 1;
-
-            if sidx >= 10 {
-                break;
-            }
         }
 
         sprites_to_draw[..sidx].sort_unstable_by(dmg_sprite_order);
@@ -518,6 +562,47 @@ impl Ppu {
     }
 }
 
+/// OAM indices of every sprite covering `line`, in OAM order. Only the first
+/// `MAX_LINE_SPRITES` get drawn.
+pub(crate) fn sprites_on_line(
+    oam: &[u8],
+    line: i32,
+    size: i32,
+) -> impl Iterator<Item = usize> + '_ {
+    (0..40).filter(move |&i| {
+        let spritey = oam[i * 4] as i32 - 16;
+        line >= spritey && line < spritey + size
+    })
+}
+
+/// VRAM offset of the tile at `tilex`, `tiley` of the BG or window map at
+/// `map`, using the tile data addressing `lcdc` selects.
+pub(crate) fn map_tile(vram: &[u8], lcdc: u8, map: u16, tilex: u16, tiley: u16) -> usize {
+    let tile_num = vram[(map - 0x8000 + tiley * 32 + tilex) as usize];
+
+    if Lcdc::from_bits_retain(lcdc).contains(Lcdc::TILE_DATA) {
+        tile_num as usize * 16
+    } else {
+        (0x1000 + tile_num as i8 as isize * 16) as usize
+    }
+}
+
+/// Colour number (0-3) of pixel `x`, `y` of the tile starting at `tile`,
+/// an offset into VRAM.
+pub(crate) fn tile_pixel(vram: &[u8], tile: usize, x: u8, y: u8) -> u8 {
+    let b1 = vram[tile + y as usize * 2];
+    let b2 = vram[tile + y as usize * 2 + 1];
+
+    let bit = 7 - x;
+
+    (((b2 >> bit) & 1) << 1) | ((b1 >> bit) & 1)
+}
+
+/// Grey level a palette register gives colour number `colnr`.
+pub(crate) fn shade(pal: u8, colnr: u8) -> u8 {
+    Ppu::get_monochrome_pal_val(pal, colnr as usize)
+}
+
 fn dmg_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
     if a.0 != b.0 {
         return b.0.cmp(&a.0);
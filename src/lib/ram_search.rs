@@ -0,0 +1,314 @@
+use std::{ops::RangeInclusive, sync::mpsc::Sender};
+
+use eframe::egui::{self, Color32};
+
+use crate::{
+    debugger::{parse_number, DebugCommand},
+    EmuCommand,
+};
+
+/// SRAM, WRAM and HRAM, as the CPU sees them.
+const REGIONS: [RangeInclusive<u16>; 3] = [0xA000..=0xBFFF, 0xC000..=0xDFFF, 0xFF80..=0xFFFE];
+
+/// Candidates listed at once. Searching still covers all of them.
+const SHOWN: usize = 200;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Size {
+    Byte,
+    Word,
+}
+
+#[derive(Clone, Copy)]
+enum Filter {
+    Start,
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equals(u16),
+}
+
+impl Filter {
+    fn keep(self, before: u16, now: u16) -> bool {
+        match self {
+            Filter::Start => true,
+            Filter::Changed => now != before,
+            Filter::Unchanged => now == before,
+            Filter::Increased => now > before,
+            Filter::Decreased => now < before,
+            Filter::Equals(v) => now == v,
+        }
+    }
+}
+
+/// Narrows RAM down to the addresses that behave like a value in the game,
+/// Cheat Engine style, and freezes them.
+pub(crate) struct RamSearch {
+    pub(crate) open: bool,
+    tx: Sender<EmuCommand>,
+    size: Size,
+    value: String,
+    candidates: Vec<u16>,
+    // The snapshot the last filter ran against.
+    previous: Option<Vec<u8>>,
+    // Waiting on a snapshot to run this against.
+    pending: Option<Filter>,
+    error: Option<String>,
+}
+
+impl RamSearch {
+    pub(crate) fn new(tx: Sender<EmuCommand>) -> Self {
+        Self {
+            open: false,
+            tx,
+            size: Size::Byte,
+            value: String::new(),
+            candidates: Vec::new(),
+            previous: None,
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// Runs the pending filter over a new copy of the bus.
+    pub(crate) fn snapshot(&mut self, memory: Vec<u8>) {
+        let Some(filter) = self.pending.take() else {
+            return;
+        };
+
+        match (filter, &self.previous) {
+            (Filter::Start, _) | (_, None) => {
+                self.candidates = REGIONS.iter().flat_map(|r| r.clone()).collect();
+            }
+            (_, Some(previous)) => {
+                let size = self.size;
+                self.candidates
+                    .retain(|&a| filter.keep(read(previous, a, size), read(&memory, a, size)));
+            }
+        }
+
+        self.previous = Some(memory);
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context, frozen: &[(u16, u8)]) {
+        let mut open = self.open;
+
+        egui::Window::new("RAM search")
+            .open(&mut open)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let mut size = self.size;
+                    ui.selectable_value(&mut size, Size::Byte, "8-bit");
+                    ui.selectable_value(&mut size, Size::Word, "16-bit");
+                    self.set_size(size);
+
+                    if ui.button("New search").clicked() {
+                        self.run(Filter::Start);
+                    }
+                });
+
+                let searching = self.previous.is_some();
+
+                ui.add_enabled_ui(searching && self.pending.is_none(), |ui| {
+                    ui.horizontal(|ui| {
+                        for (name, filter) in [
+                            ("Changed", Filter::Changed),
+                            ("Unchanged", Filter::Unchanged),
+                            ("Increased", Filter::Increased),
+                            ("Decreased", Filter::Decreased),
+                        ] {
+                            if ui.button(name).clicked() {
+                                self.run(filter);
+                            }
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut self.value).desired_width(60.0));
+
+                        if ui.button("Equals").clicked() {
+                            match parse_number(&self.value) {
+                                Some(v) => self.run(Filter::Equals(v)),
+                                None => self.error = Some(format!("Bad value {:?}", self.value)),
+                            }
+                        }
+                    });
+                });
+
+                if let Some(e) = &self.error {
+                    ui.colored_label(Color32::RED, e);
+                }
+
+                if searching {
+                    ui.label(format!("{} candidates", self.candidates.len()));
+                }
+
+                ui.separator();
+                self.results(ui);
+                ui.separator();
+                self.frozen(ui, frozen);
+            });
+
+        self.open = open;
+    }
+
+    /// Old comparisons don't mean anything at a new size, so it starts over.
+    fn set_size(&mut self, size: Size) {
+        if size != self.size {
+            self.size = size;
+            self.previous = None;
+            self.candidates.clear();
+        }
+    }
+
+    fn send(&self, cmd: DebugCommand) {
+        _ = self.tx.send(EmuCommand::Debug(cmd));
+    }
+
+    fn run(&mut self, filter: Filter) {
+        self.error = None;
+        self.pending = Some(filter);
+        _ = self.tx.send(EmuCommand::RamSnapshot);
+    }
+
+    fn results(&mut self, ui: &mut egui::Ui) {
+        let Some(previous) = &self.previous else {
+            return;
+        };
+
+        egui::ScrollArea::vertical()
+            .id_source("candidates")
+            .max_height(240.0)
+            .show(ui, |ui| {
+                egui::Grid::new("candidates").striped(true).show(ui, |ui| {
+                    for &addr in self.candidates.iter().take(SHOWN) {
+                        let value = read(previous, addr, self.size);
+
+                        ui.monospace(format!("${addr:04X}"));
+                        ui.monospace(match self.size {
+                            Size::Byte => format!("${value:02X} {value:>5}"),
+                            Size::Word => format!("${value:04X} {value:>5}"),
+                        });
+
+                        if ui.button("Freeze").clicked() {
+                            let [lo, hi] = value.to_le_bytes();
+                            self.send(DebugCommand::Freeze(addr, lo));
+
+                            if self.size == Size::Word {
+                                self.send(DebugCommand::Freeze(addr.wrapping_add(1), hi));
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+
+    fn frozen(&self, ui: &mut egui::Ui, frozen: &[(u16, u8)]) {
+        ui.label("Frozen");
+
+        for &(addr, v) in frozen {
+            ui.horizontal(|ui| {
+                ui.monospace(format!("${addr:04X} = ${v:02X}"));
+
+                if ui.small_button("x").clicked() {
+                    self.send(DebugCommand::Unfreeze(addr));
+                }
+            });
+        }
+    }
+}
+
+fn read(memory: &[u8], addr: u16, size: Size) -> u16 {
+    let lo = memory[addr as usize] as u16;
+
+    match size {
+        Size::Byte => lo,
+        Size::Word => lo | (memory[addr as usize + 1] as u16) << 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use super::*;
+
+    fn search() -> (RamSearch, Receiver<EmuCommand>) {
+        let (tx, rx) = mpsc::channel();
+        (RamSearch::new(tx), rx)
+    }
+
+    /// Runs `filter` against `memory`, as if the emulator had answered.
+    fn filter(search: &mut RamSearch, rx: &Receiver<EmuCommand>, filter: Filter, memory: &[u8]) {
+        search.run(filter);
+        assert!(matches!(rx.try_recv(), Ok(EmuCommand::RamSnapshot)));
+        search.snapshot(memory.to_vec());
+    }
+
+    #[test]
+    fn filters() {
+        let (mut search, rx) = search();
+        let mut before = vec![0; 0x10000];
+        before[0xC000] = 5;
+        before[0xC001] = 5;
+        before[0xC002] = 5;
+        // Outside RAM, so never a candidate.
+        before[0x8000] = 5;
+
+        filter(&mut search, &rx, Filter::Start, &before);
+        assert_eq!(search.candidates.len(), 0x2000 + 0x2000 + 0x7F);
+
+        let mut now = before.clone();
+        now[0xC000] = 6;
+        now[0xC001] = 4;
+        now[0x8000] = 6;
+        filter(&mut search, &rx, Filter::Changed, &now);
+        assert_eq!(search.candidates, [0xC000, 0xC001]);
+
+        let mut later = now.clone();
+        later[0xC000] = 7;
+        later[0xC001] = 3;
+        filter(&mut search, &rx, Filter::Increased, &later);
+        assert_eq!(search.candidates, [0xC000]);
+
+        filter(&mut search, &rx, Filter::Equals(8), &later);
+        assert!(search.candidates.is_empty());
+
+        filter(&mut search, &rx, Filter::Start, &later);
+        filter(&mut search, &rx, Filter::Equals(3), &later);
+        assert_eq!(search.candidates, [0xC001]);
+    }
+
+    #[test]
+    fn words() {
+        let mut memory = vec![0; 0x10000];
+        memory[0xFFFE] = 0x34;
+        memory[0xFFFF] = 0x12;
+
+        assert_eq!(read(&memory, 0xFFFE, Size::Byte), 0x34);
+        assert_eq!(read(&memory, 0xFFFE, Size::Word), 0x1234);
+
+        let (mut search, rx) = search();
+        search.set_size(Size::Word);
+        filter(&mut search, &rx, Filter::Start, &memory);
+        filter(&mut search, &rx, Filter::Equals(0x1234), &memory);
+        assert_eq!(search.candidates, [0xFFFE]);
+    }
+
+    #[test]
+    fn changing_size_starts_over() {
+        let (mut search, rx) = search();
+        let memory = vec![0; 0x10000];
+        filter(&mut search, &rx, Filter::Start, &memory);
+
+        search.set_size(Size::Byte);
+        assert!(!search.candidates.is_empty());
+
+        search.set_size(Size::Word);
+        assert!(search.candidates.is_empty());
+        assert!(search.previous.is_none());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::cpu::Flag;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub(crate) struct Registers {
     pub(crate) a: u8,
     pub(crate) f: u8,
@@ -0,0 +1,42 @@
+use std::{io::Write, path::Path};
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{
+    patch::crc32,
+    ppu::{SCREEN_H, SCREEN_W},
+};
+
+/// Saves an RGB frame from the PPU as a PNG.
+pub(crate) fn save(path: &Path, rgb: &[u8]) -> std::io::Result<()> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&(SCREEN_W as u32).to_be_bytes());
+    header.extend_from_slice(&(SCREEN_H as u32).to_be_bytes());
+    // 8 bits a channel, RGB, deflate, adaptive filtering, not interlaced.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Every row starts with its filter type, 0 for none.
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgb.chunks(SCREEN_W * 3) {
+        zlib.write_all(&[0])?;
+        zlib.write_all(row)?;
+    }
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib.finish()?);
+    chunk(&mut png, b"IEND", &[]);
+
+    std::fs::write(path, png)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+
+use eframe::egui;
+
+use crate::config::{Config, PALETTES};
+
+/// Edits a copy of the config, which only takes effect once it's saved.
+pub(crate) struct SettingsDialog {
+    pub(crate) open: bool,
+    draft: Config,
+}
+
+impl SettingsDialog {
+    pub(crate) fn new() -> Self {
+        Self {
+            open: false,
+            draft: Config::default(),
+        }
+    }
+
+    /// Opens on the settings in use.
+    pub(crate) fn open(&mut self, config: &Config) {
+        self.open = true;
+        self.draft = config.clone();
+    }
+
+    /// Gives back the new config when Save is pressed. The key bindings
+    /// button opens their own window through `bindings`.
+    pub(crate) fn show(
+        &mut self,
+        ctx: &egui::Context,
+        config: &Config,
+        bindings: &mut bool,
+    ) -> Option<Config> {
+        let mut open = self.open;
+        let mut saved = None;
+
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+                    self.rows(ui, bindings);
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    let changed = self.draft != *config;
+
+                    if ui.add_enabled(changed, egui::Button::new("Save")).clicked() {
+                        saved = Some(self.draft.clone());
+                    }
+                    if ui
+                        .add_enabled(changed, egui::Button::new("Revert"))
+                        .clicked()
+                    {
+                        self.draft = config.clone();
+                    }
+                    if ui.button("Defaults").clicked() {
+                        self.draft = Config::default();
+                    }
+                });
+            });
+
+        self.open = open;
+        saved
+    }
+
+    fn rows(&mut self, ui: &mut egui::Ui, bindings: &mut bool) {
+        let draft = &mut self.draft;
+
+        ui.label("Window scale");
+        ui.add(
+            egui::Slider::new(&mut draft.scale, 1.0..=16.0)
+                .step_by(1.0)
+                .suffix("x"),
+        );
+        ui.end_row();
+
+        ui.label("Palette");
+        ui.vertical(|ui| {
+            let name = PALETTES
+                .iter()
+                .find(|(_, colours)| *colours == draft.palette)
+                .map_or("Custom", |(name, _)| name);
+
+            egui::ComboBox::from_id_source("palette")
+                .selected_text(name)
+                .show_ui(ui, |ui| {
+                    for (name, colours) in PALETTES {
+                        ui.selectable_value(&mut draft.palette, colours, name);
+                    }
+                });
+
+            ui.horizontal(|ui| {
+                for colour in &mut draft.palette {
+                    ui.color_edit_button_srgb(&mut colour.0);
+                }
+            });
+        });
+        ui.end_row();
+
+        ui.label("Volume");
+        ui.horizontal(|ui| {
+            ui.add_enabled(
+                !draft.mute,
+                egui::Slider::new(&mut draft.volume, 0.0..=1.0).show_value(false),
+            );
+            ui.checkbox(&mut draft.mute, "Mute");
+        })
+        .response
+        .on_hover_text("There's no sound yet, but these are remembered for when there is.");
+        ui.end_row();
+
+        ui.label("Boot ROM")
+            .on_hover_text("A 256-byte DMG boot ROM. Takes effect on the next ROM loaded.");
+        path(ui, &mut draft.boot_rom, "None", || {
+            rfd::FileDialog::new()
+                .add_filter("boot ROM", &["bin", "gb", "rom"])
+                .pick_file()
+        });
+        ui.end_row();
+
+        ui.label("Save states");
+        path(ui, &mut draft.save_dir, "Next to the ROM", || {
+            rfd::FileDialog::new().pick_folder()
+        });
+        ui.end_row();
+
+        ui.label("Key bindings");
+        if ui.button("Edit...").clicked() {
+            *bindings = true;
+        }
+        ui.end_row();
+    }
+}
+
+/// A path with buttons to pick another or go back to `none`.
+fn path(
+    ui: &mut egui::Ui,
+    path: &mut Option<PathBuf>,
+    none: &str,
+    pick: impl FnOnce() -> Option<PathBuf>,
+) {
+    ui.horizontal(|ui| {
+        match path {
+            Some(path) => ui.monospace(path.display().to_string()),
+            None => ui.weak(none),
+        };
+
+        if ui.small_button("Browse...").clicked() {
+            if let Some(picked) = pick() {
+                *path = Some(picked);
+            }
+        }
+
+        if path.is_some() && ui.small_button("Clear").clicked() {
+            *path = None;
+        }
+    });
+}
@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    archive::RomFile,
+    cpu::Cpu,
+    error::{Error, Result},
+    ppu::{SCREEN_H, SCREEN_W},
+};
+
+const MAGIC: &[u8; 4] = b"OXYS";
+const VERSION: u8 = 2;
+
+/// Where the save state for `rom` goes: next to it, or in `dir`.
+pub(crate) fn path(rom: &RomFile, dir: Option<&Path>) -> PathBuf {
+    let path = rom.sibling("state");
+
+    match (dir, path.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => path,
+    }
+}
+
+/// A snapshot of the whole machine, ROM aside. Movies start from these and
+/// hash them to spot desyncs.
+pub(crate) fn save(cpu: &Cpu) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.bytes(MAGIC);
+    w.u8(VERSION);
+    w.u32(cpu.mmu.cart.crc);
+    // Up front, so it can be shown without loading the rest.
+    w.bytes(&cpu.mmu.ppu.buffer);
+    cpu.save_state(&mut w);
+    w.data
+}
+
+/// Restores a snapshot from `save`. The machine is left as it was if the
+/// snapshot is for another ROM or doesn't read back.
+pub(crate) fn load(cpu: &mut Cpu, data: &[u8]) -> Result<()> {
+    let mut r = Reader::new(data);
+
+    if r.take(4)? != MAGIC || r.u8()? != VERSION {
+        return Err(Error::Corrupt);
+    }
+
+    let crc = r.u32()?;
+    if crc != cpu.mmu.cart.crc {
+        return Err(Error::WrongRom(crc));
+    }
+
+    let backup = save(cpu);
+
+    let loaded = r
+        .fill(&mut cpu.mmu.ppu.buffer)
+        .and_then(|_| cpu.load_state(&mut r));
+
+    if let Err(e) = loaded {
+        load(cpu, &backup).expect("a fresh save state loads");
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// The screen a snapshot from `save` was taken on, for a thumbnail.
+pub(crate) fn screen(data: &[u8]) -> Result<Vec<u8>> {
+    let mut r = Reader::new(data);
+
+    if r.take(4)? != MAGIC || r.u8()? != VERSION {
+        return Err(Error::Corrupt);
+    }
+
+    r.u32()?;
+    Ok(r.take(SCREEN_W * SCREEN_H * 3)?.to_vec())
+}
+
+/// Little-endian fields, one after another.
+pub(crate) struct Writer {
+    pub(crate) data: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub(crate) fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub(crate) fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    /// Bytes the reader knows the length of.
+    pub(crate) fn bytes(&mut self, v: &[u8]) {
+        self.data.extend_from_slice(v);
+    }
+
+    /// Bytes with their length in front.
+    pub(crate) fn vec(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.bytes(v);
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(Error::Corrupt)?;
+
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Fills `buf` from bytes written with `Writer::bytes`.
+    pub(crate) fn fill(&mut self, buf: &mut [u8]) -> Result<()> {
+        buf.copy_from_slice(self.take(buf.len())?);
+        Ok(())
+    }
+
+    /// Bytes written with `Writer::vec`.
+    pub(crate) fn vec(&mut self) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
@@ -1,3 +1,8 @@
+use crate::{
+    error::Result,
+    state::{Reader, Writer},
+};
+
 #[derive(Default)]
 pub struct Timer {
     internaldiv: u32,
@@ -62,4 +67,23 @@ impl Timer {
     pub(crate) fn new() -> Self {
         Self::default()
     }
+
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        w.u32(self.internaldiv);
+        w.u32(self.internalcnt);
+        w.u8(self.counter);
+        w.u8(self.modulo);
+        w.u8(self.interrupt);
+        w.u8(self.control);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut Reader) -> Result<()> {
+        self.internaldiv = r.u32()?;
+        self.internalcnt = r.u32()?;
+        self.counter = r.u8()?;
+        self.modulo = r.u8()?;
+        self.interrupt = r.u8()?;
+        self.control = r.u8()?;
+        Ok(())
+    }
 }
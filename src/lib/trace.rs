@@ -0,0 +1,211 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{debugger::parse_number, mmu::Mmu, registers::Registers};
+
+/// When a trace starts or stops.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Trigger {
+    /// PC enters `start..=end`.
+    Pc { start: u16, end: u16 },
+    /// The PPU has finished this many frames.
+    Frame(u64),
+}
+
+impl Trigger {
+    /// Parses `$0150`, `$0150-$01FF` or `frame 100`.
+    pub(crate) fn parse(s: &str) -> Result<Trigger, String> {
+        let s = s.trim();
+
+        if let Some(n) = s.strip_prefix("frame") {
+            return n
+                .trim()
+                .parse()
+                .map(Trigger::Frame)
+                .map_err(|_| format!("bad frame number in `{s}`"));
+        }
+
+        let range = match s.split_once('-') {
+            Some((start, end)) => parse_number(start).zip(parse_number(end)),
+            None => parse_number(s).map(|a| (a, a)),
+        };
+
+        match range {
+            Some((start, end)) if start <= end => Ok(Trigger::Pc { start, end }),
+            _ => Err(format!(
+                "expected `$addr`, `$start-$end` or `frame N`, got `{s}`"
+            )),
+        }
+    }
+
+    fn fired(&self, pc: u16, frame: u64) -> bool {
+        match *self {
+            Trigger::Pc { start, end } => (start..=end).contains(&pc),
+            Trigger::Frame(n) => frame >= n,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct TraceConfig {
+    pub(crate) path: PathBuf,
+    pub(crate) start: Option<Trigger>,
+    pub(crate) stop: Option<Trigger>,
+    pub(crate) compress: bool,
+}
+
+/// Writes a Gameboy Doctor style line before every instruction:
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+pub(crate) struct Tracer {
+    out: Output,
+    start: Option<Trigger>,
+    stop: Option<Trigger>,
+    started: bool,
+    pub(crate) finished: bool,
+}
+
+impl Tracer {
+    pub(crate) fn create(config: &TraceConfig) -> io::Result<Tracer> {
+        let file = BufWriter::new(File::create(&config.path)?);
+
+        let out = if config.compress {
+            Output::Gz(Box::new(GzEncoder::new(file, Compression::fast())))
+        } else {
+            Output::Plain(file)
+        };
+
+        Ok(Tracer {
+            out,
+            start: config.start,
+            stop: config.stop,
+            started: config.start.is_none(),
+            finished: false,
+        })
+    }
+
+    pub(crate) fn log(&mut self, r: &Registers, mmu: &Mmu) -> io::Result<()> {
+        let frame = mmu.ppu.frames;
+
+        if !self.started {
+            self.started = self.start.is_some_and(|t| t.fired(r.pc, frame));
+        }
+
+        if !self.started {
+            return Ok(());
+        }
+
+        if self.stop.is_some_and(|t| t.fired(r.pc, frame)) {
+            self.finished = true;
+            return self.out.flush();
+        }
+
+        let mem = [0, 1, 2, 3].map(|i| mmu.peek(r.pc.wrapping_add(i)));
+
+        writeln!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc, mem[0], mem[1], mem[2], mem[3]
+        )
+    }
+
+    /// Flushes the file, and for a `.gz` writes the trailer, which dropping
+    /// it only does if nothing goes wrong.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self.out {
+            Output::Plain(mut file) => file.flush(),
+            Output::Gz(gz) => (*gz).finish()?.flush(),
+        }
+    }
+}
+
+enum Output {
+    Plain(BufWriter<File>),
+    Gz(Box<GzEncoder<BufWriter<File>>>),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            Output::Gz(gz) => gz.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            Output::Gz(gz) => gz.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn triggers() {
+        assert!(matches!(
+            Trigger::parse("$0150"),
+            Ok(Trigger::Pc {
+                start: 0x150,
+                end: 0x150
+            })
+        ));
+        assert!(matches!(
+            Trigger::parse(" $0150-0x01FF "),
+            Ok(Trigger::Pc {
+                start: 0x150,
+                end: 0x1FF
+            })
+        ));
+        assert!(matches!(
+            Trigger::parse("frame 100"),
+            Ok(Trigger::Frame(100))
+        ));
+
+        for s in ["", "frame", "frame x", "$0200-$0100", "$xyz", "$0100-"] {
+            assert!(Trigger::parse(s).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn finished_gz_trace() {
+        let path = std::env::temp_dir().join(format!("oxyboy-{}.log.gz", std::process::id()));
+        let config = TraceConfig {
+            path: path.clone(),
+            start: None,
+            stop: None,
+            compress: true,
+        };
+
+        let cpu = Cpu::new();
+        let mut tracer = Tracer::create(&config).unwrap();
+        tracer.log(&cpu.reg, &cpu.mmu).unwrap();
+        tracer.finish().unwrap();
+
+        // Reading to the end checks the trailer's there.
+        let mut text = String::new();
+        GzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert!(
+            text.ends_with("SP:FFFE PC:0100 PCMEM:FF,FF,FF,FF\n"),
+            "{text}"
+        );
+        assert_eq!(text.lines().count(), 1);
+    }
+}
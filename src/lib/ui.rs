@@ -1,4 +1,4 @@
-use eframe::egui::{self, Image, TextureFilter};
+use eframe::egui::{self, Image, TextureFilter, TextureHandle, TextureOptions};
 use std::{
     path::PathBuf,
     sync::mpsc::{Receiver, Sender},
@@ -7,24 +7,450 @@ use std::{
 use eframe::egui::ColorImage;
 
 use crate::{
-    ppu::{SCREEN_H, SCREEN_W},
-    Press,
+    archive::{self, RomFile},
+    bindings_dialog::BindingsDialog,
+    cheat_dialog::CheatDialog,
+    config::Config,
+    cpu::Lockup,
+    debug_panel::DebugPanel,
+    debugger::DebugCommand,
+    gamepad::{Gamepads, VirtualGamepad},
+    input::{Action, Controls, Hotkey},
+    library::Library,
+    library_dialog::LibraryDialog,
+    macro_dialog::MacroDialog,
+    map_viewer::MapViewer,
+    memory_editor::MemoryEditor,
+    mmu::MemorySnapshot,
+    movie_dialog::MovieDialog,
+    oam_viewer::OamViewer,
+    onscreen_pad::OnScreenPad,
+    pacer::FRAME_HZ,
+    ppu::{VideoSnapshot, SCREEN_H, SCREEN_W},
+    ram_search::RamSearch,
+    settings_dialog::SettingsDialog,
+    vram_viewer::VramViewer,
+    EmuCommand, Event, Input,
+};
+
+pub(crate) const SCREEN_TEXTURE: TextureOptions = TextureOptions {
+    wrap_mode: egui::TextureWrapMode::ClampToEdge,
+    magnification: TextureFilter::Nearest,
+    minification: TextureFilter::Nearest,
 };
 
 pub(crate) struct Ui {
     pub(crate) rx: Receiver<Vec<u8>>,
-    pub(crate) tx: Sender<Press>,
-    pub(crate) fn_tx: Sender<PathBuf>,
+    pub(crate) event_rx: Receiver<Event>,
+    emu_tx: Sender<EmuCommand>,
+    screen: Option<TextureHandle>,
+    // The last frame as the PPU drew it, before the palette.
+    frame: Vec<u8>,
+    config: Config,
+    settings: SettingsDialog,
+    library: Library,
+    library_dialog: LibraryDialog,
+    controls: Controls,
+    onscreen_pad: OnScreenPad,
+    bindings: BindingsDialog,
+    macros: MacroDialog,
+    movie: MovieDialog,
+    /// A multiple of full speed, or `None` for flat out.
+    speed: Option<f32>,
+    // What the emulator was last told, fast-forward included.
+    sent_speed: Option<f32>,
+    fps: Option<f32>,
+    show_fps: bool,
+    lockup: Option<Lockup>,
+    // Shown in a dialog until dismissed.
+    error: Option<String>,
+    debugger: DebugPanel,
+    video: Option<Box<VideoSnapshot>>,
+    // Whether the emulator has been asked to send `Event::Video`.
+    video_requested: bool,
+    vram: VramViewer,
+    maps: MapViewer,
+    oam: OamViewer,
+    memory: Option<Box<MemorySnapshot>>,
+    memory_editor: MemoryEditor,
+    ram_search: RamSearch,
+    cheats: CheatDialog,
+    // An archive with several ROMs in it, waiting for the user to pick one.
+    archive: Option<(PathBuf, Vec<String>)>,
 }
 
 impl Ui {
-    pub(crate) fn new(rx: Receiver<Vec<u8>>, tx: Sender<Press>, fn_tx: Sender<PathBuf>) -> Self {
-        Self { rx, tx, fn_tx }
+    pub(crate) fn new(
+        rx: Receiver<Vec<u8>>,
+        tx: Sender<Input>,
+        emu_tx: Sender<EmuCommand>,
+        event_rx: Receiver<Event>,
+        speed: Option<f32>,
+        config: Config,
+    ) -> Self {
+        let (pad, pad_tx) = VirtualGamepad::new();
+        let mut gamepads = Gamepads::new();
+        gamepads.add(Box::new(pad));
+
+        Self {
+            rx,
+            event_rx,
+            emu_tx: emu_tx.clone(),
+            screen: None,
+            frame: Vec::new(),
+            config,
+            settings: SettingsDialog::new(),
+            library: Library::load(),
+            library_dialog: LibraryDialog::new(),
+            macros: MacroDialog::new(tx.clone()),
+            movie: MovieDialog::new(emu_tx.clone()),
+            controls: Controls::new(tx, gamepads),
+            onscreen_pad: OnScreenPad::new(pad_tx),
+            bindings: BindingsDialog::new(),
+            speed,
+            sent_speed: speed,
+            fps: None,
+            show_fps: true,
+            lockup: None,
+            error: None,
+            debugger: DebugPanel::new(emu_tx.clone()),
+            video: None,
+            video_requested: false,
+            vram: VramViewer::new(),
+            maps: MapViewer::new(),
+            oam: OamViewer::new(),
+            memory: None,
+            memory_editor: MemoryEditor::new(emu_tx.clone()),
+            ram_search: RamSearch::new(emu_tx.clone()),
+            cheats: CheatDialog::new(emu_tx),
+            archive: None,
+        }
+    }
+
+    fn lockup_window(&mut self, ctx: &egui::Context) {
+        let Some(lockup) = self.lockup else {
+            return;
+        };
+
+        let mut open = true;
+
+        egui::Window::new("CPU locked up")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Illegal opcode ${:02X} at ${:04X}. The CPU has stopped, like real hardware would.",
+                    lockup.opcode, lockup.pc
+                ));
+            });
+
+        if !open {
+            self.lockup = None;
+        }
+    }
+
+    fn error_window(&mut self, ctx: &egui::Context) {
+        let Some(error) = &self.error else {
+            return;
+        };
+
+        let mut open = true;
+        let mut ok = false;
+
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(error);
+                ok = ui.button("OK").clicked();
+            });
+
+        if !open || ok {
+            self.error = None;
+        }
+    }
+
+    fn open_rom(&mut self, path: PathBuf) {
+        match archive::entries(&path) {
+            Ok(entries) if entries.len() > 1 => self.archive = Some((path, entries)),
+            Ok(_) => self.load(RomFile::new(path)),
+            Err(e) => self.error = Some(format!("Couldn't open {}: {e}", path.display())),
+        }
+    }
+
+    fn load(&mut self, rom: RomFile) {
+        self.lockup = None;
+
+        if self.emu_tx.send(EmuCommand::Load(rom)).is_err() {
+            self.error = Some("The emulator has stopped. Restart Oxyboy to load a ROM.".into());
+        }
+    }
+
+    fn recent_menu(&mut self, ui: &mut egui::Ui) {
+        let enabled = !self.library.recent.is_empty();
+
+        ui.add_enabled_ui(enabled, |ui| {
+            ui.menu_button("Recent", |ui| {
+                let mut picked = None;
+
+                for rom in &self.library.recent {
+                    let button = ui
+                        .button(rom.name())
+                        .on_hover_text(rom.path.display().to_string());
+                    if button.clicked() {
+                        picked = Some(rom.clone());
+                    }
+                }
+
+                ui.separator();
+
+                if ui.button("Clear").clicked() {
+                    self.library.recent.clear();
+                    self.library.save();
+                    ui.close_menu();
+                }
+
+                if let Some(rom) = picked {
+                    self.load(rom);
+                    ui.close_menu();
+                }
+            });
+        });
+    }
+
+    /// Loads a ROM dropped on the window, and says so while one's held over
+    /// it.
+    fn drop_rom(&mut self, ctx: &egui::Context) {
+        let (hovering, dropped) = ctx.input(|i| {
+            let dropped = i.raw.dropped_files.iter().find_map(|f| f.path.clone());
+            (!i.raw.hovered_files.is_empty(), dropped)
+        });
+
+        if let Some(path) = dropped {
+            self.open_rom(path);
+        }
+
+        if hovering {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("drop"),
+            ));
+            let screen = ctx.screen_rect();
+
+            painter.rect_filled(screen, 0.0, egui::Color32::from_black_alpha(160));
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop a ROM to load it",
+                egui::FontId::proportional(24.0),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+
+    fn archive_window(&mut self, ctx: &egui::Context) {
+        let Some((path, entries)) = &self.archive else {
+            return;
+        };
+
+        let mut open = true;
+        let mut picked = None;
+
+        egui::Window::new("Pick a ROM")
+            .collapsible(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(format!("{} has several ROMs in it:", path.display()));
+
+                for entry in entries {
+                    if ui.button(entry).clicked() {
+                        picked = Some(RomFile {
+                            path: path.clone(),
+                            entry: Some(entry.clone()),
+                        });
+                    }
+                }
+            });
+
+        if let Some(rom) = picked {
+            self.load(rom);
+            self.archive = None;
+        } else if !open {
+            self.archive = None;
+        }
+    }
+
+    fn hotkey(&self, hotkey: Hotkey) {
+        let paused = self.debugger.state().is_some_and(|s| s.paused);
+
+        let cmd = match hotkey {
+            Hotkey::Pause if paused => DebugCommand::Continue,
+            Hotkey::Pause => DebugCommand::Pause,
+            Hotkey::FrameAdvance => DebugCommand::RunToFrame,
+            Hotkey::LineAdvance => DebugCommand::RunToLine,
+            // These are held rather than pressed.
+            Hotkey::TurboA | Hotkey::TurboB | Hotkey::FastForward => return,
+        };
+        _ = self.emu_tx.send(EmuCommand::Debug(cmd));
+    }
+
+    /// Tells the emulator how fast to go when that changes.
+    fn send_speed(&mut self) {
+        let speed = if self.controls.holding(Hotkey::FastForward) {
+            None
+        } else {
+            self.speed
+        };
+
+        if speed != self.sent_speed {
+            _ = self.emu_tx.send(EmuCommand::Speed(speed));
+            self.sent_speed = speed;
+        }
+    }
+
+    /// Puts the last frame on screen in the palette's colours.
+    fn draw_frame(&mut self, ctx: &egui::Context) {
+        let rgb = self.config.colourise(&self.frame);
+        let image = ColorImage::from_rgb([SCREEN_W, SCREEN_H], &rgb);
+
+        match &mut self.screen {
+            Some(screen) => screen.set(image, SCREEN_TEXTURE),
+            None => self.screen = Some(ctx.load_texture("Screen", image, SCREEN_TEXTURE)),
+        }
+    }
+
+    /// Saves and applies settings from the settings window.
+    fn set_config(&mut self, ctx: &egui::Context, config: Config) {
+        if let Err(e) = config.save() {
+            self.error = Some(format!("Couldn't save settings: {e}"));
+        }
+
+        let old = std::mem::replace(&mut self.config, config);
+        let new = &self.config;
+
+        if new.scale != old.scale {
+            let size = egui::vec2(SCREEN_W as f32, SCREEN_H as f32) * new.scale;
+            ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(size));
+        }
+
+        if (&new.boot_rom, &new.save_dir) != (&old.boot_rom, &old.save_dir) {
+            _ = self.emu_tx.send(EmuCommand::Settings {
+                boot_rom: new.boot_rom.clone(),
+                save_dir: new.save_dir.clone(),
+            });
+        }
+
+        if new.palette != old.palette && !self.frame.is_empty() {
+            self.draw_frame(ctx);
+        }
+    }
+
+    /// Draws the frame rate and speed in the corner of the screen.
+    fn fps(&self, ui: &egui::Ui, screen: egui::Rect) {
+        let Some(fps) = self.fps.filter(|_| self.show_fps) else {
+            return;
+        };
+
+        let text = format!("{fps:.1} fps {:.0}%", fps as f64 / FRAME_HZ * 100.0);
+        let painter = ui.painter();
+        let text =
+            painter.layout_no_wrap(text, egui::FontId::monospace(14.0), egui::Color32::WHITE);
+        let rect = egui::Align2::RIGHT_TOP
+            .anchor_size(screen.right_top() + egui::vec2(-4.0, 4.0), text.size())
+            .expand(2.0);
+
+        painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(160));
+        painter.galley(rect.min + egui::vec2(2.0, 2.0), text, egui::Color32::WHITE);
+    }
+
+    fn emulation_menu(&mut self, ui: &mut egui::Ui) {
+        let paused = self.debugger.state().is_some_and(|s| s.paused);
+
+        let items = [
+            (Hotkey::Pause, if paused { "Resume" } else { "Pause" }),
+            (Hotkey::FrameAdvance, "Advance frame"),
+            (Hotkey::LineAdvance, "Advance scanline"),
+        ];
+
+        for (hotkey, label) in items {
+            let keys = self
+                .controls
+                .keyboard
+                .bindings
+                .action_keys(Action::Hotkey(hotkey), None)
+                .join(", ");
+
+            if ui
+                .add(egui::Button::new(label).shortcut_text(keys))
+                .clicked()
+            {
+                self.hotkey(hotkey);
+                ui.close_menu();
+            }
+        }
+
+        ui.separator();
+        ui.menu_button("Speed", |ui| {
+            for speed in [0.25, 0.5, 1.0, 2.0, 4.0] {
+                let label = format!("{}%", speed * 100.0);
+                ui.radio_value(&mut self.speed, Some(speed), label);
+            }
+            ui.radio_value(&mut self.speed, None, "Unlimited");
+        });
+        ui.checkbox(&mut self.show_fps, "Show FPS");
+    }
+
+    /// Only has the emulator copy out video memory while a viewer wants it.
+    fn request_video(&mut self) {
+        let wanted = self.vram.open || self.maps.open || self.oam.open;
+
+        if wanted != self.video_requested {
+            _ = self.emu_tx.send(EmuCommand::Video(wanted));
+            self.video_requested = wanted;
+
+            if !wanted {
+                self.video = None;
+            }
+        }
     }
 }
 
 impl eframe::App for Ui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        while let Ok(event) = self.event_rx.try_recv() {
+            match event {
+                Event::Lockup(lockup) => self.lockup = Some(lockup),
+                Event::Loaded(rom, title) => {
+                    self.library.loaded(&rom);
+                    self.controls.keyboard.profile = Some(title);
+                }
+                Event::LoadFailed(rom, e) => {
+                    self.error = Some(format!("Couldn't load {}: {e}", rom.path.display()))
+                }
+                Event::Debug(state) => self.debugger.update(*state),
+                Event::Video(video) => self.video = Some(video),
+                Event::Memory(memory) => self.memory = Some(memory),
+                Event::Ram(memory) => self.ram_search.snapshot(memory),
+                Event::Cheats(list) => self.cheats.set_list(list),
+                Event::Recorded(frames) => {
+                    self.macros.recorded(&frames, &mut self.controls.keyboard)
+                }
+                Event::Movie(status) => self.movie.update(status),
+                Event::MovieFailed(path, e) => {
+                    self.error = Some(format!("Couldn't play {}: {e}", path.display()))
+                }
+                Event::Fps(fps) => self.fps = Some(fps),
+                Event::BootRomFailed(e) => {
+                    self.error = Some(format!("Couldn't change the boot ROM: {e}"))
+                }
+                Event::StateFailed(path, e) => {
+                    self.error = Some(format!("Couldn't use {}: {e}", path.display()))
+                }
+            }
+        }
+
         egui::TopBottomPanel::top("top")
             .show_separator_line(true)
             .show(ctx, |ui| {
@@ -32,57 +458,125 @@ impl eframe::App for Ui {
                     ui.menu_button("File", |ui| {
                         if ui.button("Load").clicked() {
                             let file = rfd::FileDialog::new()
-                                .add_filter("rom", &["gb"])
+                                .add_filter("rom", &archive::EXTENSIONS)
                                 .pick_file();
 
                             if let Some(file) = file {
-                                self.fn_tx.send(file).unwrap();
+                                self.open_rom(file);
                             };
                         }
+
+                        self.recent_menu(ui);
+                        ui.checkbox(&mut self.library_dialog.open, "Library");
+                        ui.separator();
+
+                        if ui.button("Save state").clicked() {
+                            _ = self.emu_tx.send(EmuCommand::SaveState);
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Load state").clicked() {
+                            _ = self.emu_tx.send(EmuCommand::LoadState);
+                            ui.close_menu();
+                        }
+                    });
+
+                    ui.menu_button("Emulation", |ui| self.emulation_menu(ui));
+
+                    ui.menu_button("Tools", |ui| {
+                        if ui.button("Settings").clicked() {
+                            self.settings.open(&self.config);
+                            ui.close_menu();
+                        }
+                        ui.checkbox(&mut self.bindings.open, "Key bindings");
+                        ui.checkbox(&mut self.onscreen_pad.open, "On-screen gamepad");
+                        ui.checkbox(&mut self.macros.open, "Turbo and macros");
+                        ui.checkbox(&mut self.movie.open, "Movie");
+                        ui.checkbox(&mut self.cheats.open, "Cheats");
+                    });
+
+                    ui.menu_button("Debug", |ui| {
+                        ui.checkbox(&mut self.debugger.open, "Debugger");
+                        ui.checkbox(&mut self.vram.open, "VRAM tiles");
+                        ui.checkbox(&mut self.maps.open, "Tile maps");
+                        ui.checkbox(&mut self.oam.open, "OAM");
+                        ui.checkbox(&mut self.memory_editor.open, "Memory");
+                        ui.checkbox(&mut self.ram_search.open, "RAM search");
                     });
                 });
             });
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let data = self.rx.try_recv();
+        self.lockup_window(ctx);
+        self.archive_window(ctx);
+        self.error_window(ctx);
+        self.debugger.show(ctx);
+        self.vram.show(ctx, self.video.as_deref());
+        self.maps.show(ctx, self.video.as_deref());
+        self.oam.show(ctx, self.video.as_deref());
+        self.request_video();
+        self.memory_editor.show(ctx, self.memory.as_deref());
 
-            if data.is_err() {
-                return;
-            }
+        let frozen = self.debugger.state().map_or(&[][..], |s| &s.frozen);
+        self.ram_search.show(ctx, frozen);
+        self.cheats.show(ctx);
 
-            #[rustfmt::skip]
-            ui.input(|i| {
-                if i.key_pressed(egui::Key::ArrowDown) || i.key_pressed(egui::Key::J) { _ = self.tx.send(Press::Down(0b0000_1000)); }
-                if i.key_released(egui::Key::ArrowDown) || i.key_released(egui::Key::J) { _ = self.tx.send(Press::Up(0b0000_1000)); }
-                if i.key_pressed(egui::Key::ArrowUp) || i.key_pressed(egui::Key::K) { _ = self.tx.send(Press::Down(0b0000_0100)); }
-                if i.key_released(egui::Key::ArrowUp) || i.key_released(egui::Key::K) { _ = self.tx.send(Press::Up(0b0000_0100)); }
-                if i.key_pressed(egui::Key::ArrowLeft) || i.key_pressed(egui::Key::H) { _ = self.tx.send(Press::Down(0b0000_0010)); }
-                if i.key_released(egui::Key::ArrowLeft) || i.key_released(egui::Key::H) { _ = self.tx.send(Press::Up(0b0000_0010)); }
-                if i.key_pressed(egui::Key::ArrowRight) || i.key_pressed(egui::Key::L) { _ = self.tx.send(Press::Down(0b0000_0001)); }
-                if i.key_released(egui::Key::ArrowRight) || i.key_released(egui::Key::L) { _ = self.tx.send(Press::Up(0b0000_0001)); }
-                if i.key_pressed(egui::Key::Z) { _ = self.tx.send(Press::Down(0b0001_0000)); }
-                if i.key_released(egui::Key::Z) { _ = self.tx.send(Press::Up(0b0001_0000)); }
-                if i.key_pressed(egui::Key::X) { _ = self.tx.send(Press::Down(0b0010_0000)); }
-                if i.key_released(egui::Key::X) { _ = self.tx.send(Press::Up(0b0010_0000)); }
-                if i.key_pressed(egui::Key::Space) { _ = self.tx.send(Press::Down(0b0100_0000)); }
-                if i.key_released(egui::Key::Space) { _ = self.tx.send(Press::Up(0b0100_0000)); }
-                if i.key_pressed(egui::Key::Enter) { _ = self.tx.send(Press::Down(0b1000_0000)); }
-                if i.key_released(egui::Key::Enter) { _ = self.tx.send(Press::Up(0b1000_0000)); }
-            });
+        if let Some(config) = self
+            .settings
+            .show(ctx, &self.config, &mut self.bindings.open)
+        {
+            self.set_config(ctx, config);
+        }
 
-            let t = ColorImage::from_rgb([SCREEN_W, SCREEN_H], &data.unwrap());
+        if let Some(rom) = self
+            .library_dialog
+            .show(ctx, &mut self.library, &self.config)
+        {
+            self.load(rom);
+        }
 
-            let s = ui
-                .ctx()
-                .load_texture("Screen", t, egui::TextureOptions {
-                    wrap_mode: egui::TextureWrapMode::ClampToEdge,
-                    magnification: TextureFilter::Nearest,
-                    minification: TextureFilter::Nearest,
-                });
+        self.drop_rom(ctx);
 
-            Image::from_texture(&s).paint_at(ui, ui.ctx().screen_rect());
-        });
+        self.bindings.show(
+            ctx,
+            &mut self.controls.keyboard,
+            &self.controls.gamepads.connected,
+        );
+        self.macros.show(ctx, &mut self.controls.keyboard);
+        self.onscreen_pad.show(ctx);
+
+        if let Some(error) = self.movie.show(ctx) {
+            self.error = Some(error);
+        }
+
+        // Typing into a text field (e.g. in the debugger) shouldn't press buttons.
+        let keys =
+            !ctx.wants_keyboard_input() && !self.bindings.capturing() && !self.macros.capturing();
+
+        for hotkey in self.controls.update(ctx, keys) {
+            self.hotkey(hotkey);
+        }
+        self.send_speed();
+
+        if let Ok(data) = self.rx.try_recv() {
+            self.frame = data;
+            self.draw_frame(ctx);
+        }
+
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none())
+            .show(ctx, |ui| {
+                if let Some(screen) = &self.screen {
+                    Image::from_texture(screen).paint_at(ui, ui.max_rect());
+                }
+
+                self.movie.counter(ui, ui.max_rect());
+                self.fps(ui, ui.max_rect());
+            });
 
         ctx.request_repaint();
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        _ = self.emu_tx.send(EmuCommand::Quit);
+    }
 }
@@ -0,0 +1,162 @@
+use eframe::egui::{self, ColorImage, ComboBox, Sense, TextureHandle, Vec2};
+
+use crate::{
+    ppu::{shade, tile_pixel, VideoSnapshot},
+    ui::SCREEN_TEXTURE,
+};
+
+/// Tiles in one VRAM bank, and how many go on a row of the view.
+const TILES: usize = 384;
+const COLUMNS: usize = 16;
+const ROWS: usize = TILES / COLUMNS;
+
+const BANK_LEN: usize = 0x2000;
+const SCALE: f32 = 2.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Palette {
+    Grey,
+    Bgp,
+    Obp0,
+    Obp1,
+}
+
+impl Palette {
+    const ALL: [Palette; 4] = [Palette::Grey, Palette::Bgp, Palette::Obp0, Palette::Obp1];
+
+    fn name(self) -> &'static str {
+        match self {
+            Palette::Grey => "Grey",
+            Palette::Bgp => "BGP",
+            Palette::Obp0 => "OBP0",
+            Palette::Obp1 => "OBP1",
+        }
+    }
+
+    fn register(self, video: &VideoSnapshot) -> u8 {
+        match self {
+            // Colour 0 white through colour 3 black.
+            Palette::Grey => 0b11_10_01_00,
+            Palette::Bgp => video.palb,
+            Palette::Obp0 => video.pal0,
+            Palette::Obp1 => video.pal1,
+        }
+    }
+}
+
+/// Shows every tile in VRAM, as it stands at the end of the last frame.
+pub(crate) struct VramViewer {
+    pub(crate) open: bool,
+    palette: Palette,
+    texture: Option<TextureHandle>,
+}
+
+impl VramViewer {
+    pub(crate) fn new() -> Self {
+        Self {
+            open: false,
+            palette: Palette::Grey,
+            texture: None,
+        }
+    }
+
+    pub(crate) fn show(&mut self, ctx: &egui::Context, video: Option<&VideoSnapshot>) {
+        let mut open = self.open;
+
+        egui::Window::new("VRAM tiles")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let Some(video) = video else {
+                    ui.label("Waiting for the emulator...");
+                    return;
+                };
+
+                ComboBox::from_label("Palette")
+                    .selected_text(self.palette.name())
+                    .show_ui(ui, |ui| {
+                        for palette in Palette::ALL {
+                            ui.selectable_value(&mut self.palette, palette, palette.name());
+                        }
+                    });
+
+                let banks = if video.cgb { 2 } else { 1 };
+                let image = render(video, banks, self.palette.register(video));
+
+                let texture = match &mut self.texture {
+                    Some(texture) => {
+                        texture.set(image, SCREEN_TEXTURE);
+                        texture
+                    }
+                    None => {
+                        self.texture
+                            .insert(ctx.load_texture("VRAM tiles", image, SCREEN_TEXTURE))
+                    }
+                };
+
+                let size = texture.size_vec2() * SCALE;
+                let (rect, response) = ui.allocate_exact_size(size, Sense::hover());
+                egui::Image::from_texture(&*texture).paint_at(ui, rect);
+
+                let hovered = response.hover_pos().map(|pos| {
+                    let cell = (pos - rect.min) / (8.0 * SCALE);
+                    tile_at(cell, banks)
+                });
+
+                match hovered.flatten() {
+                    Some((bank, tile)) => {
+                        let addr = 0x8000 + tile * 16;
+                        ui.label(format!(
+                            "Tile {tile} (${:02X}) at {bank}:${addr:04X}",
+                            tile & 0xFF
+                        ));
+                    }
+                    None => {
+                        ui.label("Hover a tile to see its index and address");
+                    }
+                }
+            });
+
+        self.open = open;
+    }
+}
+
+/// The bank and tile number under a position given in tiles.
+fn tile_at(cell: Vec2, banks: usize) -> Option<(usize, usize)> {
+    if cell.x < 0.0 || cell.y < 0.0 {
+        return None;
+    }
+
+    let (col, row) = (cell.x as usize, cell.y as usize);
+
+    if col >= COLUMNS * banks || row >= ROWS {
+        return None;
+    }
+
+    Some((col / COLUMNS, row * COLUMNS + col % COLUMNS))
+}
+
+/// Lays the tiles out 16 to a row, with the banks side by side.
+fn render(video: &VideoSnapshot, banks: usize, pal: u8) -> ColorImage {
+    let width = COLUMNS * 8 * banks;
+    let height = ROWS * 8;
+    let mut rgb = vec![0; width * height * 3];
+
+    for bank in 0..banks {
+        for tile in 0..TILES {
+            let base = bank * BANK_LEN + tile * 16;
+            let left = (bank * COLUMNS + tile % COLUMNS) * 8;
+            let top = tile / COLUMNS * 8;
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    let colour = shade(pal, tile_pixel(&video.vram, base, x, y));
+                    let i = ((top + y as usize) * width + left + x as usize) * 3;
+                    rgb[i..i + 3].fill(colour);
+                }
+            }
+        }
+    }
+
+    ColorImage::from_rgb([width, height], &rgb)
+}
@@ -1,5 +1,7 @@
+use std::process::ExitCode;
+
 use oxyboy::Emulator;
 
-fn main() {
-    Emulator::start();
+fn main() -> ExitCode {
+    Emulator::start()
 }